@@ -0,0 +1,51 @@
+// Counts the instructions with memory operands in the code generated for the
+// programs under tests/, with and without register allocation. Run with
+// `cargo bench`.
+use std::{fs, path::Path, process::Command};
+
+fn memory_ops(file: &Path, flags: &[&str]) -> Option<(usize, usize)> {
+    let out = std::env::temp_dir().join(format!("memops-{}.s", std::process::id()));
+    let status = Command::new(env!("CARGO_BIN_EXE_egg-eater"))
+        .arg(file)
+        .arg(&out)
+        .args(flags)
+        .stderr(std::process::Stdio::null())
+        .status()
+        .expect("could not run the compiler");
+    if !status.success() {
+        return None;
+    }
+    let asm = fs::read_to_string(&out).unwrap();
+    let mem: Vec<&str> = asm.lines().filter(|l| l.contains('[')).collect();
+    let frame = mem.iter().filter(|l| l.contains("[rbp")).count();
+    Some((mem.len(), frame))
+}
+
+fn main() {
+    let mut files: Vec<_> = fs::read_dir("tests")
+        .unwrap()
+        .flatten()
+        .filter(|d| d.path().is_dir())
+        .flat_map(|d| fs::read_dir(d.path()).unwrap().flatten())
+        .map(|f| f.path())
+        .filter(|p| p.extension().map_or(false, |e| e == "snek"))
+        .collect();
+    files.sort();
+
+    println!("{:<40} {:>14} {:>14}", "program", "stack (frame)", "regs (frame)");
+    let (mut before, mut after) = ((0, 0), (0, 0));
+    for file in &files {
+        let (Some(b), Some(a)) = (memory_ops(file, &["--no-regalloc"]), memory_ops(file, &[])) else {
+            continue;
+        };
+        println!("{:<40} {:>6} ({:>5}) {:>6} ({:>5})", file.display(), b.0, b.1, a.0, a.1);
+        before = (before.0 + b.0, before.1 + b.1);
+        after = (after.0 + a.0, after.1 + a.1);
+    }
+    println!("{:<40} {:>6} ({:>5}) {:>6} ({:>5})", "total", before.0, before.1, after.0, after.1);
+    println!(
+        "memory operands: {:.1}% fewer; frame accesses: {:.1}% fewer",
+        100.0 * (before.0 - after.0) as f64 / before.0 as f64,
+        100.0 * (before.1 - after.1) as f64 / before.1 as f64
+    );
+}
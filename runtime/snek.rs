@@ -0,0 +1,272 @@
+// The runtime support for compiled snek code: errors, printing, equality,
+// strings and the garbage collector. It is linked into every executable by
+// start.rs, and into the compiler itself to run code in memory.
+
+pub static mut HEAP_START: *mut u64 = std::ptr::null_mut();
+
+// Default heap size in bytes, overridden with `--heap-size`.
+pub const DEFAULT_HEAP_SIZE: usize = 0x8000000;
+
+#[export_name = "\x01snek_error"]
+pub extern "C" fn snek_error(errcode: i64) {
+    eprintln!("an error ocurred {}", error_message(errcode));
+    std::process::exit(1);
+}
+
+/// What `snek_error` reports for an error code.
+pub fn error_message(errcode: i64) -> String {
+    match errcode {
+        1 => "invalid argument".to_string(),
+        2 => "overflow".to_string(),
+        3 => "index out of range".to_string(),
+        4 => "out of memory".to_string(),
+        5 => "wrong number of arguments".to_string(),
+        _ => format!("error code {errcode}"),
+    }
+}
+
+const TUPLE_TAG: i64 = 1;
+const CLOSURE_TAG: i64 = 5;
+const STRING_TAG: i64 = 9;
+const VEC_TAG: i64 = 13;
+
+// An even header is followed by that many snek values, an odd one by raw bytes.
+unsafe fn has_fields(addr: *const u64) -> bool {
+    *addr & 1 == 0
+}
+
+// Number of snek values in the object at `addr`, not counting the padding
+// word, which may hold a stale pointer.
+unsafe fn field_count(addr: *const u64) -> usize {
+    if has_fields(addr) { (*addr >> 1) as usize } else { 0 }
+}
+
+// Number of words taken by the heap object at `addr`, including its header and
+// the padding that keeps objects 16-byte aligned.
+unsafe fn object_words(addr: *const u64) -> usize {
+    let len = (*addr >> 1) as usize;
+    let words = if has_fields(addr) { 1 + len } else { 1 + (len + 7) / 8 };
+    words + words % 2
+}
+
+// Finds the object a snek value refers to, if any, by its index in `objects`.
+// Heap values are tagged 0b0001, 0b0101, 0b1001 or 0b1101 in their low four bits.
+fn find_object(objects: &[*mut u64], val: u64) -> Option<usize> {
+    if val & 3 != 1 || val == 1 {
+        return None;
+    }
+    objects.binary_search(&((val & !15) as *mut u64)).ok()
+}
+
+unsafe fn string_bytes<'a>(val: i64) -> &'a [u8] {
+    let addr = (val & !15) as *const u64;
+    std::slice::from_raw_parts(addr.add(1) as *const u8, (*addr >> 1) as usize)
+}
+
+// Writes the concatenation of two strings at `dst`, which the compiled code
+// has allocated.
+#[export_name = "\x01snek_string_append"]
+pub unsafe extern "C" fn snek_string_append(dst: *mut u64, a: i64, b: i64) -> i64 {
+    let (a, b) = (string_bytes(a), string_bytes(b));
+    *dst = (((a.len() + b.len()) as u64) << 1) | 1;
+    let bytes = dst.add(1) as *mut u8;
+    std::ptr::copy_nonoverlapping(a.as_ptr(), bytes, a.len());
+    std::ptr::copy_nonoverlapping(b.as_ptr(), bytes.add(a.len()), b.len());
+    dst as i64 | STRING_TAG
+}
+
+// Writes the one-byte string at index `i` of `s` at `dst`.
+#[export_name = "\x01snek_string_ref"]
+pub unsafe extern "C" fn snek_string_ref(dst: *mut u64, s: i64, i: i64) -> i64 {
+    *dst = (1 << 1) | 1;
+    *dst.add(1) = string_bytes(s)[(i >> 1) as usize] as u64;
+    dst as i64 | STRING_TAG
+}
+
+/// Mark-compact collector, called by compiled code when an allocation of
+/// `needed` bytes does not fit. Returns the new allocation pointer; the caller
+/// reports "out of memory" if there is still not enough room.
+///
+/// Roots are found by scanning the stack conservatively, from `rsp` up to the
+/// outermost snek frame, whose saved RBP is zero. Every word that looks like a
+/// heap value pointing to the start of an object keeps it alive and is
+/// updated when the object moves. Stale slots may be retained by mistake, but
+/// since the compiler never reads a slot before writing it, rewriting them is
+/// harmless.
+#[export_name = "\x01snek_gc"]
+pub unsafe extern "C" fn snek_gc(alloc_ptr: *mut u64, _needed: u64, rsp: *mut u64, rbp: *const u64) -> *mut u64 {
+    let heap_start = HEAP_START;
+
+    let mut objects = Vec::new();
+    let mut p = heap_start;
+    while p < alloc_ptr {
+        objects.push(p);
+        p = p.add(object_words(p));
+    }
+
+    let mut stack_base = rbp;
+    while *stack_base != 0 {
+        stack_base = *stack_base as *const u64;
+    }
+
+    // mark
+    let mut marked = vec![false; objects.len()];
+    let mut worklist = Vec::new();
+    let mut slot = rsp;
+    while (slot as *const u64) < stack_base {
+        if let Some(i) = find_object(&objects, *slot) {
+            if !marked[i] {
+                marked[i] = true;
+                worklist.push(i);
+            }
+        }
+        slot = slot.add(1);
+    }
+    while let Some(i) = worklist.pop() {
+        let obj = objects[i];
+        for k in 1..=field_count(obj) {
+            if let Some(j) = find_object(&objects, *obj.add(k)) {
+                if !marked[j] {
+                    marked[j] = true;
+                    worklist.push(j);
+                }
+            }
+        }
+    }
+
+    // compute forwarding addresses
+    let mut forward = vec![std::ptr::null_mut(); objects.len()];
+    let mut free = heap_start;
+    for (i, &obj) in objects.iter().enumerate() {
+        if marked[i] {
+            forward[i] = free;
+            free = free.add(object_words(obj));
+        }
+    }
+
+    // update references, then slide live objects down
+    let update = |slot: *mut u64| {
+        if let Some(i) = find_object(&objects, *slot) {
+            *slot = forward[i] as u64 | (*slot & 15);
+        }
+    };
+    let mut slot = rsp;
+    while (slot as *const u64) < stack_base {
+        update(slot);
+        slot = slot.add(1);
+    }
+    for (i, &obj) in objects.iter().enumerate() {
+        if marked[i] {
+            for k in 1..=field_count(obj) {
+                update(obj.add(k));
+            }
+        }
+    }
+    for (i, &obj) in objects.iter().enumerate() {
+        if marked[i] {
+            std::ptr::copy(obj, forward[i], object_words(obj));
+        }
+    }
+
+    free
+}
+
+pub fn parse_input(input: &str) -> i64 {
+    // TODO: parse the input string into internal value representation
+    if input == "true" {7}
+    else if input == "false" {3}
+    else {
+        let i = input.parse::<i64>().unwrap();
+        if !(-4611686018427387904..=4611686018427387903).contains(&i) {
+            panic!("Invalid");
+        }
+        i << 1
+    }
+}
+
+fn snek_str(val: i64, seen: &mut Vec<i64>) -> String {
+    if val == 7 { "true".to_string()}
+    else if val == 3 { "false".to_string() }
+    else if val % 2 == 0 { format!("{}", val >> 1) }
+    else if val == 1 { "()".to_string() }
+    else if val & 15 == CLOSURE_TAG { "<function>".to_string() }
+    else if val & 15 == STRING_TAG { String::from_utf8_lossy(unsafe { string_bytes(val) }).into_owned() }
+    else if val & 15 == TUPLE_TAG || val & 15 == VEC_TAG {
+        let (open, close) = if val & 15 == TUPLE_TAG { ("(", ")") } else { ("[", "]") };
+        if seen.contains(&val) { format!("{open}...{close}") }
+        else {
+            let addr = (val & !15) as *const i64;
+            let len = unsafe { *addr } >> 1;
+            seen.push(val);
+            let s = (1..len as isize + 1).map(|i| snek_str(unsafe {*addr.offset(i)}, seen)).collect::<Vec<_>>().join(" ");
+            seen.pop();
+            format!("{open}{s}{close}")
+        }
+    } else { format!("Unknown value: {}", val) }
+}
+
+#[export_name = "\x01snek_print"]
+pub extern "C" fn snek_print(val: i64) -> i64 {
+    println!("{}", snek_to_string(val));
+    val
+}
+
+/// The text `snek_print` writes for a value.
+pub fn snek_to_string(val: i64) -> String {
+    snek_str(val, &mut Vec::new())
+}
+
+fn snek_structural_eq(default: bool, v1: i64, v2: i64, pending: &mut Vec<(i64, i64)>) -> bool {
+    if v1 == v2 { true }
+    else if v1 & 15 == STRING_TAG && v2 & 15 == STRING_TAG {
+        unsafe { string_bytes(v1) == string_bytes(v2) }
+    }
+    else if v1 & 15 == v2 & 15 && (v1 & 15 == TUPLE_TAG || v1 & 15 == VEC_TAG) {
+        if v1 == 1 && v2 == 1 {
+            true
+        } else if v1 == 1 || v2 == 1 {
+            false
+        } else if pending.contains(&(v1, v2)) { default }
+        else {
+            let a1 = (v1 & !15) as *const i64;
+            let a2 = (v2 & !15) as *const i64;
+            let l1 = unsafe { *a1 } >> 1;
+            let l2 = unsafe { *a2 } >> 1;
+            if l1 != l2 { false }
+            else {
+                pending.push((v1, v2));
+                for i in 1..l1 as usize + 1 {
+                    if unsafe { !snek_structural_eq(default, *a1.add(i), *a2.add(i), pending) } { return false; }
+                }
+                pending.pop();
+                true
+            }
+        }
+    } else { false }
+}
+
+#[export_name = "\x01snek_structural_eq_true"]
+pub extern "C" fn snek_structural_eq_true(v1: i64, v2: i64) -> i64 {
+    let mut pending = Vec::<(i64, i64)>::new();
+    if snek_structural_eq(true, v1, v2, &mut pending) { 7 } else { 3 }
+}
+
+// #[export_name = "\x01snek_structural_eq_false"]
+// fn snek_structural_eq_false(v1: i64, v2: i64) {
+//     let mut pending = Vec<(i64, i64)>::new();
+//     return snek_structural_eq(false, v1, v2, pending);
+// }
+
+/// Runs compiled code on `input` with a heap of `heap_size` bytes, and prints
+/// the result.
+pub unsafe fn run(our_code_starts_here: unsafe extern "C" fn(i64, *mut u64, *const u64) -> i64, input: i64, heap_size: usize) {
+    let heap_words = heap_size / 8;
+    // one spare word to align the heap to 16 bytes
+    let mut memory = Vec::<u64>::with_capacity(heap_words + 1);
+    let buffer :*mut u64 = memory.as_mut_ptr();
+    let buffer = buffer.add(buffer as usize / 8 % 2);
+
+    HEAP_START = buffer;
+    let i = our_code_starts_here(input, buffer, buffer.add(heap_words));
+    snek_print(i);
+}
@@ -0,0 +1,902 @@
+use std::collections::HashMap;
+
+use crate::ast::{Op1, Op2};
+use crate::frame::{allocate, max_args};
+use crate::ir::{self, Operand, Rhs, Stmt, Term, Ty, Var};
+use crate::x86::{ENTRY_LABEL, ERROR_LABEL, INPUT_LABEL};
+
+// Code generation for AArch64, in GNU as syntax. Values are tagged as they are
+// on x86-64, and the frames have the same layout, so that the runtime and its
+// collector work unchanged: x29 is the frame pointer, and the stack pointer is
+// kept 16-byte aligned, so arguments are stored rather than pushed.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum Reg {
+    X0,
+    X1,
+    X2,
+    X3,
+    X9,
+    X10,
+    X11,
+    X16,
+    X17,
+    X19,
+    X20,
+    X21,
+    X22,
+    X23,
+    X24,
+    X25,
+    X26,
+    X27,
+    X28,
+    X29,
+    X30,
+    SP,
+    XZR,
+}
+
+// The value being computed, and the results of snek functions.
+const ACC: Reg = Reg::X9;
+// Scratch registers. KEEP is callee-saved, so it survives the collector.
+const TMP: Reg = Reg::X10;
+const CNT: Reg = Reg::X11;
+const KEEP: Reg = Reg::X19;
+// Immediates and addresses that do not fit in an instruction.
+const IMM: Reg = Reg::X16;
+const ADDR: Reg = Reg::X17;
+// The allocation pointer and the end of the heap.
+const HEAP: Reg = Reg::X27;
+const HEAP_END: Reg = Reg::X28;
+const FP: Reg = Reg::X29;
+// snek_error takes the error code as its argument.
+const ERR: Reg = Reg::X0;
+
+// Registers that the generated code never uses as scratch, so that they can
+// hold variables. As on x86-64, only variables not live across a call are kept
+// there.
+const ALLOC_REGS: [Reg; 6] = [Reg::X20, Reg::X21, Reg::X22, Reg::X23, Reg::X24, Reg::X25];
+
+#[derive(Debug, Clone)]
+pub enum Op {
+    Reg(Reg),
+    Imm(i64),
+    Lsl(Reg, u8),
+    Asr(Reg, u8),
+}
+
+#[derive(Debug, Clone)]
+pub enum Mem {
+    Off(Reg, i32),
+    // with the base register updated before or after the access
+    Pre(Reg, i32),
+    Post(Reg, i32),
+    Lsl(Reg, Reg, u8),
+    Lo12(Reg, String),
+}
+
+#[derive(Debug)]
+pub enum Instr<'a> {
+    Mov(Reg, Reg),
+    MovImm(Reg, i64),
+    Movz(Reg, u16),
+    Movk(Reg, u16, u8),
+    Ldr(Reg, Mem),
+    Str(Reg, Mem),
+    Ldp(Reg, Reg, Mem),
+    Stp(Reg, Reg, Mem),
+    Add(Reg, Reg, Op),
+    Adds(Reg, Reg, Op),
+    Sub(Reg, Reg, Op),
+    Subs(Reg, Reg, Op),
+    And(Reg, Reg, Op),
+    Lsl(Reg, Reg, u8),
+    Asr(Reg, Reg, u8),
+    Mul(Reg, Reg, Reg),
+    Smulh(Reg, Reg, Reg),
+    Cmp(Reg, Op),
+    Tst(Reg, Op),
+    Csel(Reg, Reg, Reg, &'a str),
+    B(&'a str, String),
+    Bl(String),
+    Blr(Reg),
+    Br(Reg),
+    Ret,
+    Adr(Reg, String),
+    Adrp(Reg, String),
+    Label(String),
+}
+
+// Heap objects are 16-byte aligned and tagged in the low four bits.
+const TUPLE_TAG: i64 = 1;
+const CLOSURE_TAG: i64 = 5;
+const STRING_TAG: i64 = 9;
+const VEC_TAG: i64 = 13;
+
+// Bytes taken by a heap object of `words` words, keeping the next one aligned.
+fn object_size(words: usize) -> i64 {
+    8 * (words + words % 2) as i64
+}
+
+// Bytes taken by `words` words on the stack, which stays 16-byte aligned.
+fn stack_size(words: usize) -> i64 {
+    object_size(words)
+}
+
+fn new_label(label: &mut i32, s: &str) -> String {
+    let cur_label = *label;
+    *label += 1;
+    format!("{s}_{cur_label}")
+}
+
+fn check_num(instrs: &mut Vec<Instr>) {
+    instrs.push(Instr::Tst(ACC, Op::Imm(1)));
+    instrs.push(Instr::MovImm(ERR, 1));
+    instrs.push(Instr::B("ne", ERROR_LABEL.to_string()));
+}
+
+fn check_tag(tag: i64, instrs: &mut Vec<Instr>) {
+    instrs.push(Instr::And(TMP, ACC, Op::Imm(15)));
+    instrs.push(Instr::Cmp(TMP, Op::Imm(tag)));
+    instrs.push(Instr::MovImm(ERR, 1));
+    instrs.push(Instr::B("ne", ERROR_LABEL.to_string()));
+}
+
+fn check_overflow(instrs: &mut Vec<Instr>) {
+    instrs.push(Instr::MovImm(ERR, 2));
+    instrs.push(Instr::B("vs", ERROR_LABEL.to_string()));
+}
+
+// Loads any 64-bit value, a half-word at a time if `mov` cannot encode it.
+fn mov_imm(r: Reg, n: i64, instrs: &mut Vec<Instr>) {
+    if (-0x10000..0x10000).contains(&n) {
+        instrs.push(Instr::MovImm(r, n));
+        return;
+    }
+    instrs.push(Instr::Movz(r, n as u16));
+    for shift in [16, 32, 48] {
+        let half = (n >> shift) as u16;
+        if half != 0 {
+            instrs.push(Instr::Movk(r, half, shift));
+        }
+    }
+}
+
+// `n` as the operand of an arithmetic instruction or a comparison, which can
+// only take a 12-bit unsigned immediate, going through IMM otherwise.
+fn arith_imm(n: i64, instrs: &mut Vec<Instr>) -> Op {
+    if (0..0x1000).contains(&n) {
+        Op::Imm(n)
+    } else {
+        mov_imm(IMM, n, instrs);
+        Op::Reg(IMM)
+    }
+}
+
+// A word at `off` from `base`, going through ADDR if the offset does not fit in
+// a load or store.
+fn mem(base: Reg, off: i32, instrs: &mut Vec<Instr>) -> Mem {
+    if (-256..256).contains(&off) || (0..32768).contains(&off) && off % 8 == 0 {
+        Mem::Off(base, off)
+    } else {
+        mov_imm(ADDR, off as i64, instrs);
+        instrs.push(Instr::Add(ADDR, base, Op::Reg(ADDR)));
+        Mem::Off(ADDR, 0)
+    }
+}
+
+type Loc = crate::frame::Loc<Reg>;
+
+// Code generation for one function.
+struct FunGen<'a, 'b> {
+    locs: HashMap<Var, Loc>,
+    max_args: usize,
+    label: &'a mut i32,
+    instrs: &'a mut Vec<Instr<'b>>,
+}
+
+impl FunGen<'_, '_> {
+    fn load(&mut self, r: Reg, op: &Operand) {
+        match op {
+            Operand::Var(v) => match self.locs[v] {
+                Loc::Reg(s) if s == r => {},
+                Loc::Reg(s) => self.instrs.push(Instr::Mov(r, s)),
+                Loc::Stack(w) => {
+                    let m = mem(FP, 8 * w, self.instrs);
+                    self.instrs.push(Instr::Ldr(r, m));
+                },
+            },
+            Operand::Num(n) => mov_imm(r, n << 1, self.instrs),
+            Operand::Bool(b) => self.instrs.push(Instr::MovImm(r, if *b { 7 } else { 3 })),
+        }
+    }
+
+    fn store(&mut self, v: &Var, r: Reg) {
+        match self.locs[v] {
+            Loc::Reg(d) if d == r => {},
+            Loc::Reg(d) => self.instrs.push(Instr::Mov(d, r)),
+            Loc::Stack(w) => {
+                let m = mem(FP, 8 * w, self.instrs);
+                self.instrs.push(Instr::Str(r, m));
+            },
+        }
+    }
+
+    // `op` as the second operand of an arithmetic instruction or a comparison.
+    fn operand(&mut self, op: &Operand) -> Op {
+        match op {
+            Operand::Var(v) => match self.locs[v] {
+                Loc::Reg(r) => Op::Reg(r),
+                Loc::Stack(_) => {
+                    self.load(TMP, op);
+                    Op::Reg(TMP)
+                },
+            },
+            Operand::Num(n) => arith_imm(n << 1, self.instrs),
+            Operand::Bool(b) => Op::Imm(if *b { 7 } else { 3 }),
+        }
+    }
+
+    // Stores the arguments of a call above the closure's slot, zero-filled up
+    // to `max_args`. Every call reserves the same room for arguments, so that a
+    // tail call can reuse it whatever the arity of either function. Returns the
+    // number of bytes to free after the call.
+    fn store_args(&mut self, args: &[Operand]) -> i64 {
+        let size = stack_size(self.max_args + 1);
+        let room = arith_imm(size, self.instrs);
+        self.instrs.push(Instr::Sub(Reg::SP, Reg::SP, room));
+        for i in 0..self.max_args {
+            let r = match args.get(i) {
+                Some(a) => {
+                    self.load(ACC, a);
+                    ACC
+                },
+                None => Reg::XZR,
+            };
+            let m = mem(Reg::SP, 8 * (i as i32 + 1), self.instrs);
+            self.instrs.push(Instr::Str(r, m));
+        }
+        size
+    }
+
+    // Moves the arguments of a tail call over the current function's
+    // arguments, through the stack, as they may be read from there. The
+    // closure to call, if any, is saved after them and left in ACC.
+    fn move_tail_args(&mut self, args: &[Operand], f: Option<&Operand>) {
+        let ops: Vec<&Operand> = args.iter().chain(f).collect();
+        let size = stack_size(ops.len());
+        let room = arith_imm(size, self.instrs);
+        self.instrs.push(Instr::Sub(Reg::SP, Reg::SP, room.clone()));
+        for (i, a) in ops.iter().enumerate() {
+            self.load(ACC, a);
+            let m = mem(Reg::SP, 8 * i as i32, self.instrs);
+            self.instrs.push(Instr::Str(ACC, m));
+        }
+        for i in 0..args.len() as i32 {
+            let m = mem(Reg::SP, 8 * i, self.instrs);
+            self.instrs.push(Instr::Ldr(ACC, m));
+            let m = mem(FP, 8 * (i + 3), self.instrs);
+            self.instrs.push(Instr::Str(ACC, m));
+        }
+        if f.is_some() {
+            let m = mem(Reg::SP, 8 * args.len() as i32, self.instrs);
+            self.instrs.push(Instr::Ldr(ACC, m));
+        }
+        self.instrs.push(Instr::Add(Reg::SP, Reg::SP, room));
+    }
+
+    // With the closure in ACC, leaves its address in KEEP.
+    fn check_arity(&mut self, n: usize) {
+        self.instrs.push(Instr::And(KEEP, ACC, Op::Imm(-16)));
+        self.instrs.push(Instr::Ldr(CNT, Mem::Off(KEEP, 16)));
+        let n = arith_imm((n as i64) << 1, self.instrs);
+        self.instrs.push(Instr::Cmp(CNT, n));
+        self.instrs.push(Instr::MovImm(ERR, 5));
+        self.instrs.push(Instr::B("ne", ERROR_LABEL.to_string()));
+    }
+
+    // Pops the frame, restoring the caller's frame pointer and return address.
+    fn leave(&mut self) {
+        self.instrs.push(Instr::Mov(Reg::SP, FP));
+        self.instrs.push(Instr::Ldp(FP, Reg::X30, Mem::Post(Reg::SP, 16)));
+    }
+
+    // Makes sure there are `size` bytes free at HEAP, running the garbage
+    // collector if needed. `size` is an immediate or KEEP.
+    fn alloc(&mut self, size: Op) {
+        let lok = new_label(self.label, "alloc_ok");
+        let instrs = &mut *self.instrs;
+        let end = match size {
+            Op::Imm(n) => arith_imm(n, instrs),
+            ref size => size.clone(),
+        };
+        instrs.push(Instr::Add(ACC, HEAP, end));
+        instrs.push(Instr::Cmp(ACC, Op::Reg(HEAP_END)));
+        instrs.push(Instr::B("ls", lok.to_string()));
+
+        instrs.push(Instr::Mov(Reg::X0, HEAP));
+        match size {
+            Op::Imm(n) => mov_imm(Reg::X1, n, instrs),
+            Op::Reg(r) => instrs.push(Instr::Mov(Reg::X1, r)),
+            _ => unreachable!(),
+        }
+        instrs.push(Instr::Mov(Reg::X2, Reg::SP));
+        instrs.push(Instr::Mov(Reg::X3, FP));
+        instrs.push(Instr::Bl("snek_gc".to_string()));
+        instrs.push(Instr::Mov(HEAP, Reg::X0));
+
+        // the call may have clobbered IMM, but not KEEP
+        let end = match size {
+            Op::Imm(n) => arith_imm(n, instrs),
+            size => size,
+        };
+        instrs.push(Instr::Add(ACC, HEAP, end));
+        instrs.push(Instr::Cmp(ACC, Op::Reg(HEAP_END)));
+        // out-of-memory error code
+        instrs.push(Instr::MovImm(ERR, 4));
+        instrs.push(Instr::B("hi", ERROR_LABEL.to_string()));
+        instrs.push(Instr::Label(lok));
+    }
+
+    // Calls a runtime function with `args` in x0, x1 and x2, leaving its
+    // result in ACC.
+    fn external_call(&mut self, args: &[&Operand], n: &str, first: Option<Reg>) {
+        let regs = [Reg::X0, Reg::X1, Reg::X2];
+        let mut regs = regs.iter();
+        if let Some(r) = first {
+            self.instrs.push(Instr::Mov(*regs.next().unwrap(), r));
+        }
+        for (r, a) in regs.zip(args) {
+            self.load(*r, a);
+        }
+        self.instrs.push(Instr::Bl(n.to_string()));
+        self.instrs.push(Instr::Mov(ACC, Reg::X0));
+    }
+
+    fn stmt(&mut self, s: &Stmt) {
+        match s {
+            Stmt::Assign(d, Rhs::Copy(a)) => {
+                let src = a.var().map(|v| self.locs[v]);
+                match (self.locs[d], src) {
+                    (Loc::Reg(r), _) => self.load(r, a),
+                    (dst, Some(src)) if dst == src => {},
+                    (_, Some(Loc::Reg(r))) => self.store(d, r),
+                    _ => {
+                        self.load(ACC, a);
+                        self.store(d, ACC);
+                    },
+                }
+            },
+            Stmt::Assign(d, rhs) => {
+                self.rhs(rhs);
+                self.store(d, ACC);
+            },
+            Stmt::Check(ty, a) => {
+                self.load(ACC, a);
+                match ty {
+                    Ty::Num => check_num(self.instrs),
+                    Ty::Tuple => check_tag(TUPLE_TAG, self.instrs),
+                    Ty::Vec => check_tag(VEC_TAG, self.instrs),
+                    Ty::Str => check_tag(STRING_TAG, self.instrs),
+                    Ty::Fun => check_tag(CLOSURE_TAG, self.instrs),
+                }
+            },
+            // Tuples and vectors share a layout: a header holding the length as a
+            // snek number, then the fields.
+            Stmt::CheckIndex(obj, idx) => {
+                // index-out-of-range error code
+                self.instrs.push(Instr::MovImm(ERR, 3));
+                self.load(ACC, obj);
+                // check empty
+                self.instrs.push(Instr::Cmp(ACC, Op::Imm(1)));
+                self.instrs.push(Instr::B("eq", ERROR_LABEL.to_string()));
+                self.instrs.push(Instr::And(ACC, ACC, Op::Imm(-16)));
+                self.load(TMP, idx);
+                // check len; the unsigned compare also rejects negative indices
+                self.instrs.push(Instr::Ldr(CNT, Mem::Off(ACC, 0)));
+                self.instrs.push(Instr::Cmp(TMP, Op::Reg(CNT)));
+                self.instrs.push(Instr::B("hs", ERROR_LABEL.to_string()));
+            },
+        }
+    }
+
+    // Evaluates `rhs` into ACC.
+    fn rhs(&mut self, rhs: &Rhs) {
+        match rhs {
+            Rhs::Copy(a) => self.load(ACC, a),
+            Rhs::Input => {
+                self.instrs.push(Instr::Adrp(ACC, INPUT_LABEL.to_string()));
+                self.instrs.push(Instr::Ldr(ACC, Mem::Lo12(ACC, INPUT_LABEL.to_string())));
+            },
+            Rhs::Prim1(o, a) => {
+                self.load(ACC, a);
+                self.prim1(o);
+            },
+            Rhs::Prim2(o, a, b) => self.prim2(o, a, b),
+            Rhs::Str(s) => self.string(s),
+            Rhs::Tuple(es) if es.is_empty() => self.instrs.push(Instr::MovImm(ACC, 1)),
+            Rhs::Tuple(es) => {
+                let size = object_size(es.len() + 1);
+                self.alloc(Op::Imm(size));
+                mov_imm(ACC, (es.len() as i64) << 1, self.instrs);
+                self.instrs.push(Instr::Str(ACC, Mem::Off(HEAP, 0)));
+                for (i, e) in (1..).zip(es) {
+                    self.load(ACC, e);
+                    let m = mem(HEAP, 8 * i, self.instrs);
+                    self.instrs.push(Instr::Str(ACC, m));
+                }
+                self.instrs.push(Instr::Add(ACC, HEAP, Op::Imm(TUPLE_TAG)));
+                let size = arith_imm(size, self.instrs);
+                self.instrs.push(Instr::Add(HEAP, HEAP, size));
+            },
+            Rhs::Get(_, obj, idx) => {
+                self.load(ACC, obj);
+                self.instrs.push(Instr::And(ACC, ACC, Op::Imm(-16)));
+                self.load(TMP, idx);
+                self.instrs.push(Instr::Add(ACC, ACC, Op::Lsl(TMP, 2)));
+                self.instrs.push(Instr::Ldr(ACC, Mem::Off(ACC, 8)));
+            },
+            Rhs::Set(_, obj, idx, v) => {
+                self.load(ACC, obj);
+                self.instrs.push(Instr::And(ACC, ACC, Op::Imm(-16)));
+                self.load(TMP, idx);
+                self.instrs.push(Instr::Add(TMP, ACC, Op::Lsl(TMP, 2)));
+                self.load(ACC, v);
+                self.instrs.push(Instr::Str(ACC, Mem::Off(TMP, 8)));
+            },
+            // a header, the code address, the arity, then the captured values
+            Rhs::Closure(label, arity, es) => {
+                let size = object_size(es.len() + 3);
+                self.alloc(Op::Imm(size));
+                mov_imm(ACC, (es.len() as i64 + 2) << 1, self.instrs);
+                self.instrs.push(Instr::Str(ACC, Mem::Off(HEAP, 0)));
+                self.instrs.push(Instr::Adr(ACC, label.to_string()));
+                self.instrs.push(Instr::Str(ACC, Mem::Off(HEAP, 8)));
+                mov_imm(ACC, (*arity as i64) << 1, self.instrs);
+                self.instrs.push(Instr::Str(ACC, Mem::Off(HEAP, 16)));
+                for (i, e) in (3..).zip(es) {
+                    self.load(ACC, e);
+                    let m = mem(HEAP, 8 * i, self.instrs);
+                    self.instrs.push(Instr::Str(ACC, m));
+                }
+                self.instrs.push(Instr::Add(ACC, HEAP, Op::Imm(CLOSURE_TAG)));
+                let size = arith_imm(size, self.instrs);
+                self.instrs.push(Instr::Add(HEAP, HEAP, size));
+            },
+            Rhs::Call(label, args) => {
+                let size = self.store_args(args);
+                // top-level functions ignore their closure
+                self.instrs.push(Instr::Str(Reg::XZR, Mem::Off(Reg::SP, 0)));
+                self.instrs.push(Instr::Bl(label.to_string()));
+                let size = arith_imm(size, self.instrs);
+                self.instrs.push(Instr::Add(Reg::SP, Reg::SP, size));
+            },
+            // the closure itself is passed below the arguments
+            Rhs::Apply(f, args) => {
+                let size = self.store_args(args);
+                self.load(ACC, f);
+                self.check_arity(args.len());
+                self.instrs.push(Instr::Str(ACC, Mem::Off(Reg::SP, 0)));
+                self.instrs.push(Instr::Ldr(TMP, Mem::Off(KEEP, 8)));
+                self.instrs.push(Instr::Blr(TMP));
+                let size = arith_imm(size, self.instrs);
+                self.instrs.push(Instr::Add(Reg::SP, Reg::SP, size));
+            },
+        }
+    }
+
+    // Sets ACC to true if the last comparison met `cond`, and to false if not.
+    fn bool_of(&mut self, cond: &'static str) {
+        self.instrs.push(Instr::MovImm(TMP, 7));
+        self.instrs.push(Instr::MovImm(ACC, 3));
+        self.instrs.push(Instr::Csel(ACC, TMP, ACC, cond));
+    }
+
+    fn prim1(&mut self, o: &Op1) {
+        match o {
+            Op1::Add1 => {
+                self.instrs.push(Instr::Adds(ACC, ACC, Op::Imm(2)));
+                check_overflow(self.instrs);
+            },
+            Op1::Sub1 => {
+                self.instrs.push(Instr::Subs(ACC, ACC, Op::Imm(2)));
+                check_overflow(self.instrs);
+            },
+
+            // bool here
+            Op1::IsNum => {
+                self.instrs.push(Instr::Tst(ACC, Op::Imm(1)));
+                self.bool_of("eq");
+            },
+            Op1::IsBool => {
+                self.instrs.push(Instr::And(ACC, ACC, Op::Imm(3)));
+                self.instrs.push(Instr::Cmp(ACC, Op::Imm(3)));
+                self.bool_of("eq");
+            },
+            Op1::IsTuple | Op1::IsFun | Op1::IsString => {
+                let tag = match o {
+                    Op1::IsTuple => TUPLE_TAG,
+                    Op1::IsFun => CLOSURE_TAG,
+                    _ => STRING_TAG,
+                };
+                self.instrs.push(Instr::And(ACC, ACC, Op::Imm(15)));
+                self.instrs.push(Instr::Cmp(ACC, Op::Imm(tag)));
+                self.bool_of("eq");
+            },
+            // the header is the length as a snek number
+            Op1::VecLen => {
+                self.instrs.push(Instr::And(ACC, ACC, Op::Imm(-16)));
+                self.instrs.push(Instr::Ldr(ACC, Mem::Off(ACC, 0)));
+            },
+            // the header is the byte count shifted left, with the low bit set
+            Op1::StringLength => {
+                self.instrs.push(Instr::And(ACC, ACC, Op::Imm(-16)));
+                self.instrs.push(Instr::Ldr(ACC, Mem::Off(ACC, 0)));
+                self.instrs.push(Instr::And(ACC, ACC, Op::Imm(-2)));
+            },
+            Op1::Print => self.external_call(&[], "snek_print", Some(ACC)),
+        }
+    }
+
+    fn prim2(&mut self, o: &Op2, a: &Operand, b: &Operand) {
+        match o {
+            Op2::StEq => self.external_call(&[a, b], "snek_structural_eq_true", None),
+            Op2::StringAppend => self.string_append(a, b),
+            Op2::StringRef => self.string_ref(a, b),
+            Op2::MakeVec => self.make_vec(a, b),
+            Op2::Plus | Op2::Minus => {
+                self.load(ACC, a);
+                let b = self.operand(b);
+                let i = match o {
+                    Op2::Plus => Instr::Adds(ACC, ACC, b),
+                    _ => Instr::Subs(ACC, ACC, b),
+                };
+                self.instrs.push(i);
+                check_overflow(self.instrs);
+            },
+            // the product overflows if its high half is not the sign of its
+            // low half
+            Op2::Times => {
+                self.load(ACC, a);
+                self.load(TMP, b);
+                self.instrs.push(Instr::Asr(ACC, ACC, 1));
+                self.instrs.push(Instr::Smulh(CNT, ACC, TMP));
+                self.instrs.push(Instr::Mul(ACC, ACC, TMP));
+                self.instrs.push(Instr::Cmp(CNT, Op::Asr(ACC, 63)));
+                self.instrs.push(Instr::MovImm(ERR, 2));
+                self.instrs.push(Instr::B("ne", ERROR_LABEL.to_string()));
+            },
+
+            // bool here
+            _ => {
+                self.load(ACC, a);
+                let b = self.operand(b);
+                self.instrs.push(Instr::Cmp(ACC, b));
+                let c = match o {
+                    Op2::Equal => "eq",
+                    Op2::Less => "lt",
+                    Op2::LessEqual => "le",
+                    Op2::Greater => "gt",
+                    Op2::GreaterEqual => "ge",
+                    _ => unreachable!(),
+                };
+                self.bool_of(c);
+            },
+        }
+    }
+
+    // A string is a header holding its length in bytes, shifted left with the
+    // low bit set so that the collector does not scan it, followed by the bytes.
+    fn string(&mut self, s: &str) {
+        let bytes = s.as_bytes();
+        let size = object_size(1 + (bytes.len() + 7) / 8);
+        self.alloc(Op::Imm(size));
+        mov_imm(ACC, ((bytes.len() as i64) << 1) | 1, self.instrs);
+        self.instrs.push(Instr::Str(ACC, Mem::Off(HEAP, 0)));
+        for (i, chunk) in (1..).zip(bytes.chunks(8)) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            mov_imm(ACC, i64::from_le_bytes(word), self.instrs);
+            let m = mem(HEAP, 8 * i, self.instrs);
+            self.instrs.push(Instr::Str(ACC, m));
+        }
+        self.instrs.push(Instr::Add(ACC, HEAP, Op::Imm(STRING_TAG)));
+        let size = arith_imm(size, self.instrs);
+        self.instrs.push(Instr::Add(HEAP, HEAP, size));
+    }
+
+    // The operands of an allocation are in the frame, where the collector can
+    // update them.
+    fn string_append(&mut self, a: &Operand, b: &Operand) {
+        // size of the result: a header plus both lengths, rounded up to 16 bytes
+        self.load(ACC, b);
+        self.instrs.push(Instr::And(ACC, ACC, Op::Imm(-16)));
+        self.instrs.push(Instr::Ldr(KEEP, Mem::Off(ACC, 0)));
+        self.instrs.push(Instr::Asr(KEEP, KEEP, 1));
+        self.load(ACC, a);
+        self.instrs.push(Instr::And(ACC, ACC, Op::Imm(-16)));
+        self.instrs.push(Instr::Ldr(ACC, Mem::Off(ACC, 0)));
+        self.instrs.push(Instr::Asr(ACC, ACC, 1));
+        self.instrs.push(Instr::Add(KEEP, KEEP, Op::Reg(ACC)));
+        self.instrs.push(Instr::Add(KEEP, KEEP, Op::Imm(8 + 15)));
+        self.instrs.push(Instr::And(KEEP, KEEP, Op::Imm(-16)));
+
+        self.alloc(Op::Reg(KEEP));
+        self.external_call(&[a, b], "snek_string_append", Some(HEAP));
+        self.instrs.push(Instr::Add(HEAP, HEAP, Op::Reg(KEEP)));
+    }
+
+    // `(string-ref s i)` is the one-byte string at index `i` of `s`.
+    fn string_ref(&mut self, s: &Operand, i: &Operand) {
+        // index-out-of-range error code
+        self.instrs.push(Instr::MovImm(ERR, 3));
+
+        // check bounds, catching negative indices with an unsigned compare
+        self.load(ACC, s);
+        self.instrs.push(Instr::And(ACC, ACC, Op::Imm(-16)));
+        self.instrs.push(Instr::Ldr(TMP, Mem::Off(ACC, 0)));
+        self.instrs.push(Instr::Sub(TMP, TMP, Op::Imm(1)));
+        self.load(CNT, i);
+        self.instrs.push(Instr::Cmp(CNT, Op::Reg(TMP)));
+        self.instrs.push(Instr::B("hs", ERROR_LABEL.to_string()));
+
+        self.alloc(Op::Imm(object_size(2)));
+        self.external_call(&[s, i], "snek_string_ref", Some(HEAP));
+        self.instrs.push(Instr::Add(HEAP, HEAP, Op::Imm(object_size(2))));
+    }
+
+    // `(make-vec n init)` allocates a vector of `n` copies of `init`.
+    fn make_vec(&mut self, n: &Operand, init: &Operand) {
+        self.load(ACC, n);
+        // a negative length is an invalid argument too
+        self.instrs.push(Instr::Cmp(ACC, Op::Imm(0)));
+        self.instrs.push(Instr::MovImm(ERR, 1));
+        self.instrs.push(Instr::B("lt", ERROR_LABEL.to_string()));
+
+        // lengths that cannot fit in the heap, before the size computation overflows
+        let max = arith_imm(i32::MAX as i64, self.instrs);
+        self.instrs.push(Instr::Cmp(ACC, max));
+        self.instrs.push(Instr::MovImm(ERR, 4));
+        self.instrs.push(Instr::B("gt", ERROR_LABEL.to_string()));
+
+        // size: a header and n words, rounded up to 16 bytes
+        self.instrs.push(Instr::Lsl(KEEP, ACC, 2));
+        self.instrs.push(Instr::Add(KEEP, KEEP, Op::Imm(8 + 15)));
+        self.instrs.push(Instr::And(KEEP, KEEP, Op::Imm(-16)));
+        self.alloc(Op::Reg(KEEP));
+
+        // the header is the length as a snek number
+        self.load(CNT, n);
+        self.instrs.push(Instr::Str(CNT, Mem::Off(HEAP, 0)));
+        self.instrs.push(Instr::Asr(CNT, CNT, 1));
+        self.load(ACC, init);
+        let lfill = new_label(self.label, "fill");
+        let ldone = new_label(self.label, "fill_done");
+        let instrs = &mut *self.instrs;
+        instrs.push(Instr::Label(lfill.to_string()));
+        instrs.push(Instr::Cmp(CNT, Op::Imm(0)));
+        instrs.push(Instr::B("eq", ldone.to_string()));
+        instrs.push(Instr::Str(ACC, Mem::Lsl(HEAP, CNT, 3)));
+        instrs.push(Instr::Sub(CNT, CNT, Op::Imm(1)));
+        instrs.push(Instr::B("", lfill));
+        instrs.push(Instr::Label(ldone));
+
+        instrs.push(Instr::Add(ACC, HEAP, Op::Imm(VEC_TAG)));
+        instrs.push(Instr::Add(HEAP, HEAP, Op::Reg(KEEP)));
+    }
+
+    // `next` is the label of the block that follows, which needs no jump.
+    fn term(&mut self, t: &Term, next: Option<&String>) {
+        match t {
+            Term::Jump(l) if Some(l) == next => {},
+            Term::Jump(l) => self.instrs.push(Instr::B("", l.to_string())),
+            Term::Branch(a, thn, els) => {
+                self.load(ACC, a);
+
+                // bool here
+                self.instrs.push(Instr::Cmp(ACC, Op::Imm(3)));
+                if Some(els) == next {
+                    self.instrs.push(Instr::B("ne", thn.to_string()));
+                } else {
+                    self.instrs.push(Instr::B("eq", els.to_string()));
+                    if Some(thn) != next {
+                        self.instrs.push(Instr::B("", thn.to_string()));
+                    }
+                }
+            },
+            Term::Return(a) => {
+                self.load(ACC, a);
+                self.leave();
+                self.instrs.push(Instr::Ret);
+            },
+            Term::TailCall(label, args) => {
+                self.move_tail_args(args, None);
+                self.leave();
+                self.instrs.push(Instr::B("", label.to_string()));
+            },
+            Term::TailApply(f, args) => {
+                self.move_tail_args(args, Some(f));
+                self.check_arity(args.len());
+                self.instrs.push(Instr::Str(ACC, Mem::Off(FP, 16)));
+                self.instrs.push(Instr::Ldr(ACC, Mem::Off(KEEP, 8)));
+                self.leave();
+                self.instrs.push(Instr::Br(ACC));
+            },
+        }
+    }
+}
+
+// The closure being called is at [x29 + 16] and the arguments follow it. The
+// captured values stored in the closure are copied out on entry.
+fn compile_fun(f: &ir::Fun, regs: &[Reg], max_args: usize, label: &mut i32, instrs: &mut Vec<Instr>) {
+    let (locs, slots) = allocate(f, regs);
+    instrs.push(Instr::Label(f.name.to_string()));
+    instrs.push(Instr::Stp(FP, Reg::X30, Mem::Pre(Reg::SP, -16)));
+    instrs.push(Instr::Mov(FP, Reg::SP));
+    if slots > 0 {
+        let room = arith_imm(stack_size(slots as usize), instrs);
+        instrs.push(Instr::Sub(Reg::SP, Reg::SP, room));
+    }
+    let mut g = FunGen { locs, max_args, label, instrs };
+    if !f.captured.is_empty() {
+        g.instrs.push(Instr::Ldr(KEEP, Mem::Off(FP, 16)));
+        g.instrs.push(Instr::And(KEEP, KEEP, Op::Imm(-16)));
+        for (i, v) in (3..).zip(&f.captured) {
+            let m = mem(KEEP, 8 * i, g.instrs);
+            g.instrs.push(Instr::Ldr(ACC, m));
+            g.store(v, ACC);
+        }
+    }
+    for (i, b) in f.blocks.iter().enumerate() {
+        if i > 0 {
+            g.instrs.push(Instr::Label(b.label.to_string()));
+        }
+        for s in &b.stmts {
+            g.stmt(s);
+        }
+        g.term(&b.term, f.blocks.get(i + 1).map(|b| &b.label));
+    }
+}
+
+fn instr_to_str(i: &Instr) -> String {
+    match i {
+        Instr::Mov(d, s) => format!("mov {}, {}\n", reg_to_str(d), reg_to_str(s)),
+        Instr::MovImm(d, n) => format!("mov {}, #{n}\n", reg_to_str(d)),
+        Instr::Movz(d, n) => format!("movz {}, #{n}\n", reg_to_str(d)),
+        Instr::Movk(d, n, s) => format!("movk {}, #{n}, lsl #{s}\n", reg_to_str(d)),
+        Instr::Ldr(r, m) => format!("ldr {}, {}\n", reg_to_str(r), mem_to_str(m)),
+        Instr::Str(r, m) => format!("str {}, {}\n", reg_to_str(r), mem_to_str(m)),
+        Instr::Ldp(r1, r2, m) => format!("ldp {}, {}, {}\n", reg_to_str(r1), reg_to_str(r2), mem_to_str(m)),
+        Instr::Stp(r1, r2, m) => format!("stp {}, {}, {}\n", reg_to_str(r1), reg_to_str(r2), mem_to_str(m)),
+        Instr::Add(d, s, o) => format!("add {}, {}, {}\n", reg_to_str(d), reg_to_str(s), op_to_str(o)),
+        Instr::Adds(d, s, o) => format!("adds {}, {}, {}\n", reg_to_str(d), reg_to_str(s), op_to_str(o)),
+        Instr::Sub(d, s, o) => format!("sub {}, {}, {}\n", reg_to_str(d), reg_to_str(s), op_to_str(o)),
+        Instr::Subs(d, s, o) => format!("subs {}, {}, {}\n", reg_to_str(d), reg_to_str(s), op_to_str(o)),
+        Instr::And(d, s, o) => format!("and {}, {}, {}\n", reg_to_str(d), reg_to_str(s), op_to_str(o)),
+        Instr::Lsl(d, s, n) => format!("lsl {}, {}, #{n}\n", reg_to_str(d), reg_to_str(s)),
+        Instr::Asr(d, s, n) => format!("asr {}, {}, #{n}\n", reg_to_str(d), reg_to_str(s)),
+        Instr::Mul(d, a, b) => format!("mul {}, {}, {}\n", reg_to_str(d), reg_to_str(a), reg_to_str(b)),
+        Instr::Smulh(d, a, b) => format!("smulh {}, {}, {}\n", reg_to_str(d), reg_to_str(a), reg_to_str(b)),
+        Instr::Cmp(r, o) => format!("cmp {}, {}\n", reg_to_str(r), op_to_str(o)),
+        Instr::Tst(r, o) => format!("tst {}, {}\n", reg_to_str(r), op_to_str(o)),
+        Instr::Csel(d, a, b, c) => format!("csel {}, {}, {}, {c}\n", reg_to_str(d), reg_to_str(a), reg_to_str(b)),
+        Instr::B("", l) => format!("b {l}\n"),
+        Instr::B(c, l) => format!("b.{c} {l}\n"),
+        Instr::Bl(l) => format!("bl {l}\n"),
+        Instr::Blr(r) => format!("blr {}\n", reg_to_str(r)),
+        Instr::Br(r) => format!("br {}\n", reg_to_str(r)),
+        Instr::Ret => "ret\n".to_string(),
+        Instr::Adr(r, l) => format!("adr {}, {l}\n", reg_to_str(r)),
+        Instr::Adrp(r, l) => format!("adrp {}, {l}\n", reg_to_str(r)),
+        Instr::Label(l) => format!("{l}:\n"),
+    }
+}
+
+fn op_to_str(o: &Op) -> String {
+    match o {
+        Op::Reg(r) => reg_to_str(r).to_string(),
+        Op::Imm(n) => format!("#{n}"),
+        Op::Lsl(r, n) => format!("{}, lsl #{n}", reg_to_str(r)),
+        Op::Asr(r, n) => format!("{}, asr #{n}", reg_to_str(r)),
+    }
+}
+
+fn mem_to_str(m: &Mem) -> String {
+    match m {
+        Mem::Off(r, 0) => format!("[{}]", reg_to_str(r)),
+        Mem::Off(r, n) => format!("[{}, #{n}]", reg_to_str(r)),
+        Mem::Pre(r, n) => format!("[{}, #{n}]!", reg_to_str(r)),
+        Mem::Post(r, n) => format!("[{}], #{n}", reg_to_str(r)),
+        Mem::Lsl(b, i, n) => format!("[{}, {}, lsl #{n}]", reg_to_str(b), reg_to_str(i)),
+        Mem::Lo12(r, l) => format!("[{}, :lo12:{l}]", reg_to_str(r)),
+    }
+}
+
+fn reg_to_str(r: &Reg) -> &str {
+    match r {
+        Reg::X0 => "x0",
+        Reg::X1 => "x1",
+        Reg::X2 => "x2",
+        Reg::X3 => "x3",
+        Reg::X9 => "x9",
+        Reg::X10 => "x10",
+        Reg::X11 => "x11",
+        Reg::X16 => "x16",
+        Reg::X17 => "x17",
+        Reg::X19 => "x19",
+        Reg::X20 => "x20",
+        Reg::X21 => "x21",
+        Reg::X22 => "x22",
+        Reg::X23 => "x23",
+        Reg::X24 => "x24",
+        Reg::X25 => "x25",
+        Reg::X26 => "x26",
+        Reg::X27 => "x27",
+        Reg::X28 => "x28",
+        Reg::X29 => "x29",
+        Reg::X30 => "x30",
+        Reg::SP => "sp",
+        Reg::XZR => "xzr",
+    }
+}
+
+/// Generates the instructions for a program. With `regalloc` unset, every
+/// variable is kept in the frame.
+pub fn generate(p: &ir::Prog, regalloc: bool) -> Vec<Instr<'static>> {
+    let mut instrs: Vec<Instr> = Vec::new();
+    let mut label = 0;
+    let regs: &[Reg] = if regalloc { &ALLOC_REGS } else { &[] };
+    let max_args = max_args(p);
+
+    // the error code is already in x0, and the stack is always aligned
+    instrs.push(Instr::Label(ERROR_LABEL.to_string()));
+    instrs.push(Instr::Bl("snek_error".to_string()));
+
+    for f in &p.funs {
+        compile_fun(f, regs, max_args, &mut label, &mut instrs);
+    }
+
+    // our_code_starts_here(input, heap start, heap end), which saves the
+    // callee-saved registers that snek code uses
+    let saved = [(Reg::X19, Reg::X20), (Reg::X21, Reg::X22), (Reg::X23, Reg::X24), (Reg::X25, Reg::X26), (Reg::X27, Reg::X28)];
+    let frame = 16 * (saved.len() as i32 + 1);
+    instrs.push(Instr::Label(ENTRY_LABEL.to_string()));
+    instrs.push(Instr::Stp(FP, Reg::X30, Mem::Pre(Reg::SP, -frame)));
+    for (i, (r1, r2)) in (1..).zip(saved) {
+        instrs.push(Instr::Stp(r1, r2, Mem::Off(Reg::SP, 16 * i)));
+    }
+    instrs.push(Instr::Mov(HEAP, Reg::X1));
+    instrs.push(Instr::Mov(HEAP_END, Reg::X2));
+    instrs.push(Instr::Adrp(ACC, INPUT_LABEL.to_string()));
+    instrs.push(Instr::Str(Reg::X0, Mem::Lo12(ACC, INPUT_LABEL.to_string())));
+    // a zero saved frame pointer marks the outermost snek frame for the garbage
+    // collector
+    instrs.push(Instr::Mov(FP, Reg::XZR));
+    instrs.push(Instr::Bl(ir::MAIN_LABEL.to_string()));
+    instrs.push(Instr::Mov(Reg::X0, ACC));
+    for (i, (r1, r2)) in (1..).zip(saved) {
+        instrs.push(Instr::Ldp(r1, r2, Mem::Off(Reg::SP, 16 * i)));
+    }
+    instrs.push(Instr::Ldp(FP, Reg::X30, Mem::Post(Reg::SP, frame)));
+    instrs.push(Instr::Ret);
+
+    compile_fun(&p.main, regs, max_args, &mut label, &mut instrs);
+    instrs
+}
+
+/// Generates the assembly for a program. With `regalloc` unset, every variable
+/// is kept in the frame.
+pub fn compile(p: &ir::Prog, regalloc: bool) -> String {
+    let result = generate(p, regalloc).iter().map(instr_to_str).collect::<String>();
+    format!(
+        ".text
+.globl {ENTRY_LABEL}
+{result}
+.data
+.balign 8
+{INPUT_LABEL}: .quad 0
+"
+    )
+}
@@ -0,0 +1,86 @@
+use crate::error::Span;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Op1 {
+    Add1,
+    Sub1,
+    IsNum,
+    IsBool,
+    IsTuple,
+    IsFun,
+    IsString,
+    StringLength,
+    VecLen,
+    Print,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Op2 {
+    Plus,
+    Minus,
+    Times,
+    Equal,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    StEq,
+    StringAppend,
+    StringRef,
+    MakeVec,
+    // StEqEq,
+}
+
+#[derive(Debug)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum ExprKind {
+    Number(i64),
+    Boolean(bool),
+    Str(String),
+    Id(String),
+    Let(Vec<(String, Expr)>, Box<Expr>),
+    UnOp(Op1, Box<Expr>),
+    BinOp(Op2, Box<Expr>, Box<Expr>),
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+    Loop(Box<Expr>),
+    Break(Box<Expr>),
+    Set(String, Box<Expr>),
+    Block(Vec<Expr>),
+    Call(String, Vec<Expr>),
+    Apply(Box<Expr>, Vec<Expr>),
+    Lambda(Vec<String>, Box<Expr>),
+    Tuple(Vec<Expr>),
+    TupleGet(Box<Expr>, Box<Expr>),
+    TupleSet(Box<Expr>, Box<Expr>, Box<Expr>),
+    VecGet(Box<Expr>, Box<Expr>),
+    VecSet(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+// The types of the optional static checker. `Any` is the type of anything not
+// annotated, and is compatible with every other type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Any,
+    Num,
+    Bool,
+    Str,
+    Tuple(Vec<Type>),
+    Vec(Box<Type>),
+    Fun(Vec<Type>, Box<Type>),
+}
+
+pub struct Func {
+    pub name: String,
+    pub args: Vec<String>,
+    pub arg_types: Vec<Type>,
+    pub ret: Type,
+    pub expr: Expr,
+    pub span: Span,
+}
+
+pub struct Prog(pub Vec<Func>, pub Expr);
@@ -0,0 +1,61 @@
+use std::env;
+
+// shared with the compiler, which also runs native code and writes bytecode
+#[allow(dead_code)]
+#[path = "../../runtime/snek.rs"]
+mod runtime;
+#[allow(dead_code)]
+#[path = "../snekc.rs"]
+mod snekc;
+#[path = "../vm.rs"]
+mod vm;
+
+// Runs a `.snekc` file written with `--target=bytecode`.
+
+// Parses `[--heap-size BYTES] FILE [input]`.
+fn parse_args(args: &[String]) -> (usize, Option<&str>, &str) {
+    let mut heap_size = runtime::DEFAULT_HEAP_SIZE;
+    let mut file = None;
+    let mut input = "false";
+    let mut i = 1;
+    while i < args.len() {
+        if args[i] == "--heap-size" {
+            if i + 1 == args.len() {
+                eprintln!("option --heap-size needs a value");
+                std::process::exit(1);
+            }
+            heap_size = args[i + 1].parse().unwrap_or_else(|_| {
+                eprintln!("invalid heap size: {}", args[i + 1]);
+                std::process::exit(1);
+            });
+            i += 2;
+        } else {
+            if file.is_none() {
+                file = Some(args[i].as_str());
+            } else {
+                input = &args[i];
+            }
+            i += 1;
+        }
+    }
+    (heap_size, file, input)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let (heap_size, file, input) = parse_args(&args);
+    let Some(file) = file else {
+        eprintln!("usage: snek-vm [--heap-size BYTES] FILE.snekc [input]");
+        std::process::exit(1);
+    };
+    let bytes = std::fs::read(file).unwrap_or_else(|e| {
+        eprintln!("cannot read {file}: {e}");
+        std::process::exit(1);
+    });
+    let program = snekc::read(&bytes).unwrap_or_else(|e| {
+        eprintln!("{file}: {e}");
+        std::process::exit(1);
+    });
+    let input = runtime::parse_input(input);
+    vm::run(&program, input, heap_size);
+}
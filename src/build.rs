@@ -0,0 +1,91 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Makes executables for `build` with the tools the Makefile uses: the assembly
+// is assembled by nasm, or by `as` for `--syntax=gas`, and linked by the C
+// compiler against the runtime. The runtime is runtime/start.rs built once as
+// a static library providing `main`, with `make runtime/libsnek.a`, so that
+// linking needs no rustc.
+
+/// The runtime library linked unless `--runtime` names another.
+pub const DEFAULT_RUNTIME: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/runtime/libsnek.a");
+
+/// How far `build` goes.
+pub enum Stage {
+    Asm,
+    Obj,
+    Exe,
+}
+
+/// The output named after `in_name` in the current directory, or after `a`
+/// when the program is read from stdin.
+pub fn default_output(in_name: &str, stage: &Stage) -> String {
+    let stem = match Path::new(in_name).file_stem() {
+        Some(stem) if in_name != "-" => stem.to_string_lossy().into_owned(),
+        _ => "a".to_string(),
+    };
+    match stage {
+        Stage::Asm => format!("{stem}.s"),
+        Stage::Obj => format!("{stem}.o"),
+        Stage::Exe if in_name == "-" => "a.out".to_string(),
+        Stage::Exe => stem,
+    }
+}
+
+/// Writes `asm` to `out`, or assembles it there, or links it with `runtime`
+/// into an executable there, depending on `stage`.
+pub fn build(asm: &str, gas: bool, stage: &Stage, out: &Path, runtime: &Path) -> Result<(), String> {
+    if let Stage::Asm = stage {
+        return fs::write(out, asm).map_err(|e| format!("could not write {}: {e}", out.display()));
+    }
+    if let Stage::Exe = stage {
+        if !runtime.is_file() {
+            return Err(format!(
+                "runtime library {} not found; build it with `make runtime/libsnek.a` or name one with --runtime",
+                runtime.display()
+            ));
+        }
+    }
+    let dir = env::temp_dir().join(format!("snek-build-{}", std::process::id()));
+    fs::create_dir_all(&dir).map_err(|e| format!("could not create {}: {e}", dir.display()))?;
+    let result = assemble_and_link(asm, gas, stage, out, runtime, &dir);
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+fn assemble_and_link(asm: &str, gas: bool, stage: &Stage, out: &Path, runtime: &Path, dir: &Path) -> Result<(), String> {
+    let asm_file = dir.join("prog.s");
+    fs::write(&asm_file, asm).map_err(|e| format!("could not write {}: {e}", asm_file.display()))?;
+    let obj_file: PathBuf = if let Stage::Obj = stage { out.to_path_buf() } else { dir.join("prog.o") };
+    if gas {
+        tool(Command::new("as").arg(&asm_file).arg("-o").arg(&obj_file))?;
+    } else {
+        let format = if cfg!(target_os = "macos") { "macho64" } else { "elf64" };
+        tool(Command::new("nasm").args(["-f", format]).arg(&asm_file).arg("-o").arg(&obj_file))?;
+    }
+    if let Stage::Exe = stage {
+        let cc = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+        let mut link = Command::new(cc);
+        link.arg(&obj_file).arg(runtime).arg("-o").arg(out);
+        if cfg!(target_os = "linux") {
+            // the assembly has no .note.GNU-stack section to say so, and
+            // older C libraries keep threads and dynamic loading apart
+            link.args(["-Wl,-z,noexecstack", "-lpthread", "-ldl", "-lm"]);
+        }
+        tool(&mut link)?;
+    }
+    Ok(())
+}
+
+// Runs an external tool, whose own messages go to stderr.
+fn tool(cmd: &mut Command) -> Result<(), String> {
+    let name = cmd.get_program().to_string_lossy().into_owned();
+    let status = cmd.status().map_err(|e| format!("could not run {name}: {e}"))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{name} failed"))
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+
+use crate::ast::{Op1, Op2};
+use crate::frame::{allocate, Loc};
+use crate::ir::{self, Operand, Rhs, Stmt, Term, Ty, Var};
+use crate::snekc::{self, Instr};
+
+// Translates programs to the bytecode run by `snek-vm`. Every statement pushes
+// its operands, and the result is stored to the variable's slot, so the stack
+// is empty between statements. Variables that do not interfere share a slot,
+// as they share a frame slot in the assembly.
+
+// Code generation for one function.
+struct FunGen<'a> {
+    slots: HashMap<Var, u32>,
+    funs: &'a HashMap<&'a str, u32>,
+    code: Vec<Instr>,
+    // the jumps to patch with the position of each label
+    jumps: Vec<(usize, String)>,
+}
+
+impl FunGen<'_> {
+    fn fun(&self, label: &str) -> u32 {
+        self.funs[label]
+    }
+
+    fn push(&mut self, op: &Operand) {
+        let i = match op {
+            Operand::Var(v) => Instr::Load(self.slots[v]),
+            Operand::Num(n) => Instr::Const(n << 1),
+            Operand::Bool(b) => Instr::Const(if *b { 7 } else { 3 }),
+        };
+        self.code.push(i);
+    }
+
+    fn push_all<'o>(&mut self, ops: impl IntoIterator<Item = &'o Operand>) {
+        for op in ops {
+            self.push(op);
+        }
+    }
+
+    fn jump(&mut self, i: Instr, label: &str) {
+        self.jumps.push((self.code.len(), label.to_string()));
+        self.code.push(i);
+    }
+
+    fn stmt(&mut self, s: &Stmt) {
+        match s {
+            Stmt::Assign(d, Rhs::Copy(Operand::Var(v))) if d == v => {},
+            Stmt::Assign(d, rhs) => {
+                self.rhs(rhs);
+                self.code.push(Instr::Store(self.slots[d]));
+            },
+            Stmt::Check(ty, a) => {
+                self.push(a);
+                self.code.push(match ty {
+                    Ty::Num => Instr::CheckNum,
+                    Ty::Tuple => Instr::CheckTuple,
+                    Ty::Vec => Instr::CheckVec,
+                    Ty::Str => Instr::CheckStr,
+                    Ty::Fun => Instr::CheckFun,
+                });
+            },
+            Stmt::CheckIndex(obj, idx) => {
+                self.push_all([obj, idx]);
+                self.code.push(Instr::CheckIndex);
+            },
+        }
+    }
+
+    // Pushes the value of `rhs`.
+    fn rhs(&mut self, rhs: &Rhs) {
+        let i = match rhs {
+            Rhs::Copy(a) => return self.push(a),
+            Rhs::Input => Instr::Input,
+            Rhs::Prim1(o, a) => {
+                self.push(a);
+                prim1(o)
+            },
+            Rhs::Prim2(o, a, b) => {
+                self.push_all([a, b]);
+                prim2(o)
+            },
+            Rhs::Str(s) => Instr::Str(s.as_bytes().to_vec()),
+            Rhs::Tuple(es) if es.is_empty() => Instr::Const(1),
+            Rhs::Tuple(es) => {
+                self.push_all(es);
+                Instr::Tuple(es.len() as u32)
+            },
+            Rhs::Get(_, obj, idx) => {
+                self.push_all([obj, idx]);
+                Instr::Get
+            },
+            Rhs::Set(_, obj, idx, v) => {
+                self.push_all([obj, idx, v]);
+                Instr::Set
+            },
+            // the arity is the function's own
+            Rhs::Closure(label, _, es) => {
+                self.push_all(es);
+                Instr::Closure(self.fun(label))
+            },
+            Rhs::Call(label, args) => {
+                self.push_all(args);
+                Instr::Call(self.fun(label), args.len() as u32)
+            },
+            Rhs::Apply(f, args) => {
+                self.push_all(args.iter().chain([f]));
+                Instr::Apply(args.len() as u32)
+            },
+        };
+        self.code.push(i);
+    }
+
+    // `next` is the label of the block that follows, which needs no jump.
+    fn term(&mut self, t: &Term, next: Option<&String>) {
+        match t {
+            Term::Jump(l) if Some(l) == next => {},
+            Term::Jump(l) => self.jump(Instr::Jump(0), l),
+            Term::Branch(a, thn, els) => {
+                self.push(a);
+                self.jump(Instr::JumpIfFalse(0), els);
+                if Some(thn) != next {
+                    self.jump(Instr::Jump(0), thn);
+                }
+            },
+            Term::Return(a) => {
+                self.push(a);
+                self.code.push(Instr::Return);
+            },
+            Term::TailCall(label, args) => {
+                self.push_all(args);
+                self.code.push(Instr::TailCall(self.fun(label), args.len() as u32));
+            },
+            Term::TailApply(f, args) => {
+                self.push_all(args.iter().chain([f]));
+                self.code.push(Instr::TailApply(args.len() as u32));
+            },
+        }
+    }
+}
+
+fn prim1(o: &Op1) -> Instr {
+    match o {
+        Op1::Add1 => Instr::Add1,
+        Op1::Sub1 => Instr::Sub1,
+        Op1::IsNum => Instr::IsNum,
+        Op1::IsBool => Instr::IsBool,
+        Op1::IsTuple => Instr::IsTuple,
+        Op1::IsFun => Instr::IsFun,
+        Op1::IsString => Instr::IsString,
+        Op1::StringLength => Instr::StringLength,
+        Op1::VecLen => Instr::VecLen,
+        Op1::Print => Instr::Print,
+    }
+}
+
+fn prim2(o: &Op2) -> Instr {
+    match o {
+        Op2::Plus => Instr::Plus,
+        Op2::Minus => Instr::Minus,
+        Op2::Times => Instr::Times,
+        Op2::Equal => Instr::Equal,
+        Op2::Less => Instr::Less,
+        Op2::LessEqual => Instr::LessEqual,
+        Op2::Greater => Instr::Greater,
+        Op2::GreaterEqual => Instr::GreaterEqual,
+        Op2::StEq => Instr::StEq,
+        Op2::StringAppend => Instr::StringAppend,
+        Op2::StringRef => Instr::StringRef,
+        Op2::MakeVec => Instr::MakeVec,
+    }
+}
+
+// The arguments take the first slots and the closure the next, as in the
+// assembly, where they are above the frame pointer; the frame's own slots
+// follow.
+fn compile_fun(f: &ir::Fun, funs: &HashMap<&str, u32>) -> snekc::Fun {
+    let arity = f.params.len() as u32;
+    let (locs, frame_slots) = allocate::<()>(f, &[]);
+    let slots = locs.into_iter().map(|(v, l)| {
+        let slot = match l {
+            Loc::Stack(w) if w > 0 => w as u32 - 3,
+            Loc::Stack(w) => arity + (-w) as u32,
+            Loc::Reg(()) => unreachable!("no registers were given"),
+        };
+        (v, slot)
+    }).collect();
+    let mut g = FunGen { slots, funs, code: vec![], jumps: vec![] };
+    for (i, v) in f.captured.iter().enumerate() {
+        g.code.push(Instr::Captured(i as u32));
+        g.code.push(Instr::Store(g.slots[v]));
+    }
+    let mut labels = HashMap::new();
+    for (i, b) in f.blocks.iter().enumerate() {
+        labels.insert(&b.label, g.code.len() as u32);
+        for s in &b.stmts {
+            g.stmt(s);
+        }
+        g.term(&b.term, f.blocks.get(i + 1).map(|b| &b.label));
+    }
+    for (at, label) in g.jumps {
+        match &mut g.code[at] {
+            Instr::Jump(l) | Instr::JumpIfFalse(l) => *l = labels[&label],
+            i => unreachable!("{i:?} is not a jump"),
+        }
+    }
+    snekc::Fun {
+        name: f.name.clone(),
+        arity,
+        captured: f.captured.len() as u32,
+        locals: arity + 1 + frame_slots as u32,
+        stack: 0,
+        code: g.code,
+    }
+}
+
+/// Generates the bytecode for a program, its entry first.
+pub fn generate(p: &ir::Prog) -> snekc::Program {
+    let all: Vec<&ir::Fun> = [&p.main].into_iter().chain(&p.funs).collect();
+    let funs: HashMap<&str, u32> = all.iter().enumerate().map(|(i, f)| (f.name.as_str(), i as u32)).collect();
+    let mut prog = snekc::Program { funs: all.iter().map(|f| compile_fun(f, &funs)).collect() };
+    for i in 0..prog.funs.len() {
+        let stack = snekc::max_stack(&prog, &prog.funs[i]).unwrap_or_else(|e| panic!("{e}"));
+        prog.funs[i].stack = stack;
+    }
+    prog
+}
+
+/// The contents of the `.snekc` file for a program, which is read back as
+/// `snek-vm` will read it to check that it verifies.
+pub fn compile(p: &ir::Prog) -> Vec<u8> {
+    let bytes = snekc::write(&generate(p));
+    if let Err(e) = snekc::read(&bytes) {
+        panic!("generated bytecode does not verify: {e}");
+    }
+    bytes
+}
@@ -0,0 +1,447 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use crate::ast::{Op1, Op2};
+use crate::frame::{allocate, max_args};
+use crate::ir::{self, Operand, Rhs, Stmt, Term, Ty, Var};
+use crate::x86::ENTRY_LABEL;
+
+// Translates programs to C, to be compiled with any C compiler that has the
+// GCC overflow builtins and linked with the runtime. Values are tagged as they
+// are in the assembly. The collector cannot find values the C compiler keeps in
+// registers or its own stack, so every value that must survive a call lives on
+// a shadow stack, in frames laid out like the machine frames: arguments above
+// the closure being called, and the function's slots below. As in the
+// assembly, only variables that are not live across a call are kept in C
+// locals.
+
+// The declarations and helpers every program starts with.
+const PRELUDE: &str = r#"#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+typedef int64_t (*snek_fn)(int64_t *args);
+
+extern _Noreturn void snek_error(int64_t code);
+extern int64_t snek_print(int64_t val);
+extern int64_t snek_structural_eq_true(int64_t a, int64_t b);
+extern int64_t *snek_gc(int64_t *alloc, uint64_t needed, int64_t *sp, const int64_t *base);
+extern int64_t snek_string_append(int64_t *dst, int64_t a, int64_t b);
+extern int64_t snek_string_ref(int64_t *dst, int64_t s, int64_t i);
+
+// the object a heap value refers to
+#define ADDR(v) ((int64_t *)(intptr_t)((v) & -16))
+
+#define STACK_WORDS (1 << 22)
+
+// The shadow stack, which the collector scans from snek_sp up to its last
+// word. That word is zero, as the saved frame pointer of the outermost frame.
+static int64_t snek_stack[STACK_WORDS + 1];
+static int64_t *snek_sp = snek_stack + STACK_WORDS;
+static int64_t *snek_heap;
+static int64_t *snek_heap_end;
+static int64_t snek_input;
+
+// Pushes a frame of `slots` words, with room for the arguments of its calls.
+static inline int64_t *snek_enter(int64_t slots) {
+    int64_t *fp = snek_sp;
+    if (fp - snek_stack < slots + ARGS_WORDS) {
+        fputs("an error ocurred stack overflow\n", stderr);
+        exit(1);
+    }
+    snek_sp = fp - slots;
+    return fp;
+}
+
+// Makes sure there are `bytes` free on the heap, running the garbage collector
+// if needed.
+static inline void snek_alloc(int64_t bytes) {
+    if (snek_heap_end - snek_heap < bytes / 8) {
+        snek_heap = snek_gc(snek_heap, bytes, snek_sp, snek_stack + STACK_WORDS);
+        if (snek_heap_end - snek_heap < bytes / 8) {
+            snek_error(4);
+        }
+    }
+}
+"#;
+
+// Heap objects are 16-byte aligned and tagged in the low four bits.
+const TUPLE_TAG: i64 = 1;
+const CLOSURE_TAG: i64 = 5;
+const STRING_TAG: i64 = 9;
+const VEC_TAG: i64 = 13;
+
+// C locals that variables can be kept in, in place of registers.
+const ALLOC_REGS: [usize; 8] = [0, 1, 2, 3, 4, 5, 6, 7];
+
+// Bytes taken by a heap object of `words` words, keeping the next one aligned.
+fn object_size(words: usize) -> i64 {
+    8 * (words + words % 2) as i64
+}
+
+// A 64-bit constant, which C has no literal for when it is the most negative.
+fn lit(n: i64) -> String {
+    match n {
+        i64::MIN => "INT64_MIN".to_string(),
+        n if i32::try_from(n).is_ok() => n.to_string(),
+        n => format!("INT64_C({n})"),
+    }
+}
+
+// A C string literal holding `s`, with anything that is not plain ASCII
+// escaped.
+fn c_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for b in s.bytes() {
+        match b {
+            b'"' | b'\\' | b'?' => write!(out, "\\{}", b as char).unwrap(),
+            b' '..=b'~' => out.push(b as char),
+            _ => write!(out, "\\{b:03o}").unwrap(),
+        }
+    }
+    out.push('"');
+    out
+}
+
+type Loc = crate::frame::Loc<usize>;
+
+// Code generation for one function.
+struct FunGen {
+    locs: HashMap<Var, Loc>,
+    out: String,
+    // the labels jumped to, as C warns about the others
+    targets: HashSet<String>,
+}
+
+impl FunGen {
+    // Where `v` lives, as a C lvalue. The closure is `args[0]`, and the
+    // arguments follow it.
+    fn var(&self, v: &Var) -> String {
+        match self.locs[v] {
+            Loc::Reg(r) => format!("r{r}"),
+            Loc::Stack(w) if w > 0 => format!("args[{}]", w - 2),
+            Loc::Stack(w) => format!("fp[{w}]"),
+        }
+    }
+
+    fn val(&self, op: &Operand) -> String {
+        match op {
+            Operand::Var(v) => self.var(v),
+            Operand::Num(n) => lit(n << 1),
+            Operand::Bool(b) => (if *b { "7" } else { "3" }).to_string(),
+        }
+    }
+
+    fn line(&mut self, s: &str) {
+        writeln!(self.out, "    {s}").unwrap();
+    }
+
+    fn stmt(&mut self, s: &Stmt) {
+        match s {
+            Stmt::Assign(d, rhs) => {
+                let d = self.var(d);
+                self.rhs(&d, rhs);
+            },
+            Stmt::Check(ty, a) => {
+                let a = self.val(a);
+                let cond = match ty {
+                    Ty::Num => format!("{a} & 1"),
+                    Ty::Tuple => format!("({a} & 15) != {TUPLE_TAG}"),
+                    Ty::Vec => format!("({a} & 15) != {VEC_TAG}"),
+                    Ty::Str => format!("({a} & 15) != {STRING_TAG}"),
+                    Ty::Fun => format!("({a} & 15) != {CLOSURE_TAG}"),
+                };
+                self.line(&format!("if ({cond}) snek_error(1);"));
+            },
+            // Tuples and vectors share a layout: a header holding the length as a
+            // snek number, then the fields. The unsigned compare also rejects
+            // negative indices.
+            Stmt::CheckIndex(obj, idx) => {
+                let (obj, idx) = (self.val(obj), self.val(idx));
+                self.line(&format!("if ({obj} == 1 || (uint64_t){idx} >= (uint64_t)ADDR({obj})[0]) snek_error(3);"));
+            },
+        }
+    }
+
+    // Assigns the value of `rhs` to `d`.
+    fn rhs(&mut self, d: &str, rhs: &Rhs) {
+        match rhs {
+            Rhs::Copy(a) => {
+                let a = self.val(a);
+                if a != d {
+                    self.line(&format!("{d} = {a};"));
+                }
+            },
+            Rhs::Input => self.line(&format!("{d} = snek_input;")),
+            Rhs::Prim1(o, a) => self.prim1(d, o, a),
+            Rhs::Prim2(o, a, b) => self.prim2(d, o, a, b),
+            Rhs::Str(s) => {
+                let size = object_size(1 + (s.len() + 7) / 8);
+                self.line(&format!("snek_alloc({size});"));
+                self.line(&format!("snek_heap[0] = {};", ((s.len() as i64) << 1) | 1));
+                self.line(&format!("memcpy(snek_heap + 1, {}, {});", c_string(s), s.len()));
+                self.line(&format!("{d} = (int64_t)(intptr_t)snek_heap + {STRING_TAG};"));
+                self.line(&format!("snek_heap += {};", size / 8));
+            },
+            Rhs::Tuple(es) if es.is_empty() => self.line(&format!("{d} = 1;")),
+            Rhs::Tuple(es) => {
+                let size = object_size(es.len() + 1);
+                self.line(&format!("snek_alloc({size});"));
+                self.line(&format!("snek_heap[0] = {};", es.len() << 1));
+                for (i, e) in (1..).zip(es) {
+                    let e = self.val(e);
+                    self.line(&format!("snek_heap[{i}] = {e};"));
+                }
+                self.line(&format!("{d} = (int64_t)(intptr_t)snek_heap + {TUPLE_TAG};"));
+                self.line(&format!("snek_heap += {};", size / 8));
+            },
+            Rhs::Get(_, obj, idx) => {
+                let (obj, idx) = (self.val(obj), self.val(idx));
+                self.line(&format!("{d} = ADDR({obj})[({idx} >> 1) + 1];"));
+            },
+            Rhs::Set(_, obj, idx, v) => {
+                let (obj, idx, v) = (self.val(obj), self.val(idx), self.val(v));
+                self.line(&format!("ADDR({obj})[({idx} >> 1) + 1] = {v};"));
+                self.line(&format!("{d} = {v};"));
+            },
+            // a header, the code address, the arity, then the captured values
+            Rhs::Closure(label, arity, es) => {
+                let size = object_size(es.len() + 3);
+                self.line(&format!("snek_alloc({size});"));
+                self.line(&format!("snek_heap[0] = {};", (es.len() + 2) << 1));
+                self.line(&format!("snek_heap[1] = (int64_t)(intptr_t){label};"));
+                self.line(&format!("snek_heap[2] = {};", arity << 1));
+                for (i, e) in (3..).zip(es) {
+                    let e = self.val(e);
+                    self.line(&format!("snek_heap[{i}] = {e};"));
+                }
+                self.line(&format!("{d} = (int64_t)(intptr_t)snek_heap + {CLOSURE_TAG};"));
+                self.line(&format!("snek_heap += {};", size / 8));
+            },
+            // top-level functions ignore their closure
+            Rhs::Call(label, args) => {
+                self.store_args("0", args);
+                self.line(&format!("{d} = {label}(snek_sp);"));
+                self.line("snek_sp += ARGS_WORDS;");
+            },
+            Rhs::Apply(f, args) => {
+                let f = self.val(f);
+                self.check_arity(&f, args.len());
+                self.store_args(&f, args);
+                self.line(&format!("{d} = ((snek_fn)(intptr_t)ADDR(snek_sp[0])[1])(snek_sp);"));
+                self.line("snek_sp += ARGS_WORDS;");
+            },
+        }
+    }
+
+    // Pushes the closure and the arguments of a call, zero-filled up to the
+    // most any function takes, so that a tail call can reuse them whatever the
+    // arity of either function.
+    fn store_args(&mut self, closure: &str, args: &[Operand]) {
+        self.line("snek_sp -= ARGS_WORDS;");
+        self.line(&format!("snek_sp[0] = {closure};"));
+        for (i, a) in (1..).zip(args) {
+            let a = self.val(a);
+            self.line(&format!("snek_sp[{i}] = {a};"));
+        }
+        let n = args.len() + 1;
+        self.line(&format!("memset(snek_sp + {n}, 0, (ARGS_WORDS - {n}) * 8);"));
+    }
+
+    fn check_arity(&mut self, f: &str, n: usize) {
+        self.line(&format!("if (ADDR({f})[2] != {}) snek_error(5);", n << 1));
+    }
+
+    fn prim1(&mut self, d: &str, o: &Op1, a: &Operand) {
+        let a = self.val(a);
+        let s = match o {
+            Op1::Add1 => format!("if (__builtin_add_overflow({a}, 2, &{d})) snek_error(2);"),
+            Op1::Sub1 => format!("if (__builtin_sub_overflow({a}, 2, &{d})) snek_error(2);"),
+            Op1::IsNum => format!("{d} = ({a} & 1) == 0 ? 7 : 3;"),
+            Op1::IsBool => format!("{d} = ({a} & 3) == 3 ? 7 : 3;"),
+            Op1::IsTuple => format!("{d} = ({a} & 15) == {TUPLE_TAG} ? 7 : 3;"),
+            Op1::IsFun => format!("{d} = ({a} & 15) == {CLOSURE_TAG} ? 7 : 3;"),
+            Op1::IsString => format!("{d} = ({a} & 15) == {STRING_TAG} ? 7 : 3;"),
+            // the header is the length as a snek number
+            Op1::VecLen => format!("{d} = ADDR({a})[0];"),
+            // the header is the byte count shifted left, with the low bit set
+            Op1::StringLength => format!("{d} = ADDR({a})[0] & -2;"),
+            Op1::Print => format!("{d} = snek_print({a});"),
+        };
+        self.line(&s);
+    }
+
+    fn prim2(&mut self, d: &str, o: &Op2, a: &Operand, b: &Operand) {
+        let (a, b) = (self.val(a), self.val(b));
+        let s = match o {
+            Op2::Plus => format!("if (__builtin_add_overflow({a}, {b}, &{d})) snek_error(2);"),
+            Op2::Minus => format!("if (__builtin_sub_overflow({a}, {b}, &{d})) snek_error(2);"),
+            Op2::Times => format!("if (__builtin_mul_overflow({a} >> 1, {b}, &{d})) snek_error(2);"),
+            Op2::Equal => format!("{d} = {a} == {b} ? 7 : 3;"),
+            Op2::Less => format!("{d} = {a} < {b} ? 7 : 3;"),
+            Op2::LessEqual => format!("{d} = {a} <= {b} ? 7 : 3;"),
+            Op2::Greater => format!("{d} = {a} > {b} ? 7 : 3;"),
+            Op2::GreaterEqual => format!("{d} = {a} >= {b} ? 7 : 3;"),
+            Op2::StEq => format!("{d} = snek_structural_eq_true({a}, {b});"),
+            // The operands of an allocation are on the shadow stack, where the
+            // collector updates them, so they are read again after it.
+            Op2::StringAppend => {
+                self.line("{");
+                self.line(&format!("    int64_t size = (((ADDR({a})[0] >> 1) + (ADDR({b})[0] >> 1)) + 8 + 15) & -16;"));
+                self.line("    snek_alloc(size);");
+                self.line(&format!("    {d} = snek_string_append(snek_heap, {a}, {b});"));
+                self.line("    snek_heap += size / 8;");
+                "}".to_string()
+            },
+            // `(string-ref s i)` is the one-byte string at index `i` of `s`
+            Op2::StringRef => {
+                self.line(&format!("if ((uint64_t){b} >= (uint64_t)(ADDR({a})[0] - 1)) snek_error(3);"));
+                self.line(&format!("snek_alloc({});", object_size(2)));
+                self.line(&format!("{d} = snek_string_ref(snek_heap, {a}, {b});"));
+                format!("snek_heap += {};", object_size(2) / 8)
+            },
+            // `(make-vec n init)` allocates a vector of `n` copies of `init`; a
+            // negative length is an invalid argument, and one that cannot fit
+            // in the heap is out of memory before the size overflows
+            Op2::MakeVec => {
+                self.line(&format!("if ({a} < 0) snek_error(1);"));
+                self.line(&format!("if ({a} > INT32_MAX) snek_error(4);"));
+                self.line("{");
+                self.line(&format!("    int64_t size = (4 * {a} + 8 + 15) & -16;"));
+                self.line("    snek_alloc(size);");
+                self.line(&format!("    snek_heap[0] = {a};"));
+                self.line(&format!("    for (int64_t i = 1; i <= {a} >> 1; i++) snek_heap[i] = {b};"));
+                self.line(&format!("    {d} = (int64_t)(intptr_t)snek_heap + {VEC_TAG};"));
+                self.line("    snek_heap += size / 8;");
+                "}".to_string()
+            },
+        };
+        self.line(&s);
+    }
+
+    // Moves the arguments of a tail call over the current function's
+    // arguments, through temporaries, as they may be read from there.
+    fn move_tail_args(&mut self, args: &[Operand]) {
+        for (i, a) in args.iter().enumerate() {
+            let a = self.val(a);
+            self.line(&format!("int64_t t{i} = {a};"));
+        }
+        for i in 0..args.len() {
+            self.line(&format!("args[{}] = t{i};", i + 1));
+        }
+    }
+
+    fn goto(&mut self, cond: &str, l: &str) {
+        self.line(&format!("{cond}goto {l};"));
+        self.targets.insert(l.to_string());
+    }
+
+    // `next` is the label of the block that follows, which needs no jump.
+    fn term(&mut self, t: &Term, next: Option<&String>) {
+        match t {
+            Term::Jump(l) if Some(l) == next => {},
+            Term::Jump(l) => self.goto("", l),
+            Term::Branch(a, thn, els) => {
+                let a = self.val(a);
+                if Some(els) == next {
+                    self.goto(&format!("if ({a} != 3) "), thn);
+                } else {
+                    self.goto(&format!("if ({a} == 3) "), els);
+                    if Some(thn) != next {
+                        self.goto("", thn);
+                    }
+                }
+            },
+            Term::Return(a) => {
+                let a = self.val(a);
+                self.line("snek_sp = fp;");
+                self.line(&format!("return {a};"));
+            },
+            // Tail calls reuse the caller's arguments, and become jumps when
+            // the C compiler optimizes sibling calls.
+            Term::TailCall(label, args) => {
+                self.line("{");
+                self.move_tail_args(args);
+                self.line("snek_sp = fp;");
+                self.line(&format!("return {label}(args);"));
+                self.line("}");
+            },
+            Term::TailApply(f, args) => {
+                let f = self.val(f);
+                self.check_arity(&f, args.len());
+                self.line("{");
+                self.line(&format!("int64_t closure = {f};"));
+                self.move_tail_args(args);
+                self.line("args[0] = closure;");
+                self.line("snek_sp = fp;");
+                self.line("return ((snek_fn)(intptr_t)ADDR(closure)[1])(args);");
+                self.line("}");
+            },
+        }
+    }
+}
+
+fn prototype(f: &ir::Fun) -> String {
+    format!("static int64_t {}(int64_t *args)", f.name)
+}
+
+// The captured values stored in the closure are copied out on entry.
+fn compile_fun(f: &ir::Fun, regs: &[usize], out: &mut String) {
+    let (locs, slots) = allocate(f, regs);
+    writeln!(out, "{} {{", prototype(f)).unwrap();
+    let used = regs.iter().filter(|r| locs.values().any(|l| *l == Loc::Reg(**r)));
+    for r in used {
+        writeln!(out, "    int64_t r{r};").unwrap();
+    }
+    writeln!(out, "    int64_t *fp = snek_enter({slots});").unwrap();
+    let mut g = FunGen { locs, out: String::new(), targets: HashSet::new() };
+    for (i, v) in (3..).zip(&f.captured) {
+        let v = g.var(v);
+        g.line(&format!("{v} = ADDR(args[0])[{i}];"));
+    }
+    out.push_str(&std::mem::take(&mut g.out));
+    let mut blocks = vec![];
+    for (i, b) in f.blocks.iter().enumerate() {
+        for s in &b.stmts {
+            g.stmt(s);
+        }
+        g.term(&b.term, f.blocks.get(i + 1).map(|b| &b.label));
+        blocks.push(std::mem::take(&mut g.out));
+    }
+    for (b, code) in f.blocks.iter().zip(blocks) {
+        if g.targets.contains(&b.label) {
+            writeln!(out, "{}:", b.label).unwrap();
+        }
+        out.push_str(&code);
+    }
+    writeln!(out, "}}\n").unwrap();
+}
+
+/// Generates the C source for a program. With `regalloc` unset, every variable
+/// is kept on the shadow stack.
+pub fn compile(p: &ir::Prog, regalloc: bool) -> String {
+    let regs: &[usize] = if regalloc { &ALLOC_REGS } else { &[] };
+    let mut out = String::new();
+
+    // every call reserves room for the closure and the most arguments
+    writeln!(out, "#define ARGS_WORDS {}\n", max_args(p) + 1).unwrap();
+    out.push_str(PRELUDE);
+    out.push('\n');
+    for f in p.funs.iter().chain([&p.main]) {
+        writeln!(out, "{};", prototype(f)).unwrap();
+    }
+    out.push('\n');
+    for f in p.funs.iter().chain([&p.main]) {
+        compile_fun(f, regs, &mut out);
+    }
+
+    // our_code_starts_here(input, heap start, heap end)
+    writeln!(out, "int64_t {ENTRY_LABEL}(int64_t input, int64_t *heap, int64_t *heap_end) {{").unwrap();
+    out.push_str("    snek_input = input;\n");
+    out.push_str("    snek_heap = heap;\n");
+    out.push_str("    snek_heap_end = heap_end;\n");
+    writeln!(out, "    return {}(snek_sp);", ir::MAIN_LABEL).unwrap();
+    out.push_str("}\n");
+    out
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use crate::ast::*;
+use crate::error::{CompileError, Span};
+
+struct Env<'a> {
+    vars: im::HashSet<String>,
+    fnames: &'a HashMap<String, usize>,
+    in_loop: bool,
+}
+
+fn check_params(params: &[String], span: Span, what: &str, errs: &mut Vec<CompileError>) -> im::HashSet<String> {
+    let mut vars = im::HashSet::new();
+    for a in params {
+        if !a.is_empty() && vars.insert(a.to_string()).is_some() {
+            errs.push(CompileError::new(span, format!("Invalid: Duplicate arguments in {what}")));
+        }
+    }
+    vars
+}
+
+fn check_expr(e: &Expr, env: &Env, errs: &mut Vec<CompileError>) {
+    match &e.kind {
+        ExprKind::Number(_) | ExprKind::Boolean(_) | ExprKind::Str(_) => {},
+        ExprKind::Id(id) => {
+            if !env.vars.contains(id) && !env.fnames.contains_key(id) {
+                errs.push(CompileError::new(e.span, format!("Unbound variable identifier {id}")));
+            }
+        },
+        ExprKind::Let(bs, body) => {
+            let mut ids = HashSet::new();
+            let mut vars = env.vars.clone();
+            for (id, ee) in bs {
+                if !ids.insert(id) {
+                    errs.push(CompileError::new(e.span, format!("Duplicate binding {id}")));
+                }
+                check_expr(ee, &Env { vars: vars.clone(), ..*env }, errs);
+                vars.insert(id.to_string());
+            }
+            check_expr(body, &Env { vars, ..*env }, errs);
+        },
+        ExprKind::Set(id, e1) => {
+            if id == "input" && env.vars.contains(id) {
+                errs.push(CompileError::new(e.span, "Invalid: cannot assign to input"));
+            } else if !env.vars.contains(id) {
+                errs.push(CompileError::new(e.span, format!("Unbound variable identifier {id}")));
+            }
+            check_expr(e1, env, errs);
+        },
+        ExprKind::Loop(e1) => check_expr(e1, &Env { vars: env.vars.clone(), in_loop: true, ..*env }, errs),
+        ExprKind::Break(e1) => {
+            if !env.in_loop {
+                errs.push(CompileError::new(e.span, "Invalid: break outside of loop"));
+            }
+            check_expr(e1, env, errs);
+        },
+        ExprKind::Call(n, args) => {
+            match env.fnames.get(n) {
+                // the input is a number or a boolean, never a function
+                _ if n == "input" => errs.push(CompileError::new(e.span, "Invalid: cannot call input")),
+                // a local variable shadows the function and is called indirectly
+                _ if env.vars.contains(n) => {},
+                Some(x) if *x != args.len() => {
+                    errs.push(CompileError::new(e.span, format!("Invalid: {} takes {} arguments but {} were given", n, x, args.len())));
+                },
+                Some(_) => {},
+                None => errs.push(CompileError::new(e.span, format!("Invalid: Function {n} undefined"))),
+            }
+            for a in args {
+                check_expr(a, env, errs);
+            }
+        },
+        ExprKind::Apply(f, args) => {
+            check_expr(f, env, errs);
+            for a in args {
+                check_expr(a, env, errs);
+            }
+        },
+        ExprKind::Lambda(params, body) => {
+            let params = check_params(params, e.span, "lambda", errs);
+            let vars = env.vars.clone().union(params);
+            check_expr(body, &Env { vars, fnames: env.fnames, in_loop: false }, errs);
+        },
+        ExprKind::UnOp(_, e1) => check_expr(e1, env, errs),
+        ExprKind::BinOp(_, e1, e2) | ExprKind::TupleGet(e1, e2) | ExprKind::VecGet(e1, e2) => {
+            check_expr(e1, env, errs);
+            check_expr(e2, env, errs);
+        },
+        ExprKind::If(e1, e2, e3) | ExprKind::TupleSet(e1, e2, e3) | ExprKind::VecSet(e1, e2, e3) => {
+            check_expr(e1, env, errs);
+            check_expr(e2, env, errs);
+            check_expr(e3, env, errs);
+        },
+        ExprKind::Block(es) | ExprKind::Tuple(es) => {
+            for e1 in es {
+                check_expr(e1, env, errs);
+            }
+        },
+    }
+}
+
+/// Checks that a program is well-formed, collecting every error instead of
+/// stopping at the first one.
+pub fn check_prog(p: &Prog, errs: &mut Vec<CompileError>) {
+    check_in(p, &HashMap::new(), im::HashSet::new(), errs);
+}
+
+/// Checks a program that may also call the functions in `known`, from their
+/// names to their arities, and whose main expression may use `globals`. Its own
+/// definitions replace known functions of the same name.
+pub fn check_in(p: &Prog, known: &HashMap<String, usize>, globals: im::HashSet<String>, errs: &mut Vec<CompileError>) {
+    let Prog(fs, e) = p;
+
+    let mut fnames = known.clone();
+    let mut defined = HashSet::new();
+    for f in fs {
+        if !defined.insert(&f.name) {
+            errs.push(CompileError::new(f.span, format!("Invalid: Function {} defined multiple times", f.name)));
+        } else {
+            fnames.insert(f.name.to_string(), f.args.len());
+        }
+    }
+
+    for f in fs {
+        let vars = check_params(&f.args, f.span, &format!("function {}", f.name), errs).update("input".to_string());
+        check_expr(&f.expr, &Env { vars, fnames: &fnames, in_loop: false }, errs);
+    }
+
+    let vars = globals.update("input".to_string());
+    check_expr(e, &Env { vars, fnames: &fnames, in_loop: false }, errs);
+}
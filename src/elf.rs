@@ -0,0 +1,161 @@
+use crate::encode::{self, RelocKind};
+use crate::ir;
+use crate::x86::{self, ENTRY_LABEL, INPUT_LABEL, RUNTIME_FUNS};
+
+// Writes programs as relocatable ELF64 objects, to be linked with the runtime
+// as the assembler's output would be. The object has the code in `.text`, the
+// input word in `.data`, and a symbol for the entry point and for each runtime
+// function, which the relocations in `.rela.text` refer to.
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+const SHF_WRITE: u64 = 1;
+const SHF_ALLOC: u64 = 2;
+const SHF_EXECINSTR: u64 = 4;
+const SHF_INFO_LINK: u64 = 0x40;
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_FUNC: u8 = 2;
+const STT_SECTION: u8 = 3;
+const R_X86_64_PC32: u64 = 2;
+const R_X86_64_PLT32: u64 = 4;
+
+// The sections, in order after the null one.
+const TEXT: u16 = 1;
+const DATA: u16 = 2;
+const SYMTAB: u32 = 3;
+const STRTAB: u32 = 4;
+const SHSTRTAB: u16 = 6;
+
+// A string table, which starts with the empty name.
+struct Strtab(Vec<u8>);
+
+impl Strtab {
+    fn add(&mut self, s: &str) -> u32 {
+        let at = self.0.len() as u32;
+        self.0.extend(s.as_bytes());
+        self.0.push(0);
+        at
+    }
+}
+
+struct Section {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    data: Vec<u8>,
+    link: u32,
+    info: u32,
+    align: u64,
+    entsize: u64,
+}
+
+fn symbol(out: &mut Vec<u8>, name: u32, bind: u8, kind: u8, section: u16, value: u64) {
+    out.extend(name.to_le_bytes());
+    out.push(bind << 4 | kind);
+    out.push(0);
+    out.extend(section.to_le_bytes());
+    out.extend(value.to_le_bytes());
+    out.extend(0u64.to_le_bytes());
+}
+
+/// Generates a relocatable object file for a program. With `regalloc` unset,
+/// every variable is kept in the frame.
+pub fn compile(p: &ir::Prog, regalloc: bool) -> Vec<u8> {
+    let code = encode::encode(&x86::generate(p, regalloc));
+
+    // the section symbols are local, and come before the global symbols
+    let mut strtab = Strtab(vec![0]);
+    let mut symtab = vec![0; 24];
+    symbol(&mut symtab, 0, STB_LOCAL, STT_SECTION, TEXT, 0);
+    symbol(&mut symtab, 0, STB_LOCAL, STT_SECTION, DATA, 0);
+    let data_sym = 2;
+    let first_global = 3;
+    let name = strtab.add(ENTRY_LABEL);
+    symbol(&mut symtab, name, STB_GLOBAL, STT_FUNC, TEXT, code.labels[ENTRY_LABEL] as u64);
+    for f in RUNTIME_FUNS {
+        let name = strtab.add(f);
+        symbol(&mut symtab, name, STB_GLOBAL, STT_NOTYPE, 0, 0);
+    }
+    let fun_sym = |f: &str| first_global + 1 + RUNTIME_FUNS.iter().position(|g| *g == f).unwrap_or_else(|| panic!("undefined label {f}")) as u64;
+
+    let mut rela = Vec::new();
+    for r in &code.relocs {
+        let (sym, kind) = match r.kind {
+            // the input is the first word of `.data`
+            RelocKind::Data if r.symbol == INPUT_LABEL => (data_sym, R_X86_64_PC32),
+            RelocKind::Data => panic!("undefined label {}", r.symbol),
+            RelocKind::Call => (fun_sym(&r.symbol), R_X86_64_PLT32),
+        };
+        rela.extend((r.offset as u64).to_le_bytes());
+        rela.extend((sym << 32 | kind).to_le_bytes());
+        rela.extend(r.addend.to_le_bytes());
+    }
+
+    let mut shstrtab = Strtab(vec![0]);
+    let names: Vec<u32> = [".text", ".data", ".symtab", ".strtab", ".rela.text", ".shstrtab", ".note.GNU-stack"].iter().map(|n| shstrtab.add(n)).collect();
+    let section = |i: usize, kind, flags, data, align| Section { name: names[i], kind, flags, data, link: 0, info: 0, align, entsize: 0 };
+    let sections = [
+        section(0, SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR, code.bytes, 16),
+        section(1, SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, vec![0; 8], 8),
+        Section { link: STRTAB, info: first_global as u32, entsize: 24, ..section(2, SHT_SYMTAB, 0, symtab, 8) },
+        section(3, SHT_STRTAB, 0, strtab.0, 1),
+        Section { link: SYMTAB, info: TEXT as u32, entsize: 24, ..section(4, SHT_RELA, SHF_INFO_LINK, rela, 8) },
+        section(5, SHT_STRTAB, 0, shstrtab.0, 1),
+        // marks the stack as not executable
+        section(6, SHT_PROGBITS, 0, Vec::new(), 1),
+    ];
+
+    // the header, then each section's contents, then the section headers
+    let mut out = vec![0; 64];
+    let mut offsets = Vec::new();
+    for s in &sections {
+        while out.len() as u64 % s.align != 0 {
+            out.push(0);
+        }
+        offsets.push(out.len() as u64);
+        out.extend(&s.data);
+    }
+    while out.len() % 8 != 0 {
+        out.push(0);
+    }
+    let shoff = out.len() as u64;
+    out.extend([0; 64]);
+    for (s, offset) in sections.iter().zip(offsets) {
+        out.extend(s.name.to_le_bytes());
+        out.extend(s.kind.to_le_bytes());
+        out.extend(s.flags.to_le_bytes());
+        out.extend(0u64.to_le_bytes());
+        out.extend(offset.to_le_bytes());
+        out.extend((s.data.len() as u64).to_le_bytes());
+        out.extend(s.link.to_le_bytes());
+        out.extend(s.info.to_le_bytes());
+        out.extend(s.align.to_le_bytes());
+        out.extend(s.entsize.to_le_bytes());
+    }
+
+    let mut header = Vec::new();
+    // 64-bit, little-endian, version 1, System V
+    header.extend(b"\x7fELF\x02\x01\x01\x00");
+    header.extend([0; 8]);
+    // a relocatable file for x86-64
+    header.extend(1u16.to_le_bytes());
+    header.extend(62u16.to_le_bytes());
+    header.extend(1u32.to_le_bytes());
+    // no entry point and no program headers
+    header.extend(0u64.to_le_bytes());
+    header.extend(0u64.to_le_bytes());
+    header.extend(shoff.to_le_bytes());
+    header.extend(0u32.to_le_bytes());
+    header.extend(64u16.to_le_bytes());
+    header.extend(0u16.to_le_bytes());
+    header.extend(0u16.to_le_bytes());
+    header.extend(64u16.to_le_bytes());
+    header.extend((sections.len() as u16 + 1).to_le_bytes());
+    header.extend(SHSTRTAB.to_le_bytes());
+    out[..64].copy_from_slice(&header);
+    out
+}
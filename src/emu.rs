@@ -0,0 +1,526 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Mutex;
+
+use crate::runtime::{self, HEAP_START};
+use crate::x86::{Instr, Reg, Val, CALLEE_SAVED, ENTRY_LABEL, INPUT_LABEL};
+
+// Executes the instructions generated by `x86::generate` without assembling
+// them, so that code generation can be tested in process. Only the subset of
+// x86-64 the compiler emits is understood, all of it on 64-bit operands.
+//
+// The stack and heap are real memory, so calls into the runtime go to its Rust
+// functions with the values in the argument registers, as compiled code would
+// make them, and the collector scans the emulated stack as it scans the real
+// one. Code addresses are indices into the instructions. Every memory access
+// is checked to fall in the stack, the heap or the input word, and a call into
+// the runtime must find the stack aligned and leaves the registers it may
+// clobber holding garbage, so that code relying on what the ABI does not
+// promise fails here too. The entry must give back the registers its caller
+// expects preserved.
+
+const STACK_WORDS: usize = 1 << 20;
+
+// What the registers a runtime call may clobber hold after one. It is not a
+// snek value, and as an address it is neither aligned nor mapped.
+const CLOBBERED: i64 = 0xdead_beef_dead_beef_u64 as i64;
+
+const CALLER_SAVED: [Reg; 8] = [Reg::RCX, Reg::RDX, Reg::RSI, Reg::RDI, Reg::R8, Reg::R9, Reg::R10, Reg::R11];
+
+// The collector finds the heap through `HEAP_START`, so one program runs at a
+// time.
+static RUNNING: Mutex<()> = Mutex::new(());
+
+struct Emulator<'i> {
+    instrs: &'i [Instr<'i>],
+    labels: HashMap<&'i str, usize>,
+    regs: [i64; 16],
+    // zero, sign, overflow and carry
+    zf: bool,
+    sf: bool,
+    of: bool,
+    cf: bool,
+    stack: Vec<u64>,
+    heap: Range<usize>,
+    input: Box<u64>,
+    out: String,
+}
+
+impl<'i> Emulator<'i> {
+    fn reg(&self, r: Reg) -> i64 {
+        self.regs[r as usize]
+    }
+
+    fn set_reg(&mut self, r: Reg, x: i64) {
+        self.regs[r as usize] = x;
+    }
+
+    fn label(&self, l: &str) -> usize {
+        *self.labels.get(l).unwrap_or_else(|| panic!("undefined label {l}"))
+    }
+
+    // The instruction at code address `a`, which must be a label.
+    fn code_at(&self, a: i64) -> usize {
+        match self.instrs.get(a as usize) {
+            Some(Instr::Label(_)) if a >= 0 => a as usize,
+            _ => panic!("jump to {a:#x}, which is not code"),
+        }
+    }
+
+    fn address(&self, v: &Val) -> i64 {
+        match v {
+            Val::RegOffset(r, d) => self.reg(*r).wrapping_add(*d as i64),
+            Val::EffectiveAddr(b, i, s, d) => {
+                self.reg(*b).wrapping_add(self.reg(*i).wrapping_mul(*s as i64)).wrapping_add(*d as i64)
+            },
+            Val::RelLabel(l) if l == INPUT_LABEL => &*self.input as *const u64 as i64,
+            Val::RelLabel(l) => panic!("{l} is not data"),
+            v => panic!("{v:?} is not a memory operand"),
+        }
+    }
+
+    fn check(&self, a: i64) -> *mut u64 {
+        let a = a as usize;
+        let stack = self.stack.as_ptr_range();
+        let valid = (stack.start as usize..stack.end as usize).contains(&a)
+            || self.heap.contains(&a)
+            || a == &*self.input as *const u64 as usize;
+        if !valid || a % 8 != 0 {
+            panic!("access to {a:#x}, outside the stack, heap and input");
+        }
+        a as *mut u64
+    }
+
+    fn load(&self, a: i64) -> i64 {
+        unsafe { *self.check(a) as i64 }
+    }
+
+    fn store(&mut self, a: i64, x: i64) {
+        unsafe { *self.check(a) = x as u64 }
+    }
+
+    fn get(&self, v: &Val) -> i64 {
+        match v {
+            Val::Reg(r) => self.reg(*r),
+            Val::Imm32(n) => *n as i64,
+            Val::Imm64(n) => *n,
+            v => self.load(self.address(v)),
+        }
+    }
+
+    fn set(&mut self, v: &Val, x: i64) {
+        match v {
+            Val::Reg(r) => self.set_reg(*r, x),
+            Val::Imm32(_) | Val::Imm64(_) => panic!("cannot write to {v:?}"),
+            v => self.store(self.address(v), x),
+        }
+    }
+
+    fn push(&mut self, x: i64) {
+        let sp = self.reg(Reg::RSP) - 8;
+        self.set_reg(Reg::RSP, sp);
+        self.store(sp, x);
+    }
+
+    fn pop(&mut self) -> i64 {
+        let sp = self.reg(Reg::RSP);
+        self.set_reg(Reg::RSP, sp + 8);
+        self.load(sp)
+    }
+
+    // Writes the result of an arithmetic or logic instruction and sets the
+    // sign and zero flags from it.
+    fn result(&mut self, dst: &Val, x: i64, of: bool, cf: bool) {
+        self.set(dst, x);
+        (self.zf, self.sf, self.of, self.cf) = (x == 0, x < 0, of, cf);
+    }
+
+    fn cond(&self, c: &str) -> bool {
+        match c {
+            "" => true,
+            "e" => self.zf,
+            "ne" => !self.zf,
+            "l" => self.sf != self.of,
+            "le" => self.zf || self.sf != self.of,
+            "g" => !self.zf && self.sf == self.of,
+            "ge" => self.sf == self.of,
+            "o" => self.of,
+            "no" => !self.of,
+            "b" => self.cf,
+            "be" => self.cf || self.zf,
+            "a" => !self.cf && !self.zf,
+            "ae" => !self.cf,
+            _ => panic!("unknown condition {c}"),
+        }
+    }
+
+    // Calls runtime function `f`, or returns the code of the error it reports.
+    fn call_runtime(&mut self, f: &str) -> Result<(), i64> {
+        if self.reg(Reg::RSP) % 16 != 0 {
+            panic!("stack not aligned at call to {f}");
+        }
+        let (a, b, c, d) = (self.reg(Reg::RDI), self.reg(Reg::RSI), self.reg(Reg::RDX), self.reg(Reg::RCX));
+        let v = unsafe {
+            match f {
+                "snek_error" => return Err(a),
+                "snek_print" => {
+                    self.out.push_str(&runtime::snek_to_string(a));
+                    self.out.push('\n');
+                    a
+                },
+                "snek_structural_eq_true" => runtime::snek_structural_eq_true(a, b),
+                "snek_gc" => runtime::snek_gc(a as *mut u64, b as u64, c as *mut u64, d as *const u64) as i64,
+                "snek_string_append" => runtime::snek_string_append(self.check(a), b, c),
+                "snek_string_ref" => runtime::snek_string_ref(self.check(a), b, c),
+                _ => unreachable!("{f} is not a runtime function"),
+            }
+        };
+        for r in CALLER_SAVED {
+            self.set_reg(r, CLOBBERED);
+        }
+        self.set_reg(Reg::RAX, v);
+        Ok(())
+    }
+
+    // Runs from `pc` until the return to address `instrs.len()`, giving RAX,
+    // or until the code reports an error.
+    fn exec(&mut self, mut pc: usize) -> Result<i64, i64> {
+        let instrs = self.instrs;
+        while pc < instrs.len() {
+            let i = &instrs[pc];
+            pc += 1;
+            match i {
+                Instr::Mov(dst, src) => self.set(dst, self.get(src)),
+                Instr::Add(dst, src) => {
+                    let (a, b) = (self.get(dst), self.get(src));
+                    self.result(dst, a.wrapping_add(b), a.checked_add(b).is_none(), (a as u64).overflowing_add(b as u64).1);
+                },
+                Instr::Sub(dst, src) => {
+                    let (a, b) = (self.get(dst), self.get(src));
+                    self.result(dst, a.wrapping_sub(b), a.checked_sub(b).is_none(), (a as u64) < (b as u64));
+                },
+                Instr::Imul(dst, src) => {
+                    let (a, b) = (self.get(dst), self.get(src));
+                    let overflow = a.checked_mul(b).is_none();
+                    self.result(dst, a.wrapping_mul(b), overflow, overflow);
+                },
+                Instr::And(dst, src) => self.result(dst, self.get(dst) & self.get(src), false, false),
+                Instr::Xor(dst, src) => self.result(dst, self.get(dst) ^ self.get(src), false, false),
+                Instr::Sar(dst, src) => {
+                    let (a, n) = (self.get(dst), self.get(src) & 63);
+                    if n == 0 {
+                        continue;
+                    }
+                    self.result(dst, a >> n, false, (a >> (n - 1)) & 1 == 1);
+                },
+                Instr::Cmp(a, b) => {
+                    let (a, b) = (self.get(a), self.get(b));
+                    let x = a.wrapping_sub(b);
+                    (self.zf, self.sf, self.of, self.cf) = (x == 0, x < 0, a.checked_sub(b).is_none(), (a as u64) < (b as u64));
+                },
+                Instr::Test(a, b) => {
+                    let x = self.get(a) & self.get(b);
+                    (self.zf, self.sf, self.of, self.cf) = (x == 0, x < 0, false, false);
+                },
+                Instr::Push(v) => {
+                    let x = self.get(v);
+                    self.push(x);
+                },
+                Instr::Pop(v) => {
+                    let x = self.pop();
+                    self.set(v, x);
+                },
+                Instr::Call(f) if runtime_fun(f) => self.call_runtime(f)?,
+                Instr::Call(l) => {
+                    self.push(pc as i64);
+                    pc = self.label(l);
+                },
+                Instr::CallInd(v) => {
+                    let target = self.code_at(self.get(v));
+                    self.push(pc as i64);
+                    pc = target;
+                },
+                Instr::JmpInd(v) => pc = self.code_at(self.get(v)),
+                Instr::Leave => {
+                    self.set_reg(Reg::RSP, self.reg(Reg::RBP));
+                    let bp = self.pop();
+                    self.set_reg(Reg::RBP, bp);
+                },
+                Instr::Ret => pc = self.pop() as usize,
+                Instr::J(c, l) => {
+                    if self.cond(c) {
+                        pc = self.label(l);
+                    }
+                },
+                Instr::Cmov(c, dst, src) => {
+                    if self.cond(c) {
+                        self.set(dst, self.get(src));
+                    }
+                },
+                Instr::Lea(dst, Val::RelLabel(l)) if l != INPUT_LABEL => self.set(dst, self.label(l) as i64),
+                Instr::Lea(dst, src) => self.set(dst, self.address(src)),
+                Instr::Label(_) => {},
+            }
+        }
+        Ok(self.reg(Reg::RAX))
+    }
+}
+
+fn runtime_fun(f: &str) -> bool {
+    crate::x86::RUNTIME_FUNS.contains(&f)
+}
+
+/// Runs the instructions of a program on `input` with a heap of `heap_size`
+/// bytes, as the runtime would run them once assembled. Gives what the program
+/// prints, its result last, or the message of the error it stops with.
+///
+/// Code that the processor would fault on, or that breaks the calling
+/// convention, makes this panic.
+pub fn run(instrs: &[Instr], input: i64, heap_size: usize) -> Result<String, String> {
+    let _running = RUNNING.lock().unwrap_or_else(|e| e.into_inner());
+    let heap_words = heap_size / 8;
+    // one spare word to align the heap to 16 bytes
+    let mut memory = vec![0u64; heap_words + 1];
+    let buffer = memory.as_mut_ptr();
+    let buffer = unsafe { buffer.add(buffer as usize / 8 % 2) };
+    unsafe { HEAP_START = buffer };
+
+    let labels = instrs.iter().enumerate().filter_map(|(i, instr)| match instr {
+        Instr::Label(l) => Some((l.as_str(), i)),
+        _ => None,
+    }).collect();
+    let mut emu = Emulator {
+        instrs,
+        labels,
+        regs: [0; 16],
+        zf: false,
+        sf: false,
+        of: false,
+        cf: false,
+        stack: vec![0; STACK_WORDS],
+        heap: buffer as usize..unsafe { buffer.add(heap_words) } as usize,
+        input: Box::new(0),
+        out: String::new(),
+    };
+
+    // our_code_starts_here(input, heap start, heap end), called with the stack
+    // aligned and returning past the last instruction
+    let top = unsafe { emu.stack.as_mut_ptr().add(STACK_WORDS) } as i64;
+    emu.set_reg(Reg::RSP, top);
+    emu.push(instrs.len() as i64);
+    emu.set_reg(Reg::RDI, input);
+    emu.set_reg(Reg::RSI, buffer as i64);
+    emu.set_reg(Reg::RDX, unsafe { buffer.add(heap_words) } as i64);
+    for (i, r) in (1..).zip(CALLEE_SAVED) {
+        emu.set_reg(r, CLOBBERED ^ i);
+    }
+    let entry = emu.label(ENTRY_LABEL);
+    match emu.exec(entry) {
+        Ok(v) => {
+            for (i, r) in (1..).zip(CALLEE_SAVED) {
+                if emu.reg(r) != CLOBBERED ^ i {
+                    panic!("{r:?} not preserved by {ENTRY_LABEL}");
+                }
+            }
+            emu.out.push_str(&runtime::snek_to_string(v));
+            emu.out.push('\n');
+            Ok(emu.out)
+        },
+        Err(code) => Err(runtime::error_message(code)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{check, infer, ir, parser, sexp, x86};
+
+    fn compile(src: &str, regalloc: bool) -> Vec<Instr<'static>> {
+        let mut errs = Vec::new();
+        let prog = parser::parse_prog(&sexp::parse(src, &mut errs), &mut errs);
+        check::check_prog(&prog, &mut errs);
+        assert!(errs.is_empty(), "{}", errs.iter().map(|e| e.render("test.snek", src)).collect::<String>());
+        let mut p = ir::lower_prog(&prog);
+        infer::remove_checks(&mut p);
+        x86::generate(&p, regalloc)
+    }
+
+    // Runs `src` on `input` both with and without register allocation, which
+    // must agree.
+    fn emulate(src: &str, input: &str, heap_size: usize) -> Result<String, String> {
+        let input = runtime::parse_input(input);
+        let out = run(&compile(src, true), input, heap_size);
+        assert_eq!(out, run(&compile(src, false), input, heap_size), "with and without register allocation");
+        out
+    }
+
+    fn output(src: &str, input: &str) -> String {
+        emulate(src, input, runtime::DEFAULT_HEAP_SIZE).unwrap_or_else(|e| panic!("an error ocurred {e}"))
+    }
+
+    fn error(src: &str, input: &str) -> String {
+        emulate(src, input, runtime::DEFAULT_HEAP_SIZE).expect_err("no error")
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(output("(+ (* 6 7) (- 3 5))", "false"), "40\n");
+        assert_eq!(output("(sub1 (add1 input))", "-12"), "-12\n");
+        assert_eq!(output("(let ((x 4611686018427387903)) (- 0 x))", "false"), "-4611686018427387903\n");
+    }
+
+    #[test]
+    fn comparisons() {
+        let src = "(tuple (< input 3) (<= input 3) (> input 3) (>= input 3) (= input 3))";
+        assert_eq!(output(src, "2"), "(true true false false false)\n");
+        assert_eq!(output(src, "3"), "(false true false true true)\n");
+        assert_eq!(output(src, "-4"), "(true true false false false)\n");
+        assert_eq!(output(src, "4611686018427387903"), "(false false true true false)\n");
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(error("(+ input 1)", "4611686018427387903"), "overflow");
+        assert_eq!(error("(- input 1)", "-4611686018427387904"), "overflow");
+        assert_eq!(error("(* input input)", "4294967296"), "overflow");
+        assert_eq!(error("(add1 input)", "4611686018427387903"), "overflow");
+    }
+
+    #[test]
+    fn invalid_argument() {
+        assert_eq!(error("(+ input 1)", "true"), "invalid argument");
+        assert_eq!(error("(if (< input true) 1 2)", "1"), "invalid argument");
+        assert_eq!(error("(tuple-get input 0)", "5"), "invalid argument");
+    }
+
+    #[test]
+    fn conditionals_and_loops() {
+        let src = "(let ((i 0) (acc 1)) (loop (if (= i input) (break acc) (block (set! acc (* acc 2)) (set! i (add1 i))))))";
+        assert_eq!(output(src, "10"), "1024\n");
+        assert_eq!(output("(if input 1 2)", "false"), "2\n");
+        assert_eq!(output("(if input 1 2)", "0"), "1\n");
+    }
+
+    #[test]
+    fn print() {
+        assert_eq!(output("(block (print input) (print (tuple 1 true (tuple))) 5)", "-3"), "-3\n(1 true ())\n5\n");
+    }
+
+    #[test]
+    fn calls() {
+        let src = "
+            (fun (fact n) (if (= n 0) 1 (* n (fact (sub1 n)))))
+            (fun (three a b c) (tuple c b a))
+            (tuple (fact input) (three 1 2 3))";
+        assert_eq!(output(src, "10"), "(3628800 (3 2 1))\n");
+    }
+
+    #[test]
+    fn deep_tail_calls() {
+        let src = "
+            (fun (count n acc) (if (= n 0) acc (count (sub1 n) (add1 acc))))
+            (count input 0)";
+        assert_eq!(output(src, "1000000"), "1000000\n");
+    }
+
+    #[test]
+    fn closures() {
+        let src = "
+            (fun (adder n) (lambda (x) (+ x n)))
+            (fun (twice f x) (f (f x)))
+            (twice (adder input) 1)";
+        assert_eq!(output(src, "20"), "41\n");
+        assert_eq!(error("((lambda (x) x) 1 2)", "false"), "wrong number of arguments");
+        assert_eq!(error("(let ((f input)) (f 1))", "1"), "invalid argument");
+    }
+
+    #[test]
+    fn tuples_and_vectors() {
+        let src = "(let ((t (tuple 1 2 3)) (v (make-vec 3 0))) (block (vec-set! v 1 (tuple-get t 2)) (tuple v (vec-len v) (vec-get v input))))";
+        assert_eq!(output(src, "1"), "([0 3 0] 3 3)\n");
+        assert_eq!(error(src, "3"), "index out of range");
+        assert_eq!(error(src, "-1"), "index out of range");
+        assert_eq!(output("(let ((t (tuple 1 2))) (block (tuple-set! t 0 t) t))", "false"), "((...) 2)\n");
+    }
+
+    #[test]
+    fn strings() {
+        let src = "(let ((s (string-append \"snek\" \"!\"))) (tuple (string-length s) (string-ref s input) (= s \"snek!\")))";
+        assert_eq!(output(src, "4"), "(5 ! true)\n");
+        assert_eq!(error(src, "5"), "index out of range");
+    }
+
+    #[test]
+    fn structural_equality() {
+        assert_eq!(output("(= (tuple 1 (tuple 2)) (tuple 1 (tuple input)))", "2"), "true\n");
+        assert_eq!(output("(= (tuple 1 (tuple 2)) (tuple 1 (tuple input)))", "3"), "false\n");
+    }
+
+    #[test]
+    fn garbage_collection() {
+        let src = "
+            (fun (churn n keep) (if (= n 0) keep (block (tuple n n n) (churn (sub1 n) keep))))
+            (churn input (tuple 1 2 3))";
+        assert_eq!(emulate(src, "100000", 4096), Ok("(1 2 3)\n".to_string()));
+        let grow = "(fun (grow n acc) (grow (add1 n) (tuple n acc))) (grow 0 (tuple))";
+        assert_eq!(emulate(grow, "false", 4096), Err("out of memory".to_string()));
+    }
+
+    #[test]
+    fn unsigned_conditions() {
+        let l = |s: &str| s.to_string();
+        let r = |r| Val::Reg(r);
+        let instrs = [
+            Instr::Label(l(ENTRY_LABEL)),
+            Instr::Mov(r(Reg::RAX), Val::Imm32(-1)),
+            Instr::Cmp(r(Reg::RAX), Val::Imm32(1)),
+            Instr::J("be", l("wrong")),
+            Instr::J("l", l("right")),
+            Instr::Label(l("wrong")),
+            Instr::Mov(r(Reg::RAX), Val::Imm32(3)),
+            Instr::Ret,
+            Instr::Label(l("right")),
+            Instr::Mov(r(Reg::RAX), Val::Imm32(7)),
+            Instr::Ret,
+        ];
+        assert_eq!(run(&instrs, 0, 0), Ok("true\n".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "not aligned")]
+    fn misaligned_runtime_call() {
+        let instrs = [
+            Instr::Label(ENTRY_LABEL.to_string()),
+            Instr::Call("snek_print".to_string()),
+            Instr::Ret,
+        ];
+        let _ = run(&instrs, 0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "RBX not preserved")]
+    fn callee_saved_registers() {
+        let instrs = [
+            Instr::Label(ENTRY_LABEL.to_string()),
+            Instr::Mov(Val::Reg(Reg::RBX), Val::Imm32(0)),
+            Instr::Mov(Val::Reg(Reg::RAX), Val::Imm32(2)),
+            Instr::Ret,
+        ];
+        let _ = run(&instrs, 0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "outside the stack, heap and input")]
+    fn clobbered_registers() {
+        let instrs = [
+            Instr::Label(ENTRY_LABEL.to_string()),
+            Instr::Push(Val::Reg(Reg::RBP)),
+            Instr::Mov(Val::Reg(Reg::RCX), Val::Reg(Reg::RSP)),
+            Instr::Call("snek_print".to_string()),
+            Instr::Mov(Val::Reg(Reg::RAX), Val::RegOffset(Reg::RCX, 0)),
+            Instr::Pop(Val::Reg(Reg::RBP)),
+            Instr::Ret,
+        ];
+        let _ = run(&instrs, 0, 0);
+    }
+}
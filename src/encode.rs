@@ -0,0 +1,321 @@
+use std::collections::HashMap;
+
+use crate::x86::{Instr, Reg, Val, INPUT_LABEL};
+
+// Encodes instructions as x86-64 machine code. References to labels defined
+// among the instructions are resolved here; the others, to the runtime's
+// functions and to the input slot, are left as relocations for whatever places
+// the code in memory or in an object file.
+
+/// What a relocation refers to: a function that is called, or data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocKind {
+    Call,
+    Data,
+}
+
+/// A reference to a symbol the code does not define. The 32-bit field at
+/// `offset` must hold the symbol's address plus `addend`, minus the field's own
+/// address.
+#[derive(Debug)]
+pub struct Reloc {
+    pub offset: usize,
+    pub symbol: String,
+    pub addend: i64,
+    pub kind: RelocKind,
+}
+
+#[derive(Debug)]
+pub struct Code {
+    pub bytes: Vec<u8>,
+    pub labels: HashMap<String, usize>,
+    pub relocs: Vec<Reloc>,
+}
+
+fn num(r: Reg) -> u8 {
+    match r {
+        Reg::RAX => 0,
+        Reg::RCX => 1,
+        Reg::RDX => 2,
+        Reg::RBX => 3,
+        Reg::RSP => 4,
+        Reg::RBP => 5,
+        Reg::RSI => 6,
+        Reg::RDI => 7,
+        Reg::R8 => 8,
+        Reg::R9 => 9,
+        Reg::R10 => 10,
+        Reg::R11 => 11,
+        Reg::R12 => 12,
+        Reg::R13 => 13,
+        Reg::R14 => 14,
+        Reg::R15 => 15,
+    }
+}
+
+fn cond(c: &str) -> u8 {
+    match c {
+        "o" => 0x0,
+        "no" => 0x1,
+        "b" => 0x2,
+        "ae" => 0x3,
+        "e" => 0x4,
+        "ne" => 0x5,
+        "be" => 0x6,
+        "a" => 0x7,
+        "s" => 0x8,
+        "ns" => 0x9,
+        "l" => 0xc,
+        "ge" => 0xd,
+        "le" => 0xe,
+        "g" => 0xf,
+        _ => panic!("unknown condition {c}"),
+    }
+}
+
+// A register or memory operand, for the ModRM byte.
+enum Rm<'v> {
+    Reg(Reg),
+    Mem(Reg, Option<(Reg, i32)>, i32),
+    Rip(&'v str),
+}
+
+fn rm(v: &Val) -> Rm<'_> {
+    match v {
+        Val::Reg(r) => Rm::Reg(*r),
+        Val::RegOffset(b, d) => Rm::Mem(*b, None, *d),
+        Val::EffectiveAddr(b, i, s, d) => Rm::Mem(*b, Some((*i, *s)), *d),
+        Val::RelLabel(l) => Rm::Rip(l),
+        Val::Imm32(_) | Val::Imm64(_) => panic!("immediate {v:?} where a register or memory operand is needed"),
+    }
+}
+
+// An immediate that fits in 32 bits, sign-extended by the instruction.
+fn imm32(v: &Val) -> Option<i32> {
+    match v {
+        Val::Imm32(n) => Some(*n),
+        Val::Imm64(n) => Some(i32::try_from(*n).unwrap_or_else(|_| panic!("immediate {n} does not fit in 32 bits"))),
+        _ => None,
+    }
+}
+
+// A reference from the field at `offset` to `label`, relative to `end`, the
+// end of the instruction.
+struct Fixup {
+    offset: usize,
+    end: usize,
+    label: String,
+    kind: RelocKind,
+}
+
+struct Encoder {
+    bytes: Vec<u8>,
+    labels: HashMap<String, usize>,
+    fixups: Vec<Fixup>,
+    // the fixups of the instruction being encoded, whose end is not yet known
+    pending: Vec<(usize, String, RelocKind)>,
+}
+
+impl Encoder {
+    fn byte(&mut self, b: u8) {
+        self.bytes.push(b);
+    }
+
+    fn i32(&mut self, n: i32) {
+        self.bytes.extend(n.to_le_bytes());
+    }
+
+    fn rel32(&mut self, label: &str, kind: RelocKind) {
+        self.pending.push((self.bytes.len(), label.to_string(), kind));
+        self.i32(0);
+    }
+
+    fn end(&mut self) {
+        let end = self.bytes.len();
+        for (offset, label, kind) in self.pending.drain(..) {
+            self.fixups.push(Fixup { offset, end, label, kind });
+        }
+    }
+
+    // Emits the REX prefix, `opcode`, and the ModRM byte with `reg` in its reg
+    // field and `m` as its operand, then any SIB byte and displacement.
+    fn op(&mut self, w: bool, opcode: &[u8], reg: u8, m: &Rm) {
+        let (x, b) = match m {
+            Rm::Reg(r) => (0, num(*r)),
+            Rm::Mem(base, index, _) => (index.map_or(0, |(i, _)| num(i)), num(*base)),
+            Rm::Rip(_) => (0, 0),
+        };
+        let rex = 0x40 | (w as u8) << 3 | (reg >> 3) << 2 | (x >> 3) << 1 | b >> 3;
+        if rex != 0x40 {
+            self.byte(rex);
+        }
+        self.bytes.extend(opcode);
+        let reg = (reg & 7) << 3;
+        match m {
+            Rm::Reg(r) => self.byte(0xc0 | reg | (num(*r) & 7)),
+            Rm::Rip(l) => {
+                self.byte(reg | 5);
+                self.rel32(l, if *l == INPUT_LABEL { RelocKind::Data } else { RelocKind::Call });
+            },
+            Rm::Mem(base, index, disp) => {
+                let base = num(*base) & 7;
+                // RBP and R13 as a base always take a displacement
+                let md = match *disp {
+                    0 if base != 5 => 0x00,
+                    d if i8::try_from(d).is_ok() => 0x40,
+                    _ => 0x80,
+                };
+                match index {
+                    Some((i, scale)) => {
+                        let ss = match scale {
+                            1 => 0,
+                            2 => 1,
+                            4 => 2,
+                            8 => 3,
+                            _ => panic!("invalid scale {scale}"),
+                        };
+                        self.byte(md | reg | 4);
+                        self.byte(ss << 6 | (num(*i) & 7) << 3 | base);
+                    },
+                    // RSP and R12 as a base need a SIB byte
+                    None if base == 4 => {
+                        self.byte(md | reg | 4);
+                        self.byte(0x24);
+                    },
+                    None => self.byte(md | reg | base),
+                }
+                match md {
+                    0x40 => self.byte(*disp as u8),
+                    0x80 => self.i32(*disp),
+                    _ => {},
+                }
+            },
+        }
+    }
+
+    // add, sub, and, xor or cmp, given the opcode extension `ext` for
+    // immediates and the opcode of the register-to-r/m form.
+    fn alu(&mut self, ext: u8, opcode: u8, dst: &Val, src: &Val) {
+        match (dst, imm32(src)) {
+            (_, Some(n)) => match i8::try_from(n) {
+                Ok(b) => {
+                    self.op(true, &[0x83], ext, &rm(dst));
+                    self.byte(b as u8);
+                },
+                Err(_) => {
+                    self.op(true, &[0x81], ext, &rm(dst));
+                    self.i32(n);
+                },
+            },
+            (Val::Reg(r), None) if !matches!(src, Val::Reg(_)) => self.op(true, &[opcode + 2], num(*r), &rm(src)),
+            (_, None) => match src {
+                Val::Reg(s) => self.op(true, &[opcode], num(*s), &rm(dst)),
+                _ => panic!("invalid operands {dst:?}, {src:?}"),
+            },
+        }
+    }
+
+    fn instr(&mut self, i: &Instr) {
+        match i {
+            Instr::Mov(dst, src) => match (dst, src) {
+                (Val::Reg(r), Val::Imm64(n)) if i32::try_from(*n).is_err() => {
+                    self.byte(0x48 | num(*r) >> 3);
+                    self.byte(0xb8 + (num(*r) & 7));
+                    self.bytes.extend(n.to_le_bytes());
+                },
+                (_, Val::Imm32(_) | Val::Imm64(_)) => {
+                    self.op(true, &[0xc7], 0, &rm(dst));
+                    self.i32(imm32(src).unwrap());
+                },
+                (_, Val::Reg(s)) => self.op(true, &[0x89], num(*s), &rm(dst)),
+                (Val::Reg(r), _) => self.op(true, &[0x8b], num(*r), &rm(src)),
+                _ => panic!("invalid operands {dst:?}, {src:?}"),
+            },
+            Instr::Add(d, s) => self.alu(0, 0x01, d, s),
+            Instr::Sub(d, s) => self.alu(5, 0x29, d, s),
+            Instr::And(d, s) => self.alu(4, 0x21, d, s),
+            Instr::Xor(d, s) => self.alu(6, 0x31, d, s),
+            Instr::Cmp(d, s) => self.alu(7, 0x39, d, s),
+            Instr::Imul(Val::Reg(r), s) => self.op(true, &[0x0f, 0xaf], num(*r), &rm(s)),
+            Instr::Sar(d, s) => {
+                self.op(true, &[0xc1], 7, &rm(d));
+                self.byte(imm32(s).expect("shift by a register") as u8);
+            },
+            Instr::Test(d, s) => match imm32(s) {
+                Some(n) => {
+                    self.op(true, &[0xf7], 0, &rm(d));
+                    self.i32(n);
+                },
+                None => match s {
+                    Val::Reg(s) => self.op(true, &[0x85], num(*s), &rm(d)),
+                    _ => panic!("invalid operands {d:?}, {s:?}"),
+                },
+            },
+            Instr::Push(v) => match (v, imm32(v)) {
+                (_, Some(n)) => {
+                    self.byte(0x68);
+                    self.i32(n);
+                },
+                (Val::Reg(r), _) => {
+                    if num(*r) >= 8 {
+                        self.byte(0x41);
+                    }
+                    self.byte(0x50 + (num(*r) & 7));
+                },
+                _ => self.op(false, &[0xff], 6, &rm(v)),
+            },
+            Instr::Pop(v) => match v {
+                Val::Reg(r) => {
+                    if num(*r) >= 8 {
+                        self.byte(0x41);
+                    }
+                    self.byte(0x58 + (num(*r) & 7));
+                },
+                _ => self.op(false, &[0x8f], 0, &rm(v)),
+            },
+            Instr::Call(l) => {
+                self.byte(0xe8);
+                self.rel32(l, RelocKind::Call);
+            },
+            Instr::CallInd(v) => self.op(false, &[0xff], 2, &rm(v)),
+            Instr::JmpInd(v) => self.op(false, &[0xff], 4, &rm(v)),
+            Instr::Leave => self.byte(0xc9),
+            Instr::Ret => self.byte(0xc3),
+            Instr::J("", l) => {
+                self.byte(0xe9);
+                self.rel32(l, RelocKind::Call);
+            },
+            Instr::J(c, l) => {
+                self.bytes.extend([0x0f, 0x80 + cond(c)]);
+                self.rel32(l, RelocKind::Call);
+            },
+            Instr::Cmov(c, Val::Reg(r), s) => self.op(true, &[0x0f, 0x40 + cond(c)], num(*r), &rm(s)),
+            Instr::Lea(Val::Reg(r), s) => self.op(true, &[0x8d], num(*r), &rm(s)),
+            Instr::Label(l) => {
+                self.labels.insert(l.to_string(), self.bytes.len());
+            },
+            _ => panic!("cannot encode {i:?}"),
+        }
+        self.end();
+    }
+}
+
+/// Encodes a sequence of instructions.
+pub fn encode(instrs: &[Instr]) -> Code {
+    let mut e = Encoder { bytes: Vec::new(), labels: HashMap::new(), fixups: Vec::new(), pending: Vec::new() };
+    for i in instrs {
+        e.instr(i);
+    }
+    let mut relocs = Vec::new();
+    for f in e.fixups {
+        let addend = f.offset as i64 - f.end as i64;
+        match e.labels.get(&f.label) {
+            Some(&target) => {
+                let rel = (target as i64 - f.end as i64) as i32;
+                e.bytes[f.offset..f.offset + 4].copy_from_slice(&rel.to_le_bytes());
+            },
+            None => relocs.push(Reloc { offset: f.offset, symbol: f.label, addend, kind: f.kind }),
+        }
+    }
+    Code { bytes: e.bytes, labels: e.labels, relocs }
+}
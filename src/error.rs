@@ -0,0 +1,51 @@
+use std::fmt;
+
+/// A byte range in the source file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+}
+
+/// A static error, pointing at the s-expression that caused it.
+#[derive(Debug)]
+pub struct CompileError {
+    pub span: Span,
+    pub msg: String,
+}
+
+impl CompileError {
+    pub fn new(span: Span, msg: impl Into<String>) -> CompileError {
+        CompileError { span, msg: msg.into() }
+    }
+
+    /// Formats the error as `file:line:col: message`, followed by the offending line.
+    pub fn render(&self, file: &str, src: &str) -> String {
+        let start = self.span.start.min(src.len());
+        let line_start = src[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = src[start..].find('\n').map_or(src.len(), |i| start + i);
+        let line = &src[line_start..line_end];
+        let line_no = src[..start].matches('\n').count() + 1;
+        let col = src[line_start..start].chars().count() + 1;
+        let width = src[start..self.span.end.clamp(start, line_end)].chars().count().max(1);
+        let gutter = " ".repeat(line_no.to_string().len());
+        format!(
+            "{file}:{line_no}:{col}: {}\n{gutter} |\n{line_no} | {line}\n{gutter} | {}{}\n",
+            self.msg,
+            " ".repeat(col - 1),
+            "^".repeat(width),
+        )
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::{self, Rhs, Stmt, Term, Var};
+
+// The frame layout shared by the backends. A frame pointer points at the saved
+// frame pointer of the caller, with the return address above it; the closure
+// being called is two words up, followed by the arguments, and the variables
+// kept in the frame are below it.
+
+// Where a variable lives: a register, or a word at `8 * w` from the frame
+// pointer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Loc<R> {
+    Stack(i32),
+    Reg(R),
+}
+
+fn add_edge(edges: &mut HashMap<Var, HashSet<Var>>, u: &Var, v: &Var) {
+    if u != v {
+        edges.entry(u.to_string()).or_default().insert(v.to_string());
+        edges.entry(v.to_string()).or_default().insert(u.to_string());
+    }
+}
+
+// Gives every variable of `f` a location. Variables that interfere share
+// neither a register nor a frame slot; variables live across a call, and the
+// operands of an allocation, which the collector may move, stay in the frame.
+// Returns the locations and the number of frame slots.
+pub fn allocate<R: Copy + PartialEq>(f: &ir::Fun, regs: &[R]) -> (HashMap<Var, Loc<R>>, i32) {
+    let live = ir::liveness(f);
+    let mut order = f.captured.clone();
+    let mut edges: HashMap<Var, HashSet<Var>> = HashMap::new();
+    let mut crossing = HashSet::new();
+    for b in &f.blocks {
+        for s in &b.stmts {
+            if let Stmt::Assign(d, _) = s {
+                if !order.contains(d) {
+                    order.push(d.to_string());
+                }
+            }
+        }
+        ir::walk_back(b, &live.live_out[&b.label], |s, after| {
+            if let Stmt::Assign(d, rhs) = s {
+                if rhs.calls() {
+                    crossing.extend(after.iter().filter(|v| *v != d).cloned());
+                }
+                if rhs.allocates() {
+                    crossing.extend(rhs.operands().into_iter().filter_map(|o| o.var().cloned()));
+                }
+                for v in after {
+                    add_edge(&mut edges, d, v);
+                }
+            }
+        });
+    }
+    // the captured values are all copied out of the closure on entry
+    let entry: Vec<&Var> = live.live_in[&f.blocks[0].label].iter().chain(&f.captured).collect();
+    for c in &f.captured {
+        for v in &entry {
+            add_edge(&mut edges, c, v);
+        }
+    }
+
+    let mut locs: HashMap<Var, Loc<R>> = f.params.iter().enumerate().map(|(i, v)| (v.to_string(), Loc::Stack(i as i32 + 3))).collect();
+    let mut slots = 0;
+    for v in order {
+        if locs.contains_key(&v) {
+            continue;
+        }
+        let taken: Vec<Loc<R>> = edges.get(&v).into_iter().flatten().filter_map(|n| locs.get(n).copied()).collect();
+        let reg = regs.iter().find(|r| !taken.contains(&Loc::Reg(**r)));
+        let loc = match reg {
+            Some(r) if !crossing.contains(&v) => Loc::Reg(*r),
+            _ => {
+                let k = (1..).find(|k| !taken.contains(&Loc::Stack(-k))).unwrap();
+                slots = slots.max(k);
+                Loc::Stack(-k)
+            },
+        };
+        locs.insert(v, loc);
+    }
+    (locs, slots)
+}
+
+// The most arguments passed to or taken by any function.
+pub fn max_args(p: &ir::Prog) -> usize {
+    p.funs.iter().chain([&p.main]).flat_map(|f| {
+        let calls = f.blocks.iter().flat_map(|b| {
+            let stmts = b.stmts.iter().filter_map(|s| match s {
+                Stmt::Assign(_, Rhs::Call(_, es) | Rhs::Apply(_, es)) => Some(es.len()),
+                _ => None,
+            });
+            let term = match &b.term {
+                Term::TailCall(_, es) | Term::TailApply(_, es) => Some(es.len()),
+                _ => None,
+            };
+            stmts.chain(term)
+        });
+        calls.chain([f.params.len()]).collect::<Vec<_>>()
+    }).max().unwrap_or_default()
+}
@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+
+use crate::ast::{Op1, Op2};
+use crate::ir::{Fun, Operand, Prog, Rhs, Stmt, Ty, Var};
+
+// Infers, at each point of a function, the type of value that variables hold
+// whichever path led there, and removes the `check` statements that test for
+// a type the value is known to have. A check that passes also tells the rest
+// of the function the type of its operand.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Num,
+    Bool,
+    Tuple,
+    Vec,
+    Str,
+    Fun,
+}
+
+type Facts = HashMap<Var, Kind>;
+
+fn kind_of(ty: Ty) -> Kind {
+    match ty {
+        Ty::Num => Kind::Num,
+        Ty::Tuple => Kind::Tuple,
+        Ty::Vec => Kind::Vec,
+        Ty::Str => Kind::Str,
+        Ty::Fun => Kind::Fun,
+    }
+}
+
+fn operand(op: &Operand, facts: &Facts) -> Option<Kind> {
+    match op {
+        Operand::Num(_) => Some(Kind::Num),
+        Operand::Bool(_) => Some(Kind::Bool),
+        Operand::Var(v) => facts.get(v).copied(),
+    }
+}
+
+fn rhs(r: &Rhs, facts: &Facts) -> Option<Kind> {
+    match r {
+        Rhs::Copy(a) | Rhs::Prim1(Op1::Print, a) | Rhs::Set(_, _, _, a) => operand(a, facts),
+        Rhs::Prim1(Op1::Add1 | Op1::Sub1 | Op1::StringLength | Op1::VecLen, _) => Some(Kind::Num),
+        Rhs::Prim1(..) => Some(Kind::Bool),
+        Rhs::Prim2(o, _, _) => match o {
+            Op2::Plus | Op2::Minus | Op2::Times => Some(Kind::Num),
+            Op2::StringAppend | Op2::StringRef => Some(Kind::Str),
+            Op2::MakeVec => Some(Kind::Vec),
+            _ => Some(Kind::Bool),
+        },
+        // the empty tuple has the tuple tag too
+        Rhs::Tuple(_) => Some(Kind::Tuple),
+        Rhs::Str(_) => Some(Kind::Str),
+        Rhs::Closure(..) => Some(Kind::Fun),
+        Rhs::Input | Rhs::Get(..) | Rhs::Call(..) | Rhs::Apply(..) => None,
+    }
+}
+
+// Updates `facts` past `s`, returning whether `s` is a check that cannot fail.
+fn step(s: &Stmt, facts: &mut Facts) -> bool {
+    match s {
+        Stmt::Assign(d, r) => {
+            match rhs(r, facts) {
+                Some(k) => facts.insert(d.to_string(), k),
+                None => facts.remove(d),
+            };
+            false
+        },
+        Stmt::Check(ty, a) => {
+            let k = kind_of(*ty);
+            if operand(a, facts) == Some(k) {
+                return true;
+            }
+            if let Operand::Var(v) = a {
+                facts.insert(v.to_string(), k);
+            }
+            false
+        },
+        Stmt::CheckIndex(..) => false,
+    }
+}
+
+// Returns the number of checks removed and the number there were.
+fn remove_in_fun(f: &mut Fun) -> (usize, usize) {
+    let index: HashMap<&String, usize> = f.blocks.iter().enumerate().map(|(i, b)| (&b.label, i)).collect();
+    // nothing is known about the parameters, nor about blocks not yet reached
+    let mut ins: Vec<Option<Facts>> = vec![None; f.blocks.len()];
+    ins[0] = Some(Facts::new());
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (b, i) in f.blocks.iter().zip(0..) {
+            let Some(mut facts) = ins[i].clone() else { continue };
+            for s in &b.stmts {
+                step(s, &mut facts);
+            }
+            for l in b.term.successors() {
+                let j = index[l];
+                match &mut ins[j] {
+                    Some(known) => {
+                        let n = known.len();
+                        known.retain(|v, k| facts.get(v) == Some(k));
+                        changed |= known.len() != n;
+                    },
+                    None => {
+                        ins[j] = Some(facts.clone());
+                        changed = true;
+                    },
+                }
+            }
+        }
+    }
+
+    let mut removed = 0;
+    let mut total = 0;
+    for (b, facts) in f.blocks.iter_mut().zip(ins) {
+        let mut facts = facts.unwrap_or_default();
+        total += b.stmts.iter().filter(|s| matches!(s, Stmt::Check(..))).count();
+        let n = b.stmts.len();
+        b.stmts.retain(|s| !step(s, &mut facts));
+        removed += n - b.stmts.len();
+    }
+    (removed, total)
+}
+
+/// Removes the checks that cannot fail from every function of `p`. Returns the
+/// number removed and the number there were.
+pub fn remove_checks(p: &mut Prog) -> (usize, usize) {
+    p.funs.iter_mut().chain([&mut p.main]).map(remove_in_fun).fold((0, 0), |(r, t), (r1, t1)| (r + r1, t + t1))
+}
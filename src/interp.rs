@@ -0,0 +1,488 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::ir::free_vars;
+
+// Evaluates checked programs directly, without compiling them. Operands are
+// evaluated and checked in the order the compiled code does, so that a program
+// prints the same output and fails with the same error either way.
+
+// The range of snek numbers, which are stored shifted left by one.
+const MIN: i64 = i64::MIN >> 1;
+const MAX: i64 = i64::MAX >> 1;
+
+// The words in the runtime's default heap; no vector larger than that can be
+// allocated.
+const HEAP_WORDS: i64 = 0x8000000 / 8;
+
+// The fields of a tuple or a vector, which can be assigned.
+type Fields<'a> = Rc<RefCell<Vec<Value<'a>>>>;
+
+/// A variable, which the lambdas that capture it share.
+pub type Cell<'a> = Rc<RefCell<Value<'a>>>;
+
+/// A snek value. Tuples, vectors, strings and closures are objects, compared
+/// by identity with `==`.
+#[derive(Clone)]
+pub enum Value<'a> {
+    Num(i64),
+    Bool(bool),
+    // the empty tuple is a single value rather than an object
+    Nil,
+    Tuple(Fields<'a>),
+    Vec(Fields<'a>),
+    Str(Rc<[u8]>),
+    Fun(Rc<Closure<'a>>),
+}
+
+/// A function value: a top-level function, or a lambda with the variables it
+/// captured.
+pub struct Closure<'a> {
+    params: &'a [String],
+    body: &'a Expr,
+    captured: Env<'a>,
+}
+
+/// The errors a program can stop with, as reported by the runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    InvalidArgument,
+    Overflow,
+    IndexOutOfRange,
+    OutOfMemory,
+    WrongArity,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            Error::InvalidArgument => "invalid argument",
+            Error::Overflow => "overflow",
+            Error::IndexOutOfRange => "index out of range",
+            Error::OutOfMemory => "out of memory",
+            Error::WrongArity => "wrong number of arguments",
+        };
+        write!(f, "{msg}")
+    }
+}
+
+impl Value<'_> {
+    // The address of an object, which identifies it.
+    fn addr(&self) -> Option<*const ()> {
+        match self {
+            Value::Tuple(r) | Value::Vec(r) => Some(Rc::as_ptr(r) as *const ()),
+            Value::Str(s) => Some(Rc::as_ptr(s) as *const ()),
+            Value::Fun(c) => Some(Rc::as_ptr(c) as *const ()),
+            _ => None,
+        }
+    }
+
+    fn write(&self, seen: &mut Vec<*const ()>, out: &mut String) {
+        match self {
+            Value::Num(n) => out.push_str(&n.to_string()),
+            Value::Bool(b) => out.push_str(&b.to_string()),
+            Value::Nil => out.push_str("()"),
+            Value::Fun(_) => out.push_str("<function>"),
+            Value::Str(s) => out.push_str(&String::from_utf8_lossy(s)),
+            Value::Tuple(r) | Value::Vec(r) => {
+                let (open, close) = if matches!(self, Value::Tuple(_)) { ('(', ')') } else { ('[', ']') };
+                let addr = self.addr().unwrap();
+                out.push(open);
+                // a cycle is cut where an object contains itself
+                if seen.contains(&addr) {
+                    out.push_str("...");
+                } else {
+                    seen.push(addr);
+                    for (i, v) in r.borrow().iter().enumerate() {
+                        if i > 0 {
+                            out.push(' ');
+                        }
+                        v.write(seen, out);
+                    }
+                    seen.pop();
+                }
+                out.push(close);
+            },
+        }
+    }
+}
+
+/// Formats values the way `snek_print` does.
+impl fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut out = String::new();
+        self.write(&mut Vec::new(), &mut out);
+        write!(f, "{out}")
+    }
+}
+
+/// Parses the input of a program, as the runtime does.
+pub fn parse_input<'a>(input: &str) -> Option<Value<'a>> {
+    match input {
+        "true" => Some(Value::Bool(true)),
+        "false" => Some(Value::Bool(false)),
+        _ => input.parse().ok().filter(|n| (MIN..=MAX).contains(n)).map(Value::Num),
+    }
+}
+
+// `==` compares numbers and booleans by value and objects by identity.
+fn identical(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Num(x), Value::Num(y)) => x == y,
+        (Value::Bool(x), Value::Bool(y)) => x == y,
+        (Value::Nil, Value::Nil) => true,
+        _ => a.addr().is_some() && a.addr() == b.addr(),
+    }
+}
+
+// `=` compares strings by their bytes and tuples and vectors by their fields.
+// A pair of objects already being compared is taken to be equal.
+fn structural_eq(a: &Value, b: &Value, pending: &mut Vec<(*const (), *const ())>) -> bool {
+    if identical(a, b) {
+        return true;
+    }
+    match (a, b) {
+        (Value::Str(x), Value::Str(y)) => x == y,
+        (Value::Tuple(x), Value::Tuple(y)) | (Value::Vec(x), Value::Vec(y)) => {
+            let pair = (a.addr().unwrap(), b.addr().unwrap());
+            if pending.contains(&pair) {
+                return true;
+            }
+            let (x, y) = (x.borrow(), y.borrow());
+            if x.len() != y.len() {
+                return false;
+            }
+            pending.push(pair);
+            let eq = x.iter().zip(y.iter()).all(|(v, w)| structural_eq(v, w, pending));
+            pending.pop();
+            eq
+        },
+        _ => false,
+    }
+}
+
+fn num(v: &Value) -> Result<i64, Error> {
+    match v {
+        Value::Num(n) => Ok(*n),
+        _ => Err(Error::InvalidArgument),
+    }
+}
+
+fn checked<'a>(n: Option<i64>) -> Result<Value<'a>, Error> {
+    n.filter(|n| (MIN..=MAX).contains(n)).map(Value::Num).ok_or(Error::Overflow)
+}
+
+fn index(len: usize, i: i64) -> Result<usize, Error> {
+    usize::try_from(i).ok().filter(|i| *i < len).ok_or(Error::IndexOutOfRange)
+}
+
+// The fields of a tuple or a vector, whichever `v` must be.
+fn fields<'a>(v: &Value<'a>, tuple: bool) -> Result<Option<Fields<'a>>, Error> {
+    match v {
+        Value::Nil if tuple => Ok(None),
+        Value::Tuple(r) if tuple => Ok(Some(r.clone())),
+        Value::Vec(r) if !tuple => Ok(Some(r.clone())),
+        _ => Err(Error::InvalidArgument),
+    }
+}
+
+// The ways evaluation stops early: breaking out of a loop, or failing.
+enum Stop<'a> {
+    Break(Value<'a>),
+    Error(Error),
+}
+
+impl From<Error> for Stop<'_> {
+    fn from(e: Error) -> Self {
+        Stop::Error(e)
+    }
+}
+
+// The result of an expression in tail position: a value, or a call to make
+// once the caller's variables are gone.
+enum Tail<'a> {
+    Value(Value<'a>),
+    Call(Rc<Closure<'a>>, Vec<Value<'a>>),
+}
+
+/// The variables in scope, innermost last.
+pub type Env<'a> = Vec<(&'a str, Cell<'a>)>;
+
+/// A new variable holding `v`.
+pub fn cell(v: Value) -> Cell {
+    Rc::new(RefCell::new(v))
+}
+
+/// Evaluates expressions against a set of top-level functions.
+pub struct Interp<'a> {
+    funs: HashMap<&'a str, &'a Func>,
+    input: Value<'a>,
+}
+
+impl<'a> Interp<'a> {
+    pub fn new(input: Value<'a>) -> Interp<'a> {
+        Interp { funs: HashMap::new(), input }
+    }
+
+    /// Adds a top-level function, replacing any of the same name.
+    pub fn define(&mut self, f: &'a Func) {
+        self.funs.insert(&f.name, f);
+    }
+
+    /// Evaluates `e` with the variables of `env`, which it may assign.
+    pub fn eval(&self, e: &'a Expr, env: &mut Env<'a>) -> Result<Value<'a>, Error> {
+        match self.expr(e, env) {
+            Ok(v) => Ok(v),
+            Err(Stop::Error(e)) => Err(e),
+            Err(Stop::Break(_)) => unreachable!("break outside of loop"),
+        }
+    }
+
+    // A checked program only names functions that are defined, so one that is
+    // missing is reported like any other value that cannot be called.
+    fn function(&self, name: &str) -> Result<Rc<Closure<'a>>, Error> {
+        let f = self.funs.get(name).ok_or(Error::InvalidArgument)?;
+        Ok(Rc::new(Closure { params: &f.args, body: &f.expr, captured: Vec::new() }))
+    }
+
+    fn lookup(&self, id: &str, env: &Env<'a>) -> Result<Value<'a>, Error> {
+        match env.iter().rev().find(|(x, _)| *x == id) {
+            Some((_, v)) => Ok(v.borrow().clone()),
+            None if id == "input" => Ok(self.input.clone()),
+            None => Ok(Value::Fun(self.function(id)?)),
+        }
+    }
+
+    fn exprs(&self, es: &'a [Expr], env: &mut Env<'a>) -> Result<Vec<Value<'a>>, Stop<'a>> {
+        es.iter().map(|e| self.expr(e, env)).collect()
+    }
+
+    // Checks that a value called is a function.
+    fn closure(f: Value<'a>) -> Result<Rc<Closure<'a>>, Error> {
+        match f {
+            Value::Fun(c) => Ok(c),
+            _ => Err(Error::InvalidArgument),
+        }
+    }
+
+    // The function a call refers to and its arguments, in the order the
+    // compiled code evaluates them.
+    fn callee(&self, e: &'a Expr, env: &mut Env<'a>) -> Result<(Rc<Closure<'a>>, Vec<Value<'a>>), Stop<'a>> {
+        match &e.kind {
+            ExprKind::Call(n, args) if env.iter().any(|(x, _)| x == n) => {
+                let f = self.lookup(n, env)?;
+                let args = self.exprs(args, env)?;
+                Ok((Self::closure(f)?, args))
+            },
+            ExprKind::Call(n, args) => {
+                let args = self.exprs(args, env)?;
+                Ok((self.function(n)?, args))
+            },
+            ExprKind::Apply(f, args) => {
+                let f = self.expr(f, env)?;
+                let args = self.exprs(args, env)?;
+                Ok((Self::closure(f)?, args))
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    // Calls `f`, and then each function it calls in tail position in turn. The
+    // arity of a direct call is only known to be right when it is checked, and a
+    // function may have been redefined since.
+    fn call(&self, mut f: Rc<Closure<'a>>, mut args: Vec<Value<'a>>) -> Result<Value<'a>, Error> {
+        loop {
+            if f.params.len() != args.len() {
+                return Err(Error::WrongArity);
+            }
+            let mut env = f.captured.clone();
+            env.extend(f.params.iter().map(String::as_str).zip(args.into_iter().map(cell)));
+            match self.tail(f.body, &mut env) {
+                Ok(Tail::Value(v)) => return Ok(v),
+                Ok(Tail::Call(g, a)) => (f, args) = (g, a),
+                Err(Stop::Error(e)) => return Err(e),
+                Err(Stop::Break(_)) => unreachable!("break outside of loop"),
+            }
+        }
+    }
+
+    fn tail(&self, e: &'a Expr, env: &mut Env<'a>) -> Result<Tail<'a>, Stop<'a>> {
+        match &e.kind {
+            ExprKind::Let(bs, body) => {
+                let n = env.len();
+                let t = self.bind(bs, env).and_then(|()| self.tail(body, env));
+                env.truncate(n);
+                t
+            },
+            ExprKind::If(cond, thn, els) => match self.expr(cond, env)? {
+                Value::Bool(false) => self.tail(els, env),
+                _ => self.tail(thn, env),
+            },
+            ExprKind::Block(es) => {
+                let (last, es) = es.split_last().unwrap();
+                self.exprs(es, env)?;
+                self.tail(last, env)
+            },
+            ExprKind::Call(..) | ExprKind::Apply(..) => {
+                let (f, args) = self.callee(e, env)?;
+                Ok(Tail::Call(f, args))
+            },
+            _ => Ok(Tail::Value(self.expr(e, env)?)),
+        }
+    }
+
+    // Binds the variables of a `let`; the caller drops them again, however the
+    // body ends.
+    fn bind(&self, bs: &'a [(String, Expr)], env: &mut Env<'a>) -> Result<(), Stop<'a>> {
+        for (id, e1) in bs {
+            let v = self.expr(e1, env)?;
+            env.push((id, cell(v)));
+        }
+        Ok(())
+    }
+
+    // Evaluates the index and then the object, and checks both.
+    fn element(&self, e: &'a Expr, i: &'a Expr, tuple: bool, env: &mut Env<'a>) -> Result<(Fields<'a>, usize), Stop<'a>> {
+        let i = num(&self.expr(i, env)?)?;
+        let obj = fields(&self.expr(e, env)?, tuple)?.ok_or(Error::IndexOutOfRange)?;
+        let i = index(obj.borrow().len(), i)?;
+        Ok((obj, i))
+    }
+
+    fn expr(&self, e: &'a Expr, env: &mut Env<'a>) -> Result<Value<'a>, Stop<'a>> {
+        let v = match &e.kind {
+            ExprKind::Number(n) => Value::Num(*n),
+            ExprKind::Boolean(b) => Value::Bool(*b),
+            ExprKind::Str(s) => Value::Str(s.as_bytes().into()),
+            ExprKind::Id(id) => self.lookup(id, env)?,
+            ExprKind::Let(bs, body) => {
+                let n = env.len();
+                let v = self.bind(bs, env).and_then(|()| self.expr(body, env));
+                env.truncate(n);
+                v?
+            },
+            ExprKind::UnOp(o, e1) => {
+                let a = self.expr(e1, env)?;
+                match o {
+                    Op1::Add1 => checked(num(&a)?.checked_add(1))?,
+                    Op1::Sub1 => checked(num(&a)?.checked_sub(1))?,
+                    Op1::IsNum => Value::Bool(matches!(a, Value::Num(_))),
+                    Op1::IsBool => Value::Bool(matches!(a, Value::Bool(_))),
+                    Op1::IsTuple => Value::Bool(matches!(a, Value::Tuple(_) | Value::Nil)),
+                    Op1::IsFun => Value::Bool(matches!(a, Value::Fun(_))),
+                    Op1::IsString => Value::Bool(matches!(a, Value::Str(_))),
+                    Op1::StringLength => match a {
+                        Value::Str(s) => Value::Num(s.len() as i64),
+                        _ => return Err(Error::InvalidArgument.into()),
+                    },
+                    Op1::VecLen => Value::Num(fields(&a, false)?.unwrap().borrow().len() as i64),
+                    Op1::Print => {
+                        println!("{a}");
+                        a
+                    },
+                }
+            },
+            // the right operand is evaluated first
+            ExprKind::BinOp(o, e1, e2) => {
+                let b = self.expr(e2, env)?;
+                let numeric = !matches!(o, Op2::Equal | Op2::StEq | Op2::StringAppend | Op2::StringRef | Op2::MakeVec);
+                if numeric {
+                    num(&b)?;
+                }
+                let a = self.expr(e1, env)?;
+                match o {
+                    Op2::Equal => Value::Bool(identical(&a, &b)),
+                    Op2::StEq => Value::Bool(structural_eq(&a, &b, &mut Vec::new())),
+                    Op2::StringAppend => match (&a, &b) {
+                        (Value::Str(x), Value::Str(y)) => Value::Str([&x[..], &y[..]].concat().into()),
+                        _ => return Err(Error::InvalidArgument.into()),
+                    },
+                    Op2::StringRef => match (&a, &b) {
+                        (Value::Str(s), Value::Num(i)) => Value::Str([s[index(s.len(), *i)?]].into()),
+                        _ => return Err(Error::InvalidArgument.into()),
+                    },
+                    Op2::MakeVec => {
+                        let n = num(&a)?;
+                        if n < 0 {
+                            return Err(Error::InvalidArgument.into());
+                        }
+                        if n >= HEAP_WORDS {
+                            return Err(Error::OutOfMemory.into());
+                        }
+                        Value::Vec(Rc::new(RefCell::new(vec![b; n as usize])))
+                    },
+                    _ => {
+                        let (x, y) = (num(&a)?, num(&b)?);
+                        match o {
+                            Op2::Plus => checked(x.checked_add(y))?,
+                            Op2::Minus => checked(x.checked_sub(y))?,
+                            Op2::Times => checked(x.checked_mul(y))?,
+                            Op2::Less => Value::Bool(x < y),
+                            Op2::LessEqual => Value::Bool(x <= y),
+                            Op2::Greater => Value::Bool(x > y),
+                            Op2::GreaterEqual => Value::Bool(x >= y),
+                            _ => unreachable!(),
+                        }
+                    },
+                }
+            },
+            // only `false` is false
+            ExprKind::If(cond, thn, els) => match self.expr(cond, env)? {
+                Value::Bool(false) => self.expr(els, env)?,
+                _ => self.expr(thn, env)?,
+            },
+            ExprKind::Loop(e1) => loop {
+                match self.expr(e1, env) {
+                    Ok(_) => {},
+                    Err(Stop::Break(v)) => break v,
+                    Err(e) => return Err(e),
+                }
+            },
+            ExprKind::Break(e1) => return Err(Stop::Break(self.expr(e1, env)?)),
+            ExprKind::Set(id, e1) => {
+                let v = self.expr(e1, env)?;
+                let (_, slot) = env.iter().rev().find(|(x, _)| x == id).expect("assignment to unbound variable");
+                *slot.borrow_mut() = v.clone();
+                v
+            },
+            ExprKind::Block(es) => self.exprs(es, env)?.pop().unwrap(),
+            ExprKind::Call(..) | ExprKind::Apply(..) => {
+                let (f, args) = self.callee(e, env)?;
+                self.call(f, args)?
+            },
+            ExprKind::Lambda(params, body) => {
+                let mut fv = Vec::new();
+                free_vars(body, &params.iter().cloned().collect(), &mut fv);
+                let captured = fv.iter().filter_map(|id| env.iter().rev().find(|(x, _)| x == id).cloned()).collect();
+                Value::Fun(Rc::new(Closure { params, body, captured }))
+            },
+            ExprKind::Tuple(es) if es.is_empty() => Value::Nil,
+            ExprKind::Tuple(es) => Value::Tuple(Rc::new(RefCell::new(self.exprs(es, env)?))),
+            ExprKind::TupleGet(e1, i) | ExprKind::VecGet(e1, i) => {
+                let (obj, i) = self.element(e1, i, matches!(e.kind, ExprKind::TupleGet(..)), env)?;
+                let v = obj.borrow()[i].clone();
+                v
+            },
+            ExprKind::TupleSet(e1, i, e2) | ExprKind::VecSet(e1, i, e2) => {
+                let (obj, i) = self.element(e1, i, matches!(e.kind, ExprKind::TupleSet(..)), env)?;
+                let v = self.expr(e2, env)?;
+                obj.borrow_mut()[i] = v.clone();
+                v
+            },
+        };
+        Ok(v)
+    }
+}
+
+/// Runs a checked program on `input`.
+pub fn run<'a>(p: &'a Prog, input: Value<'a>) -> Result<Value<'a>, Error> {
+    let Prog(fs, e) = p;
+    let mut interp = Interp::new(input);
+    for f in fs {
+        interp.define(f);
+    }
+    interp.eval(e, &mut Vec::new())
+}
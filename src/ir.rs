@@ -0,0 +1,850 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::ast::*;
+
+// A function is a list of basic blocks, the first of which is its entry. Every
+// intermediate value is named, and the operands of a statement are variables or
+// constants. Variables are not in SSA form: `set!` assigns to them again, and
+// the two arms of an `if` both assign to its result.
+
+pub type Var = String;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Var(Var),
+    Num(i64),
+    Bool(bool),
+}
+
+// The runtime types that `check` statements test for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ty {
+    Num,
+    Tuple,
+    Vec,
+    Str,
+    Fun,
+}
+
+#[derive(Debug)]
+pub enum Rhs {
+    Copy(Operand),
+    Input,
+    // the operands of arithmetic and comparisons are already checked to be
+    // numbers; overflow is checked by the operation itself
+    Prim1(Op1, Operand),
+    Prim2(Op2, Operand, Operand),
+    Str(String),
+    Tuple(Vec<Operand>),
+    // `Get` and `Set` come after a `check-index` of the same object and index,
+    // except on the boxes of captured variables, which cannot fail
+    Get(Ty, Operand, Operand),
+    Set(Ty, Operand, Operand, Operand),
+    // the function's label, its arity and the captured values
+    Closure(String, usize, Vec<Operand>),
+    Call(String, Vec<Operand>),
+    // checks the arity of the closure, whose type is already checked
+    Apply(Operand, Vec<Operand>),
+}
+
+#[derive(Debug)]
+pub enum Stmt {
+    Assign(Var, Rhs),
+    Check(Ty, Operand),
+    // the object is not the empty tuple and the index is in bounds
+    CheckIndex(Operand, Operand),
+}
+
+#[derive(Debug)]
+pub enum Term {
+    Jump(String),
+    // to the first label unless the operand is false
+    Branch(Operand, String, String),
+    Return(Operand),
+    TailCall(String, Vec<Operand>),
+    TailApply(Operand, Vec<Operand>),
+}
+
+#[derive(Debug)]
+pub struct Block {
+    pub label: String,
+    pub stmts: Vec<Stmt>,
+    pub term: Term,
+}
+
+// `captured` are the variables a lambda copies out of its closure on entry.
+#[derive(Debug)]
+pub struct Fun {
+    pub name: String,
+    pub params: Vec<Var>,
+    pub captured: Vec<Var>,
+    pub blocks: Vec<Block>,
+}
+
+#[derive(Debug)]
+pub struct Prog {
+    pub funs: Vec<Fun>,
+    pub main: Fun,
+}
+
+// Function names may contain `-`, which is not allowed in assembler labels.
+pub fn func_label(s: &str) -> String {
+    format!("func_{}", s.replace('_', "__").replace('-', "_m"))
+}
+
+pub const MAIN_LABEL: &str = "__our_code_starts_here";
+
+impl Operand {
+    pub fn var(&self) -> Option<&Var> {
+        match self {
+            Operand::Var(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl Rhs {
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Rhs::Input | Rhs::Str(_) => vec![],
+            Rhs::Copy(a) | Rhs::Prim1(_, a) => vec![a],
+            Rhs::Prim2(_, a, b) | Rhs::Get(_, a, b) => vec![a, b],
+            Rhs::Set(_, a, b, c) => vec![a, b, c],
+            Rhs::Tuple(es) | Rhs::Closure(_, _, es) | Rhs::Call(_, es) => es.iter().collect(),
+            Rhs::Apply(f, es) => std::iter::once(f).chain(es).collect(),
+        }
+    }
+
+    // Whether the heap may be collected before the operands are read, which
+    // happens when an object is allocated from them.
+    pub fn allocates(&self) -> bool {
+        match self {
+            Rhs::Str(_) | Rhs::Closure(..) => true,
+            Rhs::Tuple(es) => !es.is_empty(),
+            Rhs::Prim2(o, _, _) => matches!(o, Op2::StringAppend | Op2::StringRef | Op2::MakeVec),
+            _ => false,
+        }
+    }
+
+    // Whether evaluating it calls out, to a function, the runtime or the collector.
+    pub fn calls(&self) -> bool {
+        match self {
+            Rhs::Call(..) | Rhs::Apply(..) | Rhs::Prim1(Op1::Print, _) | Rhs::Prim2(Op2::StEq, _, _) => true,
+            _ => self.allocates(),
+        }
+    }
+}
+
+impl Stmt {
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Stmt::Assign(_, rhs) => rhs.operands(),
+            Stmt::Check(_, a) => vec![a],
+            Stmt::CheckIndex(a, b) => vec![a, b],
+        }
+    }
+}
+
+impl Term {
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Term::Jump(_) => vec![],
+            Term::Branch(a, _, _) | Term::Return(a) => vec![a],
+            Term::TailCall(_, es) => es.iter().collect(),
+            Term::TailApply(f, es) => std::iter::once(f).chain(es).collect(),
+        }
+    }
+
+    pub fn successors(&self) -> Vec<&String> {
+        match self {
+            Term::Jump(l) => vec![l],
+            Term::Branch(_, l1, l2) => vec![l1, l2],
+            _ => vec![],
+        }
+    }
+}
+
+// The variables live on entry to each block, and at the end of it.
+pub struct Liveness {
+    pub live_in: HashMap<String, HashSet<Var>>,
+    pub live_out: HashMap<String, HashSet<Var>>,
+}
+
+// Walks `b` backwards from the variables live at its end, calling `f` with each
+// statement and the variables live just after it.
+pub fn walk_back(b: &Block, live_out: &HashSet<Var>, mut f: impl FnMut(&Stmt, &HashSet<Var>)) -> HashSet<Var> {
+    let mut live = live_out.clone();
+    live.extend(b.term.operands().into_iter().filter_map(|o| o.var().cloned()));
+    for s in b.stmts.iter().rev() {
+        f(s, &live);
+        if let Stmt::Assign(d, _) = s {
+            live.remove(d);
+        }
+        live.extend(s.operands().into_iter().filter_map(|o| o.var().cloned()));
+    }
+    live
+}
+
+pub fn liveness(f: &Fun) -> Liveness {
+    let mut live_in: HashMap<String, HashSet<Var>> = f.blocks.iter().map(|b| (b.label.to_string(), HashSet::new())).collect();
+    let mut live_out = HashMap::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for b in f.blocks.iter().rev() {
+            let out: HashSet<Var> = b.term.successors().iter().flat_map(|l| live_in[*l].iter().cloned()).collect();
+            let inn = walk_back(b, &out, |_, _| {});
+            if inn != live_in[&b.label] {
+                live_in.insert(b.label.to_string(), inn);
+                changed = true;
+            }
+            live_out.insert(b.label.to_string(), out);
+        }
+    }
+    Liveness { live_in, live_out }
+}
+
+// Collects the variables `e` uses but does not bind, in order of first use.
+pub fn free_vars(e: &Expr, bound: &im::HashSet<String>, out: &mut Vec<String>) {
+    let mut add = |id: &String| {
+        if !bound.contains(id) && !out.contains(id) {
+            out.push(id.to_string());
+        }
+    };
+    match &e.kind {
+        ExprKind::Number(_) | ExprKind::Boolean(_) | ExprKind::Str(_) => {},
+        ExprKind::Id(id) => add(id),
+        ExprKind::Set(id, e1) => {
+            add(id);
+            free_vars(e1, bound, out);
+        },
+        ExprKind::Call(n, es) => {
+            add(n);
+            for e1 in es {
+                free_vars(e1, bound, out);
+            }
+        },
+        ExprKind::Let(bs, body) => {
+            let mut bound = bound.clone();
+            for (id, e1) in bs {
+                free_vars(e1, &bound, out);
+                bound.insert(id.to_string());
+            }
+            free_vars(body, &bound, out);
+        },
+        ExprKind::Lambda(params, body) => {
+            let bound = bound.clone().union(params.iter().cloned().collect());
+            free_vars(body, &bound, out);
+        },
+        ExprKind::UnOp(_, e1) | ExprKind::Loop(e1) | ExprKind::Break(e1) => free_vars(e1, bound, out),
+        ExprKind::BinOp(_, e1, e2) | ExprKind::TupleGet(e1, e2) | ExprKind::VecGet(e1, e2) => {
+            free_vars(e1, bound, out);
+            free_vars(e2, bound, out);
+        },
+        ExprKind::If(e1, e2, e3) | ExprKind::TupleSet(e1, e2, e3) | ExprKind::VecSet(e1, e2, e3) => {
+            free_vars(e1, bound, out);
+            free_vars(e2, bound, out);
+            free_vars(e3, bound, out);
+        },
+        ExprKind::Apply(f, es) => {
+            free_vars(f, bound, out);
+            for e1 in es {
+                free_vars(e1, bound, out);
+            }
+        },
+        ExprKind::Block(es) | ExprKind::Tuple(es) => {
+            for e1 in es {
+                free_vars(e1, bound, out);
+            }
+        },
+    }
+}
+
+// The names `set!` assigns to anywhere in `e`, lambdas excluded.
+fn assigned(e: &Expr, out: &mut HashSet<String>) {
+    match &e.kind {
+        ExprKind::Number(_) | ExprKind::Boolean(_) | ExprKind::Str(_) | ExprKind::Id(_) | ExprKind::Lambda(..) => {},
+        ExprKind::Set(id, e1) => {
+            out.insert(id.to_string());
+            assigned(e1, out);
+        },
+        ExprKind::UnOp(_, e1) | ExprKind::Loop(e1) | ExprKind::Break(e1) => assigned(e1, out),
+        ExprKind::BinOp(_, e1, e2) | ExprKind::TupleGet(e1, e2) | ExprKind::VecGet(e1, e2) => {
+            assigned(e1, out);
+            assigned(e2, out);
+        },
+        ExprKind::If(e1, e2, e3) | ExprKind::TupleSet(e1, e2, e3) | ExprKind::VecSet(e1, e2, e3) => {
+            assigned(e1, out);
+            assigned(e2, out);
+            assigned(e3, out);
+        },
+        ExprKind::Let(bs, body) => {
+            for (_, e1) in bs {
+                assigned(e1, out);
+            }
+            assigned(body, out);
+        },
+        ExprKind::Apply(f, es) => {
+            assigned(f, out);
+            for e1 in es {
+                assigned(e1, out);
+            }
+        },
+        ExprKind::Call(_, es) | ExprKind::Block(es) | ExprKind::Tuple(es) => {
+            for e1 in es {
+                assigned(e1, out);
+            }
+        },
+    }
+}
+
+// The variables of lambdas in `e`, in order of first use.
+fn captures(e: &Expr, out: &mut Vec<String>) {
+    match &e.kind {
+        ExprKind::Number(_) | ExprKind::Boolean(_) | ExprKind::Str(_) | ExprKind::Id(_) => {},
+        ExprKind::Lambda(..) => free_vars(e, &im::HashSet::new(), out),
+        ExprKind::Set(_, e1) | ExprKind::UnOp(_, e1) | ExprKind::Loop(e1) | ExprKind::Break(e1) => captures(e1, out),
+        ExprKind::BinOp(_, e1, e2) | ExprKind::TupleGet(e1, e2) | ExprKind::VecGet(e1, e2) => {
+            captures(e1, out);
+            captures(e2, out);
+        },
+        ExprKind::If(e1, e2, e3) | ExprKind::TupleSet(e1, e2, e3) | ExprKind::VecSet(e1, e2, e3) => {
+            captures(e1, out);
+            captures(e2, out);
+            captures(e3, out);
+        },
+        ExprKind::Let(bs, body) => {
+            for (_, e1) in bs {
+                captures(e1, out);
+            }
+            captures(body, out);
+        },
+        ExprKind::Apply(f, es) => {
+            captures(f, out);
+            for e1 in es {
+                captures(e1, out);
+            }
+        },
+        ExprKind::Call(_, es) | ExprKind::Block(es) | ExprKind::Tuple(es) => {
+            for e1 in es {
+                captures(e1, out);
+            }
+        },
+    }
+}
+
+// State shared by all the functions of a program.
+struct Lowerer<'a> {
+    fnames: &'a HashMap<String, usize>,
+    label: usize,
+    lambdas: Vec<Fun>,
+}
+
+impl Lowerer<'_> {
+    fn new_label(&mut self, s: &str) -> String {
+        let cur_label = self.label;
+        self.label += 1;
+        format!("{s}_{cur_label}")
+    }
+}
+
+struct Ctx<'a> {
+    env: &'a im::HashMap<String, Var>,
+    // the loop's result and its exit
+    brake: Option<&'a (Var, String)>,
+}
+
+struct FunBuilder {
+    blocks: Vec<Block>,
+    label: String,
+    stmts: Vec<Stmt>,
+    vars: HashSet<Var>,
+    tmp: usize,
+    // variables that `set!` may change, whose value is copied when it is used
+    assigned: HashSet<String>,
+    // variables that a lambda captures and `set!` may change, which are kept
+    // in a tuple of one field, a box, that the lambda shares
+    boxed: HashSet<String>,
+    // the variables holding boxes
+    boxes: HashSet<Var>,
+    tail_calls: bool,
+}
+
+impl FunBuilder {
+    fn new(label: String, body: &Expr, tail_calls: bool) -> FunBuilder {
+        let mut set = HashSet::new();
+        assigned(body, &mut set);
+        let mut captured = Vec::new();
+        captures(body, &mut captured);
+        let boxed = captured.into_iter().filter(|id| crate::opt::sets(body, id)).collect();
+        FunBuilder {
+            blocks: Vec::new(),
+            label,
+            stmts: Vec::new(),
+            vars: HashSet::new(),
+            tmp: 0,
+            assigned: set,
+            boxed,
+            boxes: HashSet::new(),
+            tail_calls,
+        }
+    }
+
+    fn tmp(&mut self) -> Var {
+        self.tmp += 1;
+        format!("%{}", self.tmp - 1)
+    }
+
+    // A variable for `name`, renamed if a variable of that name already exists.
+    fn bind(&mut self, name: &str) -> Var {
+        let mut v = name.to_string();
+        let mut i = 1;
+        while self.vars.contains(&v) {
+            v = format!("{name}.{i}");
+            i += 1;
+        }
+        self.vars.insert(v.to_string());
+        v
+    }
+
+    // A variable for `id` holding `op`, or a box holding it.
+    fn define(&mut self, id: &str, op: Operand) -> Var {
+        let v = self.bind(id);
+        if self.boxed.contains(id) {
+            let b = self.assign(Rhs::Tuple(vec![op]));
+            self.assign_to(&v, b);
+            self.boxes.insert(v.to_string());
+        } else {
+            self.assign_to(&v, op);
+        }
+        v
+    }
+
+    fn push(&mut self, s: Stmt) {
+        self.stmts.push(s);
+    }
+
+    fn assign(&mut self, rhs: Rhs) -> Operand {
+        let t = self.tmp();
+        self.push(Stmt::Assign(t.to_string(), rhs));
+        Operand::Var(t)
+    }
+
+    // Assigns `op` to `v`, reusing the statement that just computed it if it was
+    // a temporary.
+    fn assign_to(&mut self, v: &Var, op: Operand) {
+        if let (Operand::Var(t), Some(Stmt::Assign(d, _))) = (&op, self.stmts.last_mut()) {
+            if t.starts_with('%') && d == t {
+                *d = v.to_string();
+                return;
+            }
+        }
+        self.push(Stmt::Assign(v.to_string(), Rhs::Copy(op)));
+    }
+
+    // Ends the current block with `term` and starts the block `next`.
+    fn finish(&mut self, term: Term, next: String) {
+        let label = std::mem::replace(&mut self.label, next);
+        let stmts = std::mem::take(&mut self.stmts);
+        self.blocks.push(Block { label, stmts, term });
+    }
+
+    // Drops the blocks that cannot be reached from the entry.
+    fn into_fun(self, name: String, params: Vec<Var>, captured: Vec<Var>) -> Fun {
+        let mut reached = HashSet::new();
+        let mut todo = vec![&self.blocks[0].label];
+        let by_label: HashMap<&String, &Block> = self.blocks.iter().map(|b| (&b.label, b)).collect();
+        while let Some(l) = todo.pop() {
+            if reached.insert(l.to_string()) {
+                todo.extend(by_label[l].term.successors());
+            }
+        }
+        let blocks = self.blocks.into_iter().filter(|b| reached.contains(&b.label)).collect();
+        Fun { name, params, captured, blocks }
+    }
+
+    fn var(&mut self, v: &Var, id: &str) -> Operand {
+        if self.boxes.contains(v) {
+            self.assign(Rhs::Get(Ty::Tuple, Operand::Var(v.to_string()), Operand::Num(0)))
+        } else if self.assigned.contains(id) {
+            self.assign(Rhs::Copy(Operand::Var(v.to_string())))
+        } else {
+            Operand::Var(v.to_string())
+        }
+    }
+
+    fn exprs(&mut self, l: &mut Lowerer, es: &[Expr], cx: &Ctx) -> Vec<Operand> {
+        es.iter().map(|e| self.expr(l, e, cx)).collect()
+    }
+
+    fn expr(&mut self, l: &mut Lowerer, e: &Expr, cx: &Ctx) -> Operand {
+        match &e.kind {
+            ExprKind::Number(n) => Operand::Num(*n),
+            ExprKind::Boolean(b) => Operand::Bool(*b),
+            ExprKind::Str(s) => self.assign(Rhs::Str(s.to_string())),
+            ExprKind::Id(id) => match cx.env.get(id) {
+                Some(v) => self.var(v, id),
+                None if id == "input" => self.assign(Rhs::Input),
+                None => self.assign(Rhs::Closure(func_label(id), l.fnames[id], vec![])),
+            },
+            ExprKind::Let(bs, body) => {
+                let mut env = cx.env.clone();
+                for (id, e1) in bs {
+                    let op = self.expr(l, e1, &Ctx { env: &env, ..*cx });
+                    let v = self.define(id, op);
+                    env.insert(id.to_string(), v);
+                }
+                self.expr(l, body, &Ctx { env: &env, ..*cx })
+            },
+            ExprKind::UnOp(o, e1) => {
+                let a = self.expr(l, e1, cx);
+                let ty = match o {
+                    Op1::Add1 | Op1::Sub1 => Some(Ty::Num),
+                    Op1::VecLen => Some(Ty::Vec),
+                    Op1::StringLength => Some(Ty::Str),
+                    _ => None,
+                };
+                if let Some(ty) = ty {
+                    self.push(Stmt::Check(ty, a.clone()));
+                }
+                self.assign(Rhs::Prim1(*o, a))
+            },
+            // the right operand is evaluated first
+            ExprKind::BinOp(o, e1, e2) => {
+                let numeric = !matches!(o, Op2::Equal | Op2::StEq | Op2::StringAppend | Op2::StringRef | Op2::MakeVec);
+                let b = self.expr(l, e2, cx);
+                if numeric {
+                    self.push(Stmt::Check(Ty::Num, b.clone()));
+                }
+                let a = self.expr(l, e1, cx);
+                match o {
+                    _ if numeric => self.push(Stmt::Check(Ty::Num, a.clone())),
+                    Op2::StringAppend => {
+                        self.push(Stmt::Check(Ty::Str, a.clone()));
+                        self.push(Stmt::Check(Ty::Str, b.clone()));
+                    },
+                    Op2::StringRef => {
+                        self.push(Stmt::Check(Ty::Str, a.clone()));
+                        self.push(Stmt::Check(Ty::Num, b.clone()));
+                    },
+                    Op2::MakeVec => self.push(Stmt::Check(Ty::Num, a.clone())),
+                    _ => {},
+                }
+                self.assign(Rhs::Prim2(*o, a, b))
+            },
+            ExprKind::If(cond, thn, els) => {
+                let c = self.expr(l, cond, cx);
+                let res = self.tmp();
+                let lthn = l.new_label("ifthen");
+                let lels = l.new_label("ifelse");
+                let lend = l.new_label("ifend");
+                self.finish(Term::Branch(c, lthn.to_string(), lels.to_string()), lthn);
+                let a = self.expr(l, thn, cx);
+                self.assign_to(&res, a);
+                self.finish(Term::Jump(lend.to_string()), lels);
+                let b = self.expr(l, els, cx);
+                self.assign_to(&res, b);
+                self.finish(Term::Jump(lend.to_string()), lend);
+                Operand::Var(res)
+            },
+            ExprKind::Loop(e1) => {
+                let res = self.tmp();
+                let lst = l.new_label("loop");
+                let brake = (res.to_string(), l.new_label("loopend"));
+                self.finish(Term::Jump(lst.to_string()), lst.to_string());
+                self.expr(l, e1, &Ctx { brake: Some(&brake), ..*cx });
+                self.finish(Term::Jump(lst), brake.1.to_string());
+                Operand::Var(res)
+            },
+            ExprKind::Break(e1) => {
+                let (res, lend) = cx.brake.expect("break outside of loop");
+                let a = self.expr(l, e1, cx);
+                self.assign_to(res, a);
+                let dead = l.new_label("dead");
+                self.finish(Term::Jump(lend.to_string()), dead);
+                Operand::Bool(false)
+            },
+            ExprKind::Set(id, e1) => {
+                let a = self.expr(l, e1, cx);
+                let v = &cx.env[id];
+                if self.boxes.contains(v) {
+                    self.assign(Rhs::Set(Ty::Tuple, Operand::Var(v.to_string()), Operand::Num(0), a.clone()));
+                } else {
+                    self.push(Stmt::Assign(v.to_string(), Rhs::Copy(a.clone())));
+                }
+                a
+            },
+            ExprKind::Block(es) => self.exprs(l, es, cx).pop().unwrap(),
+            ExprKind::Call(n, args) if cx.env.contains_key(n) => {
+                let f = self.var(&cx.env[n], n);
+                self.apply(l, f, args, cx)
+            },
+            ExprKind::Call(n, args) => {
+                let args = self.exprs(l, args, cx);
+                self.assign(Rhs::Call(func_label(n), args))
+            },
+            ExprKind::Apply(f, args) => {
+                let f = self.expr(l, f, cx);
+                self.apply(l, f, args, cx)
+            },
+            ExprKind::Lambda(params, body) => {
+                let mut captured = Vec::new();
+                free_vars(body, &params.iter().cloned().collect(), &mut captured);
+                captured.retain(|id| cx.env.contains_key(id));
+                let shared = captured.iter().filter(|id| self.boxes.contains(&cx.env[*id])).cloned().collect();
+                let name = l.new_label("lambda");
+                let fun = lower_fun(l, name.to_string(), &captured, &shared, params, body, true);
+                l.lambdas.push(fun);
+                let values = captured.iter().map(|id| Operand::Var(cx.env[id].to_string())).collect();
+                self.assign(Rhs::Closure(name, params.len(), values))
+            },
+            ExprKind::Tuple(es) => {
+                let es = self.exprs(l, es, cx);
+                self.assign(Rhs::Tuple(es))
+            },
+            ExprKind::TupleGet(e1, i) | ExprKind::VecGet(e1, i) => {
+                let ty = if matches!(e.kind, ExprKind::TupleGet(..)) { Ty::Tuple } else { Ty::Vec };
+                let (obj, idx) = self.index(l, e1, i, ty, cx);
+                self.assign(Rhs::Get(ty, obj, idx))
+            },
+            ExprKind::TupleSet(e1, i, e2) | ExprKind::VecSet(e1, i, e2) => {
+                let ty = if matches!(e.kind, ExprKind::TupleSet(..)) { Ty::Tuple } else { Ty::Vec };
+                let (obj, idx) = self.index(l, e1, i, ty, cx);
+                let v = self.expr(l, e2, cx);
+                self.assign(Rhs::Set(ty, obj, idx, v))
+            },
+        }
+    }
+
+    // Evaluates the index and then the object, and checks both.
+    fn index(&mut self, l: &mut Lowerer, e: &Expr, i: &Expr, ty: Ty, cx: &Ctx) -> (Operand, Operand) {
+        let idx = self.expr(l, i, cx);
+        self.push(Stmt::Check(Ty::Num, idx.clone()));
+        let obj = self.expr(l, e, cx);
+        self.push(Stmt::Check(ty, obj.clone()));
+        self.push(Stmt::CheckIndex(obj.clone(), idx.clone()));
+        (obj, idx)
+    }
+
+    fn apply(&mut self, l: &mut Lowerer, f: Operand, args: &[Expr], cx: &Ctx) -> Operand {
+        let args = self.exprs(l, args, cx);
+        self.push(Stmt::Check(Ty::Fun, f.clone()));
+        self.assign(Rhs::Apply(f, args))
+    }
+
+    // Lowers `e` in tail position, ending the current block.
+    fn tail(&mut self, l: &mut Lowerer, e: &Expr, cx: &Ctx) {
+        match &e.kind {
+            ExprKind::Let(bs, body) => {
+                let mut env = cx.env.clone();
+                for (id, e1) in bs {
+                    let op = self.expr(l, e1, &Ctx { env: &env, ..*cx });
+                    let v = self.define(id, op);
+                    env.insert(id.to_string(), v);
+                }
+                self.tail(l, body, &Ctx { env: &env, ..*cx })
+            },
+            ExprKind::If(cond, thn, els) => {
+                let c = self.expr(l, cond, cx);
+                let lthn = l.new_label("ifthen");
+                let lels = l.new_label("ifelse");
+                self.finish(Term::Branch(c, lthn.to_string(), lels.to_string()), lthn);
+                self.tail(l, thn, cx);
+                self.label = lels;
+                self.tail(l, els, cx);
+            },
+            ExprKind::Block(es) => {
+                let (last, es) = es.split_last().unwrap();
+                self.exprs(l, es, cx);
+                self.tail(l, last, cx);
+            },
+            ExprKind::Call(n, args) if self.tail_calls && !cx.env.contains_key(n) => {
+                let args = self.exprs(l, args, cx);
+                let dead = l.new_label("dead");
+                self.finish(Term::TailCall(func_label(n), args), dead);
+            },
+            ExprKind::Call(_, args) | ExprKind::Apply(_, args) if self.tail_calls => {
+                let f = match &e.kind {
+                    ExprKind::Call(n, _) => self.var(&cx.env[n], n),
+                    ExprKind::Apply(f, _) => self.expr(l, f, cx),
+                    _ => unreachable!(),
+                };
+                let args = self.exprs(l, args, cx);
+                self.push(Stmt::Check(Ty::Fun, f.clone()));
+                let dead = l.new_label("dead");
+                self.finish(Term::TailApply(f, args), dead);
+            },
+            _ => {
+                let a = self.expr(l, e, cx);
+                let dead = l.new_label("dead");
+                self.finish(Term::Return(a), dead);
+            },
+        }
+    }
+}
+
+// `shared` are the captured variables that hold boxes.
+fn lower_fun(l: &mut Lowerer, name: String, captured: &[String], shared: &HashSet<String>, params: &[String], body: &Expr, tail_calls: bool) -> Fun {
+    let entry = l.new_label("entry");
+    let mut b = FunBuilder::new(entry, body, tail_calls);
+    let mut env = im::HashMap::new();
+    let captured: Vec<Var> = captured.iter().map(|id| {
+        let v = b.bind(id);
+        if shared.contains(id) {
+            b.boxes.insert(v.to_string());
+        }
+        env.insert(id.to_string(), v.to_string());
+        v
+    }).collect();
+    let vars: Vec<Var> = params.iter().map(|id| {
+        let v = b.bind(id);
+        env.insert(id.to_string(), v.to_string());
+        v
+    }).collect();
+    // a boxed parameter is moved into its box on entry
+    for (id, p) in params.iter().zip(&vars) {
+        if b.boxed.contains(id) {
+            let v = b.define(id, Operand::Var(p.to_string()));
+            env.insert(id.to_string(), v);
+        }
+    }
+    b.tail(l, body, &Ctx { env: &env, brake: None });
+    b.into_fun(name, vars, captured)
+}
+
+/// Lowers a checked program. Lambdas become functions of their own, after the
+/// top-level ones.
+pub fn lower_prog(p: &crate::ast::Prog) -> Prog {
+    let crate::ast::Prog(fs, e) = p;
+    let fnames: HashMap<String, usize> = fs.iter().map(|f| (f.name.to_string(), f.args.len())).collect();
+    let mut l = Lowerer { fnames: &fnames, label: 0, lambdas: Vec::new() };
+    let mut funs: Vec<Fun> = fs.iter().map(|f| lower_fun(&mut l, func_label(&f.name), &[], &HashSet::new(), &f.args, &f.expr, true)).collect();
+    // the main expression has no argument slots of its own, so it makes no tail calls
+    let main = lower_fun(&mut l, MAIN_LABEL.to_string(), &[], &HashSet::new(), &[], e, false);
+    funs.append(&mut l.lambdas);
+    Prog { funs, main }
+}
+
+fn op1_name(o: &Op1) -> &'static str {
+    match o {
+        Op1::Add1 => "add1",
+        Op1::Sub1 => "sub1",
+        Op1::IsNum => "isnum",
+        Op1::IsBool => "isbool",
+        Op1::IsTuple => "istuple",
+        Op1::IsFun => "isfun",
+        Op1::IsString => "isstring",
+        Op1::StringLength => "string-length",
+        Op1::VecLen => "vec-len",
+        Op1::Print => "print",
+    }
+}
+
+fn op2_name(o: &Op2) -> &'static str {
+    match o {
+        Op2::Plus => "+",
+        Op2::Minus => "-",
+        Op2::Times => "*",
+        Op2::Equal => "==",
+        Op2::Greater => ">",
+        Op2::GreaterEqual => ">=",
+        Op2::Less => "<",
+        Op2::LessEqual => "<=",
+        Op2::StEq => "=",
+        Op2::StringAppend => "string-append",
+        Op2::StringRef => "string-ref",
+        Op2::MakeVec => "make-vec",
+    }
+}
+
+fn ty_name(ty: Ty) -> &'static str {
+    match ty {
+        Ty::Num => "num",
+        Ty::Tuple => "tuple",
+        Ty::Vec => "vec",
+        Ty::Str => "string",
+        Ty::Fun => "fun",
+    }
+}
+
+fn list(es: &[Operand]) -> String {
+    es.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Var(v) => write!(f, "{v}"),
+            Operand::Num(n) => write!(f, "{n}"),
+            Operand::Bool(b) => write!(f, "{b}"),
+        }
+    }
+}
+
+impl fmt::Display for Rhs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rhs::Copy(a) => write!(f, "{a}"),
+            Rhs::Input => write!(f, "input"),
+            Rhs::Prim1(o, a) => write!(f, "{} {a}", op1_name(o)),
+            Rhs::Prim2(o, a, b) => write!(f, "{} {a}, {b}", op2_name(o)),
+            Rhs::Str(s) => write!(f, "{s:?}"),
+            Rhs::Tuple(es) => write!(f, "tuple({})", list(es)),
+            Rhs::Get(ty, a, i) => write!(f, "{}-get {a}, {i}", ty_name(*ty)),
+            Rhs::Set(ty, a, i, v) => write!(f, "{}-set! {a}, {i}, {v}", ty_name(*ty)),
+            Rhs::Closure(l, arity, es) => write!(f, "closure {l}/{arity}[{}]", list(es)),
+            Rhs::Call(l, es) => write!(f, "call {l}({})", list(es)),
+            Rhs::Apply(c, es) => write!(f, "apply {c}({})", list(es)),
+        }
+    }
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stmt::Assign(v, rhs) => write!(f, "{v} := {rhs}"),
+            Stmt::Check(ty, a) => write!(f, "check {} {a}", ty_name(*ty)),
+            Stmt::CheckIndex(a, i) => write!(f, "check-index {a}, {i}"),
+        }
+    }
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Term::Jump(l) => write!(f, "jmp {l}"),
+            Term::Branch(a, l1, l2) => write!(f, "br {a}, {l1}, {l2}"),
+            Term::Return(a) => write!(f, "ret {a}"),
+            Term::TailCall(l, es) => write!(f, "tailcall {l}({})", list(es)),
+            Term::TailApply(c, es) => write!(f, "tailapply {c}({})", list(es)),
+        }
+    }
+}
+
+impl fmt::Display for Fun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fun {}({})", self.name, self.params.join(", "))?;
+        if !self.captured.is_empty() {
+            write!(f, " [{}]", self.captured.join(", "))?;
+        }
+        writeln!(f, ":")?;
+        for b in &self.blocks {
+            writeln!(f, "{}:", b.label)?;
+            for s in &b.stmts {
+                writeln!(f, "  {s}")?;
+            }
+            writeln!(f, "  {}", b.term)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Prog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for fun in &self.funs {
+            writeln!(f, "{fun}")?;
+        }
+        write!(f, "{}", self.main)
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+
+use crate::encode::{self, RelocKind};
+use crate::ir;
+use crate::runtime;
+use crate::x86::{self, ENTRY_LABEL, INPUT_LABEL, RUNTIME_FUNS};
+
+// Runs programs in memory. The code is followed by a trampoline for each
+// runtime function, `jmp [rip+slot]`, and then by a writable page holding the
+// input and the functions' addresses, so that every reference from the code is
+// a 32-bit displacement however far from the runtime the code is mapped.
+
+extern "C" {
+    fn mmap(addr: *mut u8, len: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> *mut u8;
+    fn mprotect(addr: *mut u8, len: usize, prot: i32) -> i32;
+}
+
+const PROT_READ: i32 = 1;
+const PROT_WRITE: i32 = 2;
+const PROT_EXEC: i32 = 4;
+const MAP_PRIVATE: i32 = 0x02;
+#[cfg(target_os = "macos")]
+const MAP_ANONYMOUS: i32 = 0x1000;
+#[cfg(not(target_os = "macos"))]
+const MAP_ANONYMOUS: i32 = 0x20;
+const PAGE_SIZE: usize = 4096;
+// `jmp [rip+disp32]`, padded to 8 bytes
+const TRAMPOLINE_SIZE: usize = 8;
+
+fn address(f: &str) -> usize {
+    match f {
+        "snek_error" => runtime::snek_error as *const () as usize,
+        "snek_print" => runtime::snek_print as *const () as usize,
+        "snek_structural_eq_true" => runtime::snek_structural_eq_true as *const () as usize,
+        "snek_gc" => runtime::snek_gc as *const () as usize,
+        "snek_string_append" => runtime::snek_string_append as *const () as usize,
+        "snek_string_ref" => runtime::snek_string_ref as *const () as usize,
+        _ => panic!("unknown runtime function {f}"),
+    }
+}
+
+/// Compiles a program to machine code in memory and runs it on `input` with a
+/// heap of `heap_size` bytes, printing the result.
+pub fn run(p: &ir::Prog, regalloc: bool, input: i64, heap_size: usize) {
+    let code = encode::encode(&x86::generate(p, regalloc));
+    let trampolines = (code.bytes.len() + 15) & !15;
+    let text_size = (trampolines + RUNTIME_FUNS.len() * TRAMPOLINE_SIZE + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    let size = text_size + PAGE_SIZE;
+
+    let base = unsafe { mmap(std::ptr::null_mut(), size, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0) };
+    if base as isize == -1 {
+        panic!("cannot map memory for the code: {}", std::io::Error::last_os_error());
+    }
+    let mem = unsafe { std::slice::from_raw_parts_mut(base, size) };
+    let base = base as usize;
+    mem[..code.bytes.len()].copy_from_slice(&code.bytes);
+
+    // the data page holds the input, then a slot for each function's address
+    let input_slot = text_size;
+    let mut targets: HashMap<&str, usize> = HashMap::new();
+    for (i, f) in RUNTIME_FUNS.iter().enumerate() {
+        let slot = text_size + 8 * (i + 1);
+        mem[slot..slot + 8].copy_from_slice(&(address(f) as u64).to_le_bytes());
+        let t = trampolines + i * TRAMPOLINE_SIZE;
+        let rel = (slot - (t + 6)) as i32;
+        mem[t..t + 2].copy_from_slice(&[0xff, 0x25]);
+        mem[t + 2..t + 6].copy_from_slice(&rel.to_le_bytes());
+        targets.insert(f, t);
+    }
+
+    for r in &code.relocs {
+        let target = match r.kind {
+            RelocKind::Data if r.symbol == INPUT_LABEL => input_slot,
+            RelocKind::Call => *targets.get(r.symbol.as_str()).unwrap_or_else(|| panic!("undefined label {}", r.symbol)),
+            RelocKind::Data => panic!("undefined label {}", r.symbol),
+        };
+        let rel = (target as i64 + r.addend - r.offset as i64) as i32;
+        mem[r.offset..r.offset + 4].copy_from_slice(&rel.to_le_bytes());
+    }
+
+    unsafe {
+        if mprotect(base as *mut u8, text_size, PROT_READ | PROT_EXEC) != 0 {
+            panic!("cannot make the code executable: {}", std::io::Error::last_os_error());
+        }
+        let entry: unsafe extern "C" fn(i64, *mut u64, *const u64) -> i64 = std::mem::transmute(base + code.labels[ENTRY_LABEL]);
+        runtime::run(entry, input, heap_size);
+    }
+}
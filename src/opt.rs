@@ -0,0 +1,206 @@
+use crate::ast::*;
+use crate::ir::free_vars;
+
+// Folds operations on constants, drops the branches of `if`s whose condition
+// is a constant, and drops `let` bindings that are unused and have no effect.
+// Variables bound to constants and never assigned are replaced by their value.
+// An operation that would fail at run time, on a value of the wrong type or by
+// overflowing, is left for the program to report.
+
+// The range of snek numbers, which are stored shifted left by one.
+const MIN: i64 = i64::MIN >> 1;
+const MAX: i64 = i64::MAX >> 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Const {
+    Num(i64),
+    Bool(bool),
+}
+
+impl Const {
+    fn kind(self) -> ExprKind {
+        match self {
+            Const::Num(n) => ExprKind::Number(n),
+            Const::Bool(b) => ExprKind::Boolean(b),
+        }
+    }
+}
+
+type Env = im::HashMap<String, Const>;
+
+fn constant(e: &Expr) -> Option<Const> {
+    match e.kind {
+        ExprKind::Number(n) => Some(Const::Num(n)),
+        ExprKind::Boolean(b) => Some(Const::Bool(b)),
+        _ => None,
+    }
+}
+
+fn num(n: Option<i64>) -> Option<Const> {
+    n.filter(|n| (MIN..=MAX).contains(n)).map(Const::Num)
+}
+
+fn fold1(o: Op1, a: Const) -> Option<Const> {
+    match (o, a) {
+        (Op1::Add1, Const::Num(n)) => num(n.checked_add(1)),
+        (Op1::Sub1, Const::Num(n)) => num(n.checked_sub(1)),
+        (Op1::IsNum, _) => Some(Const::Bool(matches!(a, Const::Num(_)))),
+        (Op1::IsBool, _) => Some(Const::Bool(matches!(a, Const::Bool(_)))),
+        (Op1::IsTuple | Op1::IsFun | Op1::IsString, _) => Some(Const::Bool(false)),
+        _ => None,
+    }
+}
+
+fn fold2(o: Op2, a: Const, b: Const) -> Option<Const> {
+    match (o, a, b) {
+        // numbers and booleans are only ever equal to themselves
+        (Op2::Equal | Op2::StEq, _, _) => Some(Const::Bool(a == b)),
+        (_, Const::Num(x), Const::Num(y)) => match o {
+            Op2::Plus => num(x.checked_add(y)),
+            Op2::Minus => num(x.checked_sub(y)),
+            Op2::Times => num(x.checked_mul(y)),
+            Op2::Less => Some(Const::Bool(x < y)),
+            Op2::LessEqual => Some(Const::Bool(x <= y)),
+            Op2::Greater => Some(Const::Bool(x > y)),
+            Op2::GreaterEqual => Some(Const::Bool(x >= y)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Whether evaluating `e` can have no effect and cannot fail.
+fn pure(e: &Expr) -> bool {
+    match &e.kind {
+        ExprKind::Number(_) | ExprKind::Boolean(_) | ExprKind::Str(_) | ExprKind::Id(_) | ExprKind::Lambda(..) => true,
+        ExprKind::Tuple(es) => es.iter().all(pure),
+        _ => false,
+    }
+}
+
+/// Whether `e` contains a `set!` of a variable named `x`, in whatever scope.
+pub fn sets(e: &Expr, x: &str) -> bool {
+    match &e.kind {
+        ExprKind::Number(_) | ExprKind::Boolean(_) | ExprKind::Str(_) | ExprKind::Id(_) => false,
+        ExprKind::Set(id, e1) => id == x || sets(e1, x),
+        ExprKind::UnOp(_, e1) | ExprKind::Loop(e1) | ExprKind::Break(e1) | ExprKind::Lambda(_, e1) => sets(e1, x),
+        ExprKind::BinOp(_, e1, e2) | ExprKind::TupleGet(e1, e2) | ExprKind::VecGet(e1, e2) => sets(e1, x) || sets(e2, x),
+        ExprKind::If(e1, e2, e3) | ExprKind::TupleSet(e1, e2, e3) | ExprKind::VecSet(e1, e2, e3) => sets(e1, x) || sets(e2, x) || sets(e3, x),
+        ExprKind::Let(bs, body) => bs.iter().any(|(_, e1)| sets(e1, x)) || sets(body, x),
+        ExprKind::Apply(f, es) => sets(f, x) || es.iter().any(|e1| sets(e1, x)),
+        ExprKind::Call(_, es) | ExprKind::Block(es) | ExprKind::Tuple(es) => es.iter().any(|e1| sets(e1, x)),
+    }
+}
+
+fn uses(e: &Expr, x: &str) -> bool {
+    let mut fv = Vec::new();
+    free_vars(e, &im::HashSet::new(), &mut fv);
+    fv.iter().any(|id| id == x)
+}
+
+fn fold_all(es: Vec<Expr>, env: &Env) -> Vec<Expr> {
+    es.into_iter().map(|e| fold(e, env)).collect()
+}
+
+fn fold_box(e: Expr, env: &Env) -> Box<Expr> {
+    Box::new(fold(e, env))
+}
+
+fn fold_let(bs: Vec<(String, Expr)>, body: Box<Expr>, env: &Env) -> ExprKind {
+    let assigned: Vec<bool> = (0..bs.len())
+        .map(|i| bs[i + 1..].iter().any(|(_, e)| sets(e, &bs[i].0)) || sets(&body, &bs[i].0))
+        .collect();
+    let mut env = env.clone();
+    let mut folded = Vec::new();
+    for ((id, e), assigned) in bs.into_iter().zip(assigned) {
+        let e = fold(e, &env);
+        match constant(&e) {
+            Some(c) if !assigned => env.insert(id.to_string(), c),
+            _ => env.remove(&id),
+        };
+        folded.push((id, e));
+    }
+    let body = fold(*body, &env);
+
+    // from the last binding back, so that dropping one can make an earlier one unused
+    let mut kept: Vec<(String, Expr)> = Vec::new();
+    for (id, e) in folded.into_iter().rev() {
+        let mut used = uses(&body, &id);
+        for (id2, e2) in kept.iter().rev() {
+            if uses(e2, &id) {
+                used = true;
+                break;
+            }
+            if *id2 == id {
+                used = false;
+                break;
+            }
+        }
+        if used || !pure(&e) {
+            kept.push((id, e));
+        }
+    }
+    if kept.is_empty() {
+        return body.kind;
+    }
+    kept.reverse();
+    ExprKind::Let(kept, Box::new(body))
+}
+
+fn fold(e: Expr, env: &Env) -> Expr {
+    let Expr { kind, span } = e;
+    let kind = match kind {
+        ExprKind::Number(_) | ExprKind::Boolean(_) | ExprKind::Str(_) => kind,
+        ExprKind::Id(id) => match env.get(&id) {
+            Some(c) => c.kind(),
+            None => ExprKind::Id(id),
+        },
+        ExprKind::UnOp(o, e1) => {
+            let e1 = fold(*e1, env);
+            match constant(&e1).and_then(|a| fold1(o, a)) {
+                Some(c) => c.kind(),
+                None => ExprKind::UnOp(o, Box::new(e1)),
+            }
+        },
+        ExprKind::BinOp(o, e1, e2) => {
+            let (e1, e2) = (fold(*e1, env), fold(*e2, env));
+            match constant(&e1).zip(constant(&e2)).and_then(|(a, b)| fold2(o, a, b)) {
+                Some(c) => c.kind(),
+                None => ExprKind::BinOp(o, Box::new(e1), Box::new(e2)),
+            }
+        },
+        ExprKind::Let(bs, body) => fold_let(bs, body, env),
+        // only `false` is false
+        ExprKind::If(cond, thn, els) => {
+            let cond = fold(*cond, env);
+            match constant(&cond) {
+                Some(Const::Bool(false)) => return fold(*els, env),
+                Some(_) => return fold(*thn, env),
+                None => ExprKind::If(Box::new(cond), fold_box(*thn, env), fold_box(*els, env)),
+            }
+        },
+        ExprKind::Loop(e1) => ExprKind::Loop(fold_box(*e1, env)),
+        ExprKind::Break(e1) => ExprKind::Break(fold_box(*e1, env)),
+        ExprKind::Set(id, e1) => ExprKind::Set(id, fold_box(*e1, env)),
+        ExprKind::Block(es) => ExprKind::Block(fold_all(es, env)),
+        ExprKind::Call(n, es) => ExprKind::Call(n, fold_all(es, env)),
+        ExprKind::Apply(f, es) => ExprKind::Apply(fold_box(*f, env), fold_all(es, env)),
+        ExprKind::Lambda(params, body) => {
+            let env = params.iter().fold(env.clone(), |env, p| env.without(p));
+            ExprKind::Lambda(params, fold_box(*body, &env))
+        },
+        ExprKind::Tuple(es) => ExprKind::Tuple(fold_all(es, env)),
+        ExprKind::TupleGet(e1, e2) => ExprKind::TupleGet(fold_box(*e1, env), fold_box(*e2, env)),
+        ExprKind::TupleSet(e1, e2, e3) => ExprKind::TupleSet(fold_box(*e1, env), fold_box(*e2, env), fold_box(*e3, env)),
+        ExprKind::VecGet(e1, e2) => ExprKind::VecGet(fold_box(*e1, env), fold_box(*e2, env)),
+        ExprKind::VecSet(e1, e2, e3) => ExprKind::VecSet(fold_box(*e1, env), fold_box(*e2, env), fold_box(*e3, env)),
+    };
+    Expr { kind, span }
+}
+
+/// Optimizes a checked program, for `-O`.
+pub fn optimize(p: Prog) -> Prog {
+    let Prog(fs, e) = p;
+    let fs = fs.into_iter().map(|f| Func { expr: fold(f.expr, &Env::new()), ..f }).collect();
+    Prog(fs, fold(e, &Env::new()))
+}
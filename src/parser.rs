@@ -0,0 +1,230 @@
+use crate::ast::*;
+use crate::error::{CompileError, Span};
+use crate::sexp::Atom::*;
+use crate::sexp::*;
+
+const OP1NAMES: [&str; 10] = ["add1", "sub1", "isnum", "isbool", "istuple", "isfun", "isstring", "string-length", "vec-len", "print"];
+const OP2NAMES: [&str; 12] = ["+", "-", "*", "<", ">", "<=", ">=", "=", "==", "string-append", "string-ref", "make-vec"];
+const KEYWORDS: [&str; 9] = ["true", "false", "input", "let", "if", "block", "loop", "break", "lambda"];
+
+pub fn check_id(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_alphabetic()) && s.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') && !OP1NAMES.contains(&s) && !OP2NAMES.contains(&s) && !KEYWORDS.contains(&s)
+}
+
+// Errors are collected in `errs`; a malformed sub-expression is replaced by a
+// placeholder so that its siblings can still be parsed and checked.
+fn placeholder(span: Span) -> Expr {
+    Expr { kind: ExprKind::Number(0), span }
+}
+
+fn parse_bind(s: &Sexp, errs: &mut Vec<CompileError>) -> Option<(String, Expr)> {
+    match s {
+        Sexp::List(vec, span) => match &vec[..] {
+            [Sexp::Atom(S(id), _), e] if check_id(id.as_str()) => Some((id.to_string(), parse_expr(e, errs))),
+            _ => {
+                errs.push(CompileError::new(*span, "Invalid keyword"));
+                None
+            },
+        },
+        _ => {
+            errs.push(CompileError::new(s.span(), "Invalid let expression"));
+            None
+        },
+    }
+}
+
+fn parse_exprs(es: &[Sexp], errs: &mut Vec<CompileError>) -> Vec<Expr> {
+    es.iter().map(|e| parse_expr(e, errs)).collect()
+}
+
+fn parse_box(s: &Sexp, errs: &mut Vec<CompileError>) -> Box<Expr> {
+    Box::new(parse_expr(s, errs))
+}
+
+pub fn parse_expr(s: &Sexp, errs: &mut Vec<CompileError>) -> Expr {
+    let span = s.span();
+    let mut err = |msg: &str| {
+        errs.push(CompileError::new(span, msg));
+        placeholder(span)
+    };
+    let kind = match s {
+        Sexp::Atom(I(n), _) => {
+            if !(-(1 << 62)..(1 << 62)).contains(n) {
+                return err("Invalid literal");
+            }
+            ExprKind::Number(*n)
+        },
+        Sexp::Atom(F(f), _) => return err(&format!("Invalid literal {f}")),
+        Sexp::Atom(Str(s), _) => ExprKind::Str(s.to_string()),
+        Sexp::Atom(S(n), _) if n == "false" => ExprKind::Boolean(false),
+        Sexp::Atom(S(n), _) if n == "true" => ExprKind::Boolean(true),
+        Sexp::Atom(S(n), _) => ExprKind::Id(n.to_string()),
+        Sexp::List(vec, _) => match &vec[..] {
+            [Sexp::Atom(S(op), _)] if op == "block" => return err("Invalid block expression"),
+            [Sexp::Atom(S(op), _), exprs @ ..] if op == "block" => ExprKind::Block(parse_exprs(exprs, errs)),
+            [Sexp::Atom(S(op), _), exprs @ ..] if op == "tuple" => ExprKind::Tuple(parse_exprs(exprs, errs)),
+            [Sexp::Atom(S(op), _), e] if OP1NAMES.contains(&op.as_str()) => {
+                let o = match op.as_str() {
+                    "add1" => Op1::Add1,
+                    "sub1" => Op1::Sub1,
+                    "isnum" => Op1::IsNum,
+                    "isbool" => Op1::IsBool,
+                    "istuple" => Op1::IsTuple,
+                    "isfun" => Op1::IsFun,
+                    "isstring" => Op1::IsString,
+                    "string-length" => Op1::StringLength,
+                    "vec-len" => Op1::VecLen,
+                    "print" => Op1::Print,
+                    _ => return err("Invalid unary operator"),
+                };
+                ExprKind::UnOp(o, parse_box(e, errs))
+            }
+            [Sexp::Atom(S(op), _), e1, e2] if OP2NAMES.contains(&op.as_str()) => {
+                let o = match op.as_str() {
+                    "+" => Op2::Plus,
+                    "-" => Op2::Minus,
+                    "*" => Op2::Times,
+                    "<" => Op2::Less,
+                    ">" => Op2::Greater,
+                    "<=" => Op2::LessEqual,
+                    ">=" => Op2::GreaterEqual,
+                    "=" => Op2::StEq,
+                    "==" => Op2::Equal,
+                    "string-append" => Op2::StringAppend,
+                    "string-ref" => Op2::StringRef,
+                    "make-vec" => Op2::MakeVec,
+                    // "===" => Op2::StEqEq,
+                    _ => unreachable!(),
+                };
+                ExprKind::BinOp(o, parse_box(e1, errs), parse_box(e2, errs))
+            },
+            [Sexp::Atom(S(op), _), e1, e2] if op == "let" => match e1 {
+                    Sexp::List(b, _) if !b.is_empty() => {
+                        let bs = b.iter().filter_map(|b| parse_bind(b, errs)).collect();
+                        ExprKind::Let(bs, parse_box(e2, errs))
+                    },
+                    _ => return err("Invalid let expression"),
+                },
+            [Sexp::Atom(S(op), _), e1, e2] if op == "set!" => match e1 {
+                    Sexp::Atom(S(n), _) => ExprKind::Set(n.to_string(), parse_box(e2, errs)),
+                    _ => return err("Invalid set! expression"),
+                },
+            [Sexp::Atom(S(op), _), e1, e2] if op == "tuple-get" => ExprKind::TupleGet(parse_box(e1, errs), parse_box(e2, errs)),
+            [Sexp::Atom(S(op), _), e1, e2, e3] if op == "tuple-set!" => ExprKind::TupleSet(parse_box(e1, errs), parse_box(e2, errs), parse_box(e3, errs)),
+            [Sexp::Atom(S(op), _), e1, e2] if op == "vec-get" => ExprKind::VecGet(parse_box(e1, errs), parse_box(e2, errs)),
+            [Sexp::Atom(S(op), _), e1, e2, e3] if op == "vec-set!" => ExprKind::VecSet(parse_box(e1, errs), parse_box(e2, errs), parse_box(e3, errs)),
+            [Sexp::Atom(S(op), _), e] if op == "loop" => ExprKind::Loop(parse_box(e, errs)),
+            [Sexp::Atom(S(op), _), e] if op == "break" => ExprKind::Break(parse_box(e, errs)),
+            [Sexp::Atom(S(op), _), cond, thn, els] if op == "if" => ExprKind::If(
+                parse_box(cond, errs),
+                parse_box(thn, errs),
+                parse_box(els, errs),
+            ),
+            [Sexp::Atom(S(op), _), Sexp::List(params, _), e] if op == "lambda" => {
+                ExprKind::Lambda(parse_params(params, errs), parse_box(e, errs))
+            },
+            [Sexp::Atom(S(op), _), ..] if op == "lambda" => return err("Invalid lambda expression"),
+            [Sexp::Atom(S(op), _), ..] if OP1NAMES.contains(&op.as_str()) => return err(&format!("Invalid: {op} takes 1 argument")),
+            [Sexp::Atom(S(op), _), ..] if OP2NAMES.contains(&op.as_str()) => return err(&format!("Invalid: {op} takes 2 arguments")),
+            [Sexp::Atom(S(n), _), exprs @ ..] => ExprKind::Call(n.to_string(), parse_exprs(exprs, errs)),
+            [f @ Sexp::List(..), exprs @ ..] => ExprKind::Apply(parse_box(f, errs), parse_exprs(exprs, errs)),
+            _ => return err("Invalid expression"),
+        },
+    };
+    Expr { kind, span }
+}
+
+fn parse_params(params: &[Sexp], errs: &mut Vec<CompileError>) -> Vec<String> {
+    for a in params {
+        match a {
+            Sexp::Atom(S(s), _) if check_id(s) => {},
+            _ => errs.push(CompileError::new(a.span(), "Invalid definition: bad parameter name")),
+        }
+    }
+    // malformed parameters are kept so that calls are still checked against the right arity
+    params.iter().map(|a| if let Sexp::Atom(S(s), _) = a { s.to_string() } else { String::new() }).collect()
+}
+
+// `Num`, `Bool`, `String`, `Any`, `(Tuple T ...)`, `(Vec T)` or `(Fun (T ...) T)`.
+fn parse_type(s: &Sexp, errs: &mut Vec<CompileError>) -> Type {
+    match s {
+        Sexp::Atom(S(t), _) if t == "Num" => Type::Num,
+        Sexp::Atom(S(t), _) if t == "Bool" => Type::Bool,
+        Sexp::Atom(S(t), _) if t == "String" => Type::Str,
+        Sexp::Atom(S(t), _) if t == "Any" => Type::Any,
+        Sexp::List(vec, _) => match &vec[..] {
+            [Sexp::Atom(S(t), _), ts @ ..] if t == "Tuple" => Type::Tuple(ts.iter().map(|t| parse_type(t, errs)).collect()),
+            [Sexp::Atom(S(t), _), t1] if t == "Vec" => Type::Vec(Box::new(parse_type(t1, errs))),
+            [Sexp::Atom(S(t), _), Sexp::List(ps, _), r] if t == "Fun" => {
+                Type::Fun(ps.iter().map(|t| parse_type(t, errs)).collect(), Box::new(parse_type(r, errs)))
+            },
+            _ => {
+                errs.push(CompileError::new(s.span(), "Invalid type"));
+                Type::Any
+            },
+        },
+        _ => {
+            errs.push(CompileError::new(s.span(), "Invalid type"));
+            Type::Any
+        },
+    }
+}
+
+// Parameters of a function, each a name or an annotated `(name : T)`.
+fn parse_fun_params(params: &[Sexp], errs: &mut Vec<CompileError>) -> (Vec<String>, Vec<Type>) {
+    let mut names = Vec::new();
+    let mut types = Vec::new();
+    for a in params {
+        let (id, t) = match a {
+            Sexp::List(vec, _) if matches!(&vec[..], [Sexp::Atom(..), Sexp::Atom(S(c), _), _] if c == ":") => (&vec[0], parse_type(&vec[2], errs)),
+            _ => (a, Type::Any),
+        };
+        names.extend(parse_params(std::slice::from_ref(id), errs));
+        types.push(t);
+    }
+    (names, types)
+}
+
+// `(fun (name params ...) body)`, with an optional `-> T` before the body.
+pub fn parse_func(f: &Sexp, errs: &mut Vec<CompileError>) -> Option<Func> {
+    let span = f.span();
+    if let Sexp::List(vec, _) = f {
+        let (sig, ret, e) = match &vec[..] {
+            [Sexp::Atom(S(func), _), Sexp::List(b, _), e] if func == "fun" => (b, Type::Any, e),
+            [Sexp::Atom(S(func), _), Sexp::List(b, _), Sexp::Atom(S(arrow), _), t, e] if func == "fun" && arrow == "->" => {
+                (b, parse_type(t, errs), e)
+            },
+            _ => {
+                errs.push(CompileError::new(span, "Invalid definition"));
+                return None;
+            },
+        };
+        if let [Sexp::Atom(S(n), _), args @ ..] = &sig[..] {
+            let (args, arg_types) = parse_fun_params(args, errs);
+            return Some(Func { name: n.to_string(), args, arg_types, ret, expr: parse_expr(e, errs), span });
+        }
+    }
+    errs.push(CompileError::new(span, "Invalid definition"));
+    None
+}
+
+pub fn is_definition(s: &Sexp) -> bool {
+    matches!(s, Sexp::List(vec, _) if matches!(vec.first(), Some(Sexp::Atom(S(f), _)) if f == "fun"))
+}
+
+pub fn parse_prog(sexps: &[Sexp], errs: &mut Vec<CompileError>) -> Prog {
+    match sexps {
+        [.., e] if is_definition(e) => {
+            errs.push(CompileError::new(e.span(), "Invalid program: missing main expression"));
+            Prog(sexps.iter().filter_map(|f| parse_func(f, errs)).collect(), placeholder(e.span()))
+        },
+        [fs @ .., e] => {
+            let r: Vec<Func> = fs.iter().filter_map(|f| parse_func(f, errs)).collect();
+            Prog(r, parse_expr(e, errs))
+        },
+        [] => {
+            errs.push(CompileError::new(Span::default(), "Invalid program: missing main expression"));
+            Prog(vec![], placeholder(Span::default()))
+        },
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::ast::*;
+use crate::check;
+use crate::error::CompileError;
+use crate::interp::{self, Env, Interp, Value};
+use crate::parser;
+use crate::sexp::{self, Atom, Sexp};
+
+// An interactive session, evaluating each entry with the interpreter. An entry
+// is a `fun` definition, a top-level binding `(define x e)`, or an expression
+// whose value is printed. Definitions and bindings stay in scope for the rest
+// of the session, and a failed entry leaves them as they were. A function may
+// be redefined with another number of parameters, after which the calls that
+// earlier entries make with the old number fail as indirect calls do.
+
+struct Session {
+    interp: Interp<'static>,
+    // the arities of the functions defined so far
+    fnames: HashMap<String, usize>,
+    globals: Env<'static>,
+}
+
+// Entries live as long as the session, since the values they produce may
+// refer to them.
+fn keep<T>(x: T) -> &'static T {
+    Box::leak(Box::new(x))
+}
+
+impl Session {
+    fn check(&self, p: &Prog, errs: &mut Vec<CompileError>) {
+        let globals = self.globals.iter().map(|(x, _)| x.to_string()).collect();
+        check::check_in(p, &self.fnames, globals, errs);
+    }
+
+    fn eval(&mut self, e: &'static Expr) -> Option<Value<'static>> {
+        // lambdas share the bindings, so it is their values that are restored
+        let saved: Vec<Value> = self.globals.iter().map(|(_, v)| v.borrow().clone()).collect();
+        match self.interp.eval(e, &mut self.globals) {
+            Ok(v) => Some(v),
+            Err(err) => {
+                for ((_, v), old) in self.globals.iter().zip(saved) {
+                    *v.borrow_mut() = old;
+                }
+                eprintln!("an error ocurred {err}");
+                None
+            },
+        }
+    }
+
+    fn define(&mut self, id: &str, e: &Sexp, errs: &mut Vec<CompileError>) {
+        let p = keep(Prog(vec![], parser::parse_expr(e, errs)));
+        self.check(p, errs);
+        if errs.is_empty() {
+            if let Some(v) = self.eval(&p.1) {
+                self.globals.retain(|(x, _)| *x != id);
+                self.globals.push((keep(id.to_string()), interp::cell(v)));
+            }
+        }
+    }
+
+    fn entry(&mut self, s: &Sexp, errs: &mut Vec<CompileError>) {
+        if parser::is_definition(s) {
+            let Some(f) = parser::parse_func(s, errs) else { return };
+            let p = keep(Prog(vec![f], Expr { kind: ExprKind::Boolean(false), span: s.span() }));
+            self.check(p, errs);
+            if errs.is_empty() {
+                let f = &p.0[0];
+                self.fnames.insert(f.name.to_string(), f.args.len());
+                self.interp.define(f);
+            }
+            return;
+        }
+        if let Sexp::List(vec, span) = s {
+            match &vec[..] {
+                [Sexp::Atom(Atom::S(op), _), Sexp::Atom(Atom::S(id), _), e] if op == "define" && parser::check_id(id) => {
+                    return self.define(id, e, errs);
+                },
+                [Sexp::Atom(Atom::S(op), _), ..] if op == "define" => {
+                    errs.push(CompileError::new(*span, "Invalid define"));
+                    return;
+                },
+                _ => {},
+            }
+        }
+        let p = keep(Prog(vec![], parser::parse_expr(s, errs)));
+        self.check(p, errs);
+        if errs.is_empty() {
+            if let Some(v) = self.eval(&p.1) {
+                println!("{v}");
+            }
+        }
+    }
+}
+
+/// Reads entries from standard input until it ends, printing the value of
+/// each expression.
+pub fn run(input: Value<'static>) -> io::Result<()> {
+    let mut session = Session { interp: Interp::new(input), fnames: HashMap::new(), globals: Vec::new() };
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut src = String::new();
+    loop {
+        // prompts go to stderr, so that the output is only what snek prints
+        eprint!("{}", if src.is_empty() { "> " } else { ". " });
+        io::stderr().flush()?;
+        match lines.next() {
+            Some(line) => {
+                src.push_str(&line?);
+                src.push('\n');
+                if sexp::is_incomplete(&src) {
+                    continue;
+                }
+            },
+            None if src.trim().is_empty() => break,
+            None => {},
+        }
+        let mut errs = Vec::new();
+        for s in sexp::parse(&src, &mut errs) {
+            if !errs.is_empty() {
+                break;
+            }
+            session.entry(&s, &mut errs);
+        }
+        for e in &errs {
+            eprint!("{}", e.render("repl", &src));
+        }
+        src.clear();
+    }
+    eprintln!();
+    Ok(())
+}
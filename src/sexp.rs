@@ -0,0 +1,155 @@
+use crate::error::{CompileError, Span};
+
+#[derive(Debug)]
+pub enum Atom {
+    S(String),
+    Str(String),
+    I(i64),
+    F(f64),
+}
+
+/// An s-expression together with the source range it was read from.
+#[derive(Debug)]
+pub enum Sexp {
+    Atom(Atom, Span),
+    List(Vec<Sexp>, Span),
+}
+
+impl Sexp {
+    pub fn span(&self) -> Span {
+        match self {
+            Sexp::Atom(_, span) | Sexp::List(_, span) => *span,
+        }
+    }
+}
+
+struct Reader<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) {
+        if let Some(c) = self.peek() {
+            self.pos += c.len_utf8();
+        }
+    }
+
+    fn skip_space(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == ';' {
+                while !matches!(self.peek(), None | Some('\n')) { self.bump(); }
+            } else if c.is_whitespace() {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn read_sexp(&mut self) -> Result<Sexp, CompileError> {
+        let start = self.pos;
+        match self.peek() {
+            None => Err(CompileError::new(Span::new(start, start), "Invalid s-expression: unexpected end of file")),
+            Some(')') => Err(CompileError::new(Span::new(start, start + 1), "Invalid s-expression: unmatched `)`")),
+            Some('(') => {
+                self.bump();
+                let mut vec = Vec::new();
+                loop {
+                    self.skip_space();
+                    match self.peek() {
+                        None => return Err(CompileError::new(Span::new(start, start + 1), "Invalid s-expression: unclosed `(`")),
+                        Some(')') => {
+                            self.bump();
+                            return Ok(Sexp::List(vec, Span::new(start, self.pos)));
+                        },
+                        Some(_) => vec.push(self.read_sexp()?),
+                    }
+                }
+            },
+            Some('"') => {
+                self.bump();
+                let mut s = String::new();
+                loop {
+                    match self.peek() {
+                        None => return Err(CompileError::new(Span::new(start, self.pos), "Invalid s-expression: unterminated string")),
+                        Some('"') => break,
+                        Some('\\') => {
+                            self.bump();
+                            match self.peek() {
+                                Some(c @ ('"' | '\\')) => s.push(c),
+                                Some('n') => s.push('\n'),
+                                Some('t') => s.push('\t'),
+                                Some(c) => { s.push('\\'); s.push(c); },
+                                None => continue,
+                            }
+                        },
+                        Some(c) => s.push(c),
+                    }
+                    self.bump();
+                }
+                self.bump();
+                Ok(Sexp::Atom(Atom::Str(s), Span::new(start, self.pos)))
+            },
+            Some(_) => {
+                while matches!(self.peek(), Some(c) if !c.is_whitespace() && c != '(' && c != ')' && c != ';') {
+                    self.bump();
+                }
+                let span = Span::new(start, self.pos);
+                let s = &self.src[start..self.pos];
+                let atom = if let Ok(i) = s.parse::<i64>() {
+                    Atom::I(i)
+                } else if let Ok(f) = s.parse::<f64>() {
+                    Atom::F(f)
+                } else {
+                    Atom::S(s.to_string())
+                };
+                Ok(Sexp::Atom(atom, span))
+            },
+        }
+    }
+}
+
+/// Reads every top-level s-expression in `src`.
+///
+/// After a malformed top-level form, reading resumes at the next line starting
+/// with `(fun`, so that later definitions are still checked.
+pub fn parse(src: &str, errs: &mut Vec<CompileError>) -> Vec<Sexp> {
+    let mut r = Reader { src, pos: 0 };
+    let mut sexps = Vec::new();
+    r.skip_space();
+    while r.peek().is_some() {
+        let start = r.pos;
+        match r.read_sexp() {
+            Ok(s) => sexps.push(s),
+            Err(e) => {
+                errs.push(e);
+                if r.peek() == Some(')') && r.pos == start {
+                    r.bump();
+                } else {
+                    r.pos = src[start + 1..].find("\n(fun").map_or(src.len(), |i| start + 2 + i);
+                }
+            },
+        }
+        r.skip_space();
+    }
+    sexps
+}
+
+/// Whether `src` stops in the middle of an s-expression, so that a reader of
+/// lines should wait for the next one.
+pub fn is_incomplete(src: &str) -> bool {
+    let mut r = Reader { src, pos: 0 };
+    r.skip_space();
+    while r.peek().is_some() {
+        if r.read_sexp().is_err() {
+            return r.peek().is_none();
+        }
+        r.skip_space();
+    }
+    false
+}
@@ -0,0 +1,399 @@
+// The bytecode format written with `--target=bytecode` and run by `snek-vm`.
+//
+// Each function has a frame of `locals` slots: its arguments, then the closure
+// it was called through, then its variables. Instructions take their operands
+// from a stack above the slots and push their results there. Values are tagged
+// as they are in compiled code.
+//
+// A `.snekc` file is the magic number and a version byte, then the functions,
+// the first of which is the program's entry. Integers are little-endian.
+// Programs are verified when they are read, so that no file can make the VM
+// use a slot, function or stack entry that does not exist, or forge a heap
+// value from a constant.
+
+pub const MAGIC: &[u8; 4] = b"SNKC";
+pub const VERSION: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instr {
+    // numbers, booleans and the empty tuple; never a heap value
+    Const(i64),
+    Load(u32),
+    Store(u32),
+    Input,
+    // a value captured by the closure the function was called through
+    Captured(u32),
+
+    Add1,
+    Sub1,
+    IsNum,
+    IsBool,
+    IsTuple,
+    IsFun,
+    IsString,
+    StringLength,
+    VecLen,
+    Print,
+
+    Plus,
+    Minus,
+    Times,
+    Equal,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    StEq,
+    StringAppend,
+    StringRef,
+    MakeVec,
+
+    Str(Vec<u8>),
+    // pops the fields, the first deepest
+    Tuple(u32),
+    // the object below the index
+    Get,
+    // the object, the index, then the value, which is pushed back
+    Set,
+    // pops the function's captured values
+    Closure(u32),
+    // pops the arguments
+    Call(u32, u32),
+    // pops the closure, above the arguments
+    Apply(u32),
+
+    CheckNum,
+    CheckTuple,
+    CheckVec,
+    CheckStr,
+    CheckFun,
+    CheckIndex,
+
+    Jump(u32),
+    JumpIfFalse(u32),
+    Return,
+    TailCall(u32, u32),
+    TailApply(u32),
+}
+
+#[derive(Debug)]
+pub struct Fun {
+    pub name: String,
+    pub arity: u32,
+    pub captured: u32,
+    // the slots of a frame, the arguments and the closure included
+    pub locals: u32,
+    // the most operands on the stack at once
+    pub stack: u32,
+    pub code: Vec<Instr>,
+}
+
+#[derive(Debug)]
+pub struct Program {
+    pub funs: Vec<Fun>,
+}
+
+impl Instr {
+    // How many operands it pops, and whether execution can go on to the next
+    // instruction. Every instruction that continues pushes at most one value.
+    fn effect(&self, p: &Program) -> (u32, bool) {
+        use Instr::*;
+        match self {
+            Const(_) | Load(_) | Input | Captured(_) | Str(_) => (0, true),
+            Store(_) | CheckNum | CheckTuple | CheckVec | CheckStr | CheckFun | JumpIfFalse(_) => (1, true),
+            Add1 | Sub1 | IsNum | IsBool | IsTuple | IsFun | IsString | StringLength | VecLen | Print => (1, true),
+            Plus | Minus | Times | Equal | Less | LessEqual | Greater | GreaterEqual | StEq | StringAppend
+            | StringRef | MakeVec | Get | CheckIndex => (2, true),
+            Set => (3, true),
+            Tuple(n) | Call(_, n) => (*n, true),
+            Closure(f) => (p.funs[*f as usize].captured, true),
+            Apply(n) => (n + 1, true),
+            Jump(_) => (0, false),
+            Return => (1, false),
+            TailCall(_, n) => (*n, false),
+            TailApply(n) => (n + 1, false),
+        }
+    }
+
+    fn pushes(&self) -> u32 {
+        use Instr::*;
+        match self {
+            Store(_) | CheckNum | CheckTuple | CheckVec | CheckStr | CheckFun | CheckIndex | Jump(_)
+            | JumpIfFalse(_) | Return | TailCall(..) | TailApply(_) => 0,
+            _ => 1,
+        }
+    }
+
+    fn target(&self) -> Option<u32> {
+        match self {
+            Instr::Jump(l) | Instr::JumpIfFalse(l) => Some(*l),
+            _ => None,
+        }
+    }
+}
+
+/// The most operands `f` has on its stack at once. Fails if an instruction
+/// refers to something that does not exist, pops more than there is, or if
+/// two paths reach an instruction with different stack depths.
+pub fn max_stack(p: &Program, f: &Fun) -> Result<u32, String> {
+    let err = |pc: usize, msg: &str| Err(format!("invalid bytecode in {} at {pc}: {msg}", f.name));
+    let fun = |i: &u32| p.funs.get(*i as usize);
+    let mut depth: Vec<Option<u32>> = vec![None; f.code.len()];
+    let mut work = vec![(0, 0)];
+    let mut max = 0;
+    while let Some((pc, d)) = work.pop() {
+        let Some(instr) = f.code.get(pc) else {
+            return err(pc, "runs past the end of the function");
+        };
+        match depth[pc] {
+            Some(e) if e == d => continue,
+            Some(_) => return err(pc, "reached with different stack depths"),
+            None => depth[pc] = Some(d),
+        }
+        let ok = match instr {
+            Instr::Const(v) => v & 1 == 0 || matches!(v, 1 | 3 | 7),
+            Instr::Load(s) | Instr::Store(s) => *s < f.locals,
+            Instr::Captured(i) => *i < f.captured,
+            Instr::Closure(g) => fun(g).is_some(),
+            Instr::Call(g, n) | Instr::TailCall(g, n) => fun(g).map_or(false, |g| g.arity == *n && g.captured == 0),
+            Instr::Jump(l) | Instr::JumpIfFalse(l) => (*l as usize) < f.code.len(),
+            _ => true,
+        };
+        if !ok {
+            return err(pc, &format!("bad operand in {instr:?}"));
+        }
+        let (pops, next) = instr.effect(p);
+        if d < pops {
+            return err(pc, "pops more operands than there are");
+        }
+        let d = d - pops + instr.pushes();
+        max = max.max(d);
+        if let Some(l) = instr.target() {
+            work.push((l as usize, d));
+        }
+        if next {
+            work.push((pc + 1, d));
+        }
+    }
+    Ok(max)
+}
+
+/// Checks that every function is well formed, and keeps to its frame.
+pub fn verify(p: &Program) -> Result<(), String> {
+    match p.funs.first() {
+        None => return Err("invalid bytecode: no entry function".to_string()),
+        Some(main) if main.arity != 0 || main.captured != 0 => {
+            return Err("invalid bytecode: the entry function takes arguments".to_string())
+        },
+        _ => {},
+    }
+    for f in &p.funs {
+        if f.locals <= f.arity {
+            return Err(format!("invalid bytecode in {}: too few locals", f.name));
+        }
+        if max_stack(p, f)? > f.stack {
+            return Err(format!("invalid bytecode in {}: the stack outgrows the frame", f.name));
+        }
+    }
+    Ok(())
+}
+
+fn opcode(i: &Instr) -> u8 {
+    use Instr::*;
+    match i {
+        Const(_) => 0,
+        Load(_) => 1,
+        Store(_) => 2,
+        Input => 3,
+        Captured(_) => 4,
+        Add1 => 5,
+        Sub1 => 6,
+        IsNum => 7,
+        IsBool => 8,
+        IsTuple => 9,
+        IsFun => 10,
+        IsString => 11,
+        StringLength => 12,
+        VecLen => 13,
+        Print => 14,
+        Plus => 15,
+        Minus => 16,
+        Times => 17,
+        Equal => 18,
+        Less => 19,
+        LessEqual => 20,
+        Greater => 21,
+        GreaterEqual => 22,
+        StEq => 23,
+        StringAppend => 24,
+        StringRef => 25,
+        MakeVec => 26,
+        Str(_) => 27,
+        Tuple(_) => 28,
+        Get => 29,
+        Set => 30,
+        Closure(_) => 31,
+        Call(..) => 32,
+        Apply(_) => 33,
+        CheckNum => 34,
+        CheckTuple => 35,
+        CheckVec => 36,
+        CheckStr => 37,
+        CheckFun => 38,
+        CheckIndex => 39,
+        Jump(_) => 40,
+        JumpIfFalse(_) => 41,
+        Return => 42,
+        TailCall(..) => 43,
+        TailApply(_) => 44,
+    }
+}
+
+fn put_u32(out: &mut Vec<u8>, n: u32) {
+    out.extend_from_slice(&n.to_le_bytes());
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_u32(out, bytes.len() as u32);
+    out.extend_from_slice(bytes);
+}
+
+/// The contents of a `.snekc` file holding `p`.
+pub fn write(p: &Program) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    put_u32(&mut out, p.funs.len() as u32);
+    for f in &p.funs {
+        put_bytes(&mut out, f.name.as_bytes());
+        for n in [f.arity, f.captured, f.locals, f.stack, f.code.len() as u32] {
+            put_u32(&mut out, n);
+        }
+        for i in &f.code {
+            out.push(opcode(i));
+            match i {
+                Instr::Const(v) => out.extend_from_slice(&v.to_le_bytes()),
+                Instr::Str(s) => put_bytes(&mut out, s),
+                Instr::Load(n) | Instr::Store(n) | Instr::Captured(n) | Instr::Tuple(n) | Instr::Closure(n)
+                | Instr::Apply(n) | Instr::Jump(n) | Instr::JumpIfFalse(n) | Instr::TailApply(n) => {
+                    put_u32(&mut out, *n)
+                },
+                Instr::Call(f, n) | Instr::TailCall(f, n) => {
+                    put_u32(&mut out, *f);
+                    put_u32(&mut out, *n);
+                },
+                _ => {},
+            }
+        }
+    }
+    out
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let end = self.pos.checked_add(n).filter(|end| *end <= self.bytes.len());
+        let end = end.ok_or("invalid bytecode: the file is truncated")?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> Result<i64, String> {
+        Ok(i64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], String> {
+        let n = self.u32()?;
+        self.take(n as usize)
+    }
+
+    fn instr(&mut self) -> Result<Instr, String> {
+        use Instr::*;
+        let op = self.u8()?;
+        Ok(match op {
+            0 => Const(self.i64()?),
+            1 => Load(self.u32()?),
+            2 => Store(self.u32()?),
+            3 => Input,
+            4 => Captured(self.u32()?),
+            5 => Add1,
+            6 => Sub1,
+            7 => IsNum,
+            8 => IsBool,
+            9 => IsTuple,
+            10 => IsFun,
+            11 => IsString,
+            12 => StringLength,
+            13 => VecLen,
+            14 => Print,
+            15 => Plus,
+            16 => Minus,
+            17 => Times,
+            18 => Equal,
+            19 => Less,
+            20 => LessEqual,
+            21 => Greater,
+            22 => GreaterEqual,
+            23 => StEq,
+            24 => StringAppend,
+            25 => StringRef,
+            26 => MakeVec,
+            27 => Str(self.bytes()?.to_vec()),
+            28 => Tuple(self.u32()?),
+            29 => Get,
+            30 => Set,
+            31 => Closure(self.u32()?),
+            32 => Call(self.u32()?, self.u32()?),
+            33 => Apply(self.u32()?),
+            34 => CheckNum,
+            35 => CheckTuple,
+            36 => CheckVec,
+            37 => CheckStr,
+            38 => CheckFun,
+            39 => CheckIndex,
+            40 => Jump(self.u32()?),
+            41 => JumpIfFalse(self.u32()?),
+            42 => Return,
+            43 => TailCall(self.u32()?, self.u32()?),
+            44 => TailApply(self.u32()?),
+            _ => return Err(format!("invalid bytecode: unknown opcode {op}")),
+        })
+    }
+}
+
+/// Reads and verifies the program in the contents of a `.snekc` file.
+pub fn read(bytes: &[u8]) -> Result<Program, String> {
+    let mut r = Reader { bytes, pos: 0 };
+    if r.take(MAGIC.len()).ok() != Some(MAGIC) {
+        return Err("not a bytecode file".to_string());
+    }
+    let version = r.u8()?;
+    if version != VERSION {
+        return Err(format!("unsupported bytecode version {version}"));
+    }
+    let mut funs = Vec::new();
+    for _ in 0..r.u32()? {
+        let name = String::from_utf8_lossy(r.bytes()?).into_owned();
+        let (arity, captured, locals, stack) = (r.u32()?, r.u32()?, r.u32()?, r.u32()?);
+        let code = (0..r.u32()?).map(|_| r.instr()).collect::<Result<_, _>>()?;
+        funs.push(Fun { name, arity, captured, locals, stack, code });
+    }
+    if r.pos != bytes.len() {
+        return Err("invalid bytecode: trailing bytes".to_string());
+    }
+    let p = Program { funs };
+    verify(&p)?;
+    Ok(p)
+}
@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast::*;
+use crate::error::{CompileError, Span};
+use crate::opt::sets;
+
+// The optional static type checker, run on well-formed programs with
+// `--typecheck`. It is gradual: `Any` is compatible with every type, so that
+// unannotated code is only rejected where its types are known to clash.
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |ts: &[Type]| ts.iter().map(|t| format!(" {t}")).collect::<String>();
+        match self {
+            Type::Any => write!(f, "Any"),
+            Type::Num => write!(f, "Num"),
+            Type::Bool => write!(f, "Bool"),
+            Type::Str => write!(f, "String"),
+            Type::Tuple(ts) => write!(f, "(Tuple{})", list(ts)),
+            Type::Vec(t) => write!(f, "(Vec {t})"),
+            Type::Fun(ps, r) => write!(f, "(Fun ({}) {r})", list(ps).trim_start()),
+        }
+    }
+}
+
+// Whether a value of type `a` may be used where `b` is expected.
+fn consistent(a: &Type, b: &Type) -> bool {
+    match (a, b) {
+        (Type::Any, _) | (_, Type::Any) => true,
+        (Type::Tuple(xs), Type::Tuple(ys)) => xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| consistent(x, y)),
+        (Type::Vec(x), Type::Vec(y)) => consistent(x, y),
+        (Type::Fun(ps, r), Type::Fun(qs, s)) => ps.len() == qs.len() && ps.iter().zip(qs).all(|(p, q)| consistent(p, q)) && consistent(r, s),
+        _ => a == b,
+    }
+}
+
+// The type of a value that is either an `a` or a `b`.
+fn join(a: Type, b: Type) -> Type {
+    if a == b { a } else { Type::Any }
+}
+
+type Vars = im::HashMap<String, Type>;
+
+struct Checker<'a> {
+    funs: &'a HashMap<String, Type>,
+    // the types of the values broken out of each enclosing loop
+    breaks: Vec<Vec<Type>>,
+    errs: &'a mut Vec<CompileError>,
+}
+
+impl Checker<'_> {
+    fn expect(&mut self, found: &Type, expected: &Type, span: Span) {
+        if !consistent(found, expected) {
+            self.errs.push(CompileError::new(span, format!("Type error: expected {expected}, found {found}")));
+        }
+    }
+
+    fn mismatch(&mut self, what: &str, found: &Type, span: Span) -> Type {
+        self.errs.push(CompileError::new(span, format!("Type error: expected {what}, found {found}")));
+        Type::Any
+    }
+
+    fn apply(&mut self, f: Type, args: &[Expr], span: Span, vars: &Vars) -> Type {
+        let ts: Vec<Type> = args.iter().map(|a| self.expr(a, vars)).collect();
+        match f {
+            Type::Fun(ps, r) if ps.len() == ts.len() => {
+                for ((t, p), a) in ts.iter().zip(&ps).zip(args) {
+                    self.expect(t, p, a.span);
+                }
+                *r
+            },
+            Type::Fun(ps, _) => {
+                self.errs.push(CompileError::new(span, format!("Type error: function takes {} arguments but {} were given", ps.len(), ts.len())));
+                Type::Any
+            },
+            Type::Any => Type::Any,
+            t => self.mismatch("a function", &t, span),
+        }
+    }
+
+    // The type of the element of a tuple of type `t` at index `i`.
+    fn field(&mut self, t: Type, i: &Expr, span: Span) -> Type {
+        match (t, &i.kind) {
+            (Type::Tuple(ts), ExprKind::Number(n)) => match usize::try_from(*n).ok().and_then(|n| ts.get(n)) {
+                Some(t) => t.clone(),
+                None => {
+                    self.errs.push(CompileError::new(span, format!("Type error: index {n} out of range for {}", Type::Tuple(ts))));
+                    Type::Any
+                },
+            },
+            (Type::Tuple(ts), _) => ts.into_iter().reduce(join).unwrap_or(Type::Any),
+            (Type::Any, _) => Type::Any,
+            (t, _) => self.mismatch("a tuple", &t, span),
+        }
+    }
+
+    fn element(&mut self, t: Type, span: Span) -> Type {
+        match t {
+            Type::Vec(t) => *t,
+            Type::Any => Type::Any,
+            t => self.mismatch("a vector", &t, span),
+        }
+    }
+
+    fn expr(&mut self, e: &Expr, vars: &Vars) -> Type {
+        match &e.kind {
+            ExprKind::Number(_) => Type::Num,
+            ExprKind::Boolean(_) => Type::Bool,
+            ExprKind::Str(_) => Type::Str,
+            ExprKind::Id(id) => vars.get(id).or_else(|| self.funs.get(id)).cloned().unwrap_or(Type::Any),
+            ExprKind::Let(bs, body) => {
+                let mut vars = vars.clone();
+                for (i, (id, e1)) in bs.iter().enumerate() {
+                    let t = self.expr(e1, &vars);
+                    // a variable assigned later may come to hold any value
+                    let assigned = bs[i + 1..].iter().any(|(_, e2)| sets(e2, id)) || sets(body, id);
+                    vars.insert(id.to_string(), if assigned { Type::Any } else { t });
+                }
+                self.expr(body, &vars)
+            },
+            ExprKind::UnOp(o, e1) => {
+                let t = self.expr(e1, vars);
+                match o {
+                    Op1::Add1 | Op1::Sub1 => {
+                        self.expect(&t, &Type::Num, e1.span);
+                        Type::Num
+                    },
+                    Op1::IsNum | Op1::IsBool | Op1::IsTuple | Op1::IsFun | Op1::IsString => Type::Bool,
+                    Op1::StringLength => {
+                        self.expect(&t, &Type::Str, e1.span);
+                        Type::Num
+                    },
+                    Op1::VecLen => {
+                        self.element(t, e1.span);
+                        Type::Num
+                    },
+                    Op1::Print => t,
+                }
+            },
+            ExprKind::BinOp(o, e1, e2) => {
+                let (t1, t2) = (self.expr(e1, vars), self.expr(e2, vars));
+                let (want1, want2, result) = match o {
+                    Op2::Plus | Op2::Minus | Op2::Times => (Type::Num, Type::Num, Type::Num),
+                    Op2::Less | Op2::LessEqual | Op2::Greater | Op2::GreaterEqual => (Type::Num, Type::Num, Type::Bool),
+                    Op2::Equal | Op2::StEq => (Type::Any, Type::Any, Type::Bool),
+                    Op2::StringAppend => (Type::Str, Type::Str, Type::Str),
+                    Op2::StringRef => (Type::Str, Type::Num, Type::Str),
+                    Op2::MakeVec => (Type::Num, Type::Any, Type::Vec(Box::new(Type::Any))),
+                };
+                self.expect(&t1, &want1, e1.span);
+                self.expect(&t2, &want2, e2.span);
+                result
+            },
+            // any value can be a condition; only `false` is false
+            ExprKind::If(cond, thn, els) => {
+                self.expr(cond, vars);
+                let t = self.expr(thn, vars);
+                let u = self.expr(els, vars);
+                join(t, u)
+            },
+            ExprKind::Loop(e1) => {
+                self.breaks.push(Vec::new());
+                self.expr(e1, vars);
+                self.breaks.pop().unwrap().into_iter().reduce(join).unwrap_or(Type::Any)
+            },
+            ExprKind::Break(e1) => {
+                let t = self.expr(e1, vars);
+                self.breaks.last_mut().unwrap().push(t);
+                Type::Any
+            },
+            ExprKind::Set(id, e1) => {
+                let t = self.expr(e1, vars);
+                let want = vars.get(id).cloned().unwrap_or(Type::Any);
+                self.expect(&t, &want, e1.span);
+                t
+            },
+            ExprKind::Block(es) => es.iter().map(|e1| self.expr(e1, vars)).last().unwrap_or(Type::Any),
+            ExprKind::Call(n, args) => {
+                let f = vars.get(n).or_else(|| self.funs.get(n)).cloned().unwrap_or(Type::Any);
+                self.apply(f, args, e.span, vars)
+            },
+            ExprKind::Apply(f, args) => {
+                let f = self.expr(f, vars);
+                self.apply(f, args, e.span, vars)
+            },
+            // lambda parameters are not annotated
+            ExprKind::Lambda(params, body) => {
+                let inner = params.iter().fold(vars.clone(), |vars, p| vars.update(p.to_string(), Type::Any));
+                let breaks = std::mem::take(&mut self.breaks);
+                let r = self.expr(body, &inner);
+                self.breaks = breaks;
+                Type::Fun(vec![Type::Any; params.len()], Box::new(r))
+            },
+            // a field keeps the type it is built with, since `tuple-set!` may
+            // only replace it by a value of that type
+            ExprKind::Tuple(es) => Type::Tuple(es.iter().map(|e1| self.expr(e1, vars)).collect()),
+            ExprKind::TupleGet(e1, i) => {
+                let t = self.expr(e1, vars);
+                let ti = self.expr(i, vars);
+                self.expect(&ti, &Type::Num, i.span);
+                self.field(t, i, e1.span)
+            },
+            ExprKind::TupleSet(e1, i, v) => {
+                let t = self.expr(e1, vars);
+                let ti = self.expr(i, vars);
+                self.expect(&ti, &Type::Num, i.span);
+                let tv = self.expr(v, vars);
+                let tf = self.field(t, i, e1.span);
+                self.expect(&tv, &tf, v.span);
+                tv
+            },
+            ExprKind::VecGet(e1, i) => {
+                let t = self.expr(e1, vars);
+                let ti = self.expr(i, vars);
+                self.expect(&ti, &Type::Num, i.span);
+                self.element(t, e1.span)
+            },
+            ExprKind::VecSet(e1, i, v) => {
+                let t = self.expr(e1, vars);
+                let ti = self.expr(i, vars);
+                self.expect(&ti, &Type::Num, i.span);
+                let tv = self.expr(v, vars);
+                let te = self.element(t, e1.span);
+                self.expect(&tv, &te, v.span);
+                tv
+            },
+        }
+    }
+}
+
+/// Checks the types of a well-formed program, collecting every error.
+pub fn typecheck_prog(p: &Prog, errs: &mut Vec<CompileError>) {
+    let Prog(fs, e) = p;
+    let funs: HashMap<String, Type> = fs.iter().map(|f| (f.name.to_string(), Type::Fun(f.arg_types.clone(), Box::new(f.ret.clone())))).collect();
+    let mut c = Checker { funs: &funs, breaks: Vec::new(), errs };
+    let input = Vars::unit("input".to_string(), Type::Any);
+
+    for f in fs {
+        let vars = f.args.iter().cloned().zip(f.arg_types.iter().cloned()).fold(input.clone(), |vars, (a, t)| vars.update(a, t));
+        let t = c.expr(&f.expr, &vars);
+        c.expect(&t, &f.ret, f.expr.span);
+    }
+    c.expr(e, &input);
+}
@@ -0,0 +1,411 @@
+use crate::runtime::*;
+use crate::snekc::{Instr, Program};
+
+// Runs verified bytecode. Objects are laid out on a heap as they are by
+// compiled code, so that the runtime prints, compares and collects them. The
+// frames and operands are on a stack of values, which the collector scans as it
+// does the machine stack of compiled code.
+//
+// The instructions check their operands themselves, besides the checks the
+// compiler emits, so that no verified program can make the VM read or write
+// outside its heap and stack.
+
+// The stack holds this many values, and one more zero at the top, where the
+// collector stops scanning.
+const STACK_WORDS: usize = 1 << 22;
+
+const TUPLE_TAG: i64 = 1;
+const CLOSURE_TAG: i64 = 5;
+const STRING_TAG: i64 = 9;
+const VEC_TAG: i64 = 13;
+
+fn error(code: i64) -> ! {
+    snek_error(code);
+    unreachable!("snek_error exits")
+}
+
+fn num(v: i64) -> i64 {
+    if v & 1 != 0 {
+        error(1);
+    }
+    v
+}
+
+fn bool_val(b: bool) -> i64 {
+    if b { 7 } else { 3 }
+}
+
+fn checked(v: Option<i64>) -> i64 {
+    v.unwrap_or_else(|| error(2))
+}
+
+// The object a heap value refers to.
+fn addr(v: i64) -> *mut i64 {
+    (v & -16) as *mut i64
+}
+
+// The object a value of the type tagged `tag` refers to; the empty tuple is
+// not one.
+fn object(v: i64, tag: i64) -> *mut i64 {
+    if v & 15 != tag || v == 1 {
+        error(1);
+    }
+    addr(v)
+}
+
+// Words taken by an object of `words` words, keeping the next one aligned.
+fn object_words(words: usize) -> usize {
+    words + words % 2
+}
+
+struct Vm<'a> {
+    p: &'a Program,
+    stack: Vec<i64>,
+    sp: usize,
+    heap: *mut u64,
+    heap_end: *mut u64,
+    input: i64,
+}
+
+// Where to go on when a function returns.
+struct Frame {
+    fun: usize,
+    pc: usize,
+    bp: usize,
+}
+
+impl Vm<'_> {
+    fn push(&mut self, v: i64) {
+        self.stack[self.sp] = v;
+        self.sp += 1;
+    }
+
+    fn pop(&mut self) -> i64 {
+        self.sp -= 1;
+        self.stack[self.sp]
+    }
+
+    // The operand `i` places below the top of the stack.
+    fn peek(&self, i: usize) -> i64 {
+        self.stack[self.sp - 1 - i]
+    }
+
+    // Makes room for an object of `words` words, collecting the heap if
+    // needed. Operands still on the stack are updated if their objects move.
+    fn alloc(&mut self, words: usize) -> *mut i64 {
+        let words = object_words(words);
+        if (self.heap_end as usize - self.heap as usize) / 8 < words {
+            self.stack[self.sp] = 0;
+            let base = unsafe { self.stack.as_ptr().add(self.sp) } as *const u64;
+            self.heap = unsafe { snek_gc(self.heap, 8 * words as u64, self.stack.as_mut_ptr() as *mut u64, base) };
+            if (self.heap_end as usize - self.heap as usize) / 8 < words {
+                error(4);
+            }
+        }
+        let obj = self.heap as *mut i64;
+        self.heap = unsafe { self.heap.add(words) };
+        obj
+    }
+
+    // Makes an object of the words in `head`, the header first, followed by
+    // `n` values popped from the stack, the deepest first.
+    fn make_object(&mut self, head: &[i64], n: usize, tag: i64) -> i64 {
+        let obj = self.alloc(head.len() + n);
+        unsafe {
+            std::ptr::copy_nonoverlapping(head.as_ptr(), obj, head.len());
+            std::ptr::copy_nonoverlapping(self.stack[self.sp - n..].as_ptr(), obj.add(head.len()), n);
+        }
+        self.sp -= n;
+        obj as i64 | tag
+    }
+
+    // The field of a tuple or vector at snek number `idx`.
+    fn field(&self, obj: i64, idx: i64) -> *mut i64 {
+        if obj == 1 {
+            error(3);
+        }
+        if obj & 15 != TUPLE_TAG && obj & 15 != VEC_TAG {
+            error(1);
+        }
+        let obj = addr(obj);
+        if num(idx) as u64 >= unsafe { *obj } as u64 {
+            error(3);
+        }
+        unsafe { obj.add((idx >> 1) as usize + 1) }
+    }
+
+    // Makes a frame for function `fun` at `bp`, where its arguments and its
+    // closure already are.
+    fn enter(&mut self, fun: usize, bp: usize) {
+        let f = &self.p.funs[fun];
+        if bp + f.locals as usize + f.stack as usize > STACK_WORDS {
+            eprintln!("an error ocurred stack overflow");
+            std::process::exit(1);
+        }
+        self.sp = bp + f.locals as usize;
+    }
+
+    // The function a closure of arity `n` runs.
+    fn closure_fun(&self, f: i64, n: u32) -> usize {
+        let obj = object(f, CLOSURE_TAG);
+        if unsafe { *obj.add(2) } != (n as i64) << 1 {
+            error(5);
+        }
+        let fun = (unsafe { *obj.add(1) } >> 1) as usize;
+        assert!(fun < self.p.funs.len(), "closure of an unknown function");
+        fun
+    }
+
+    fn exec(&mut self) -> i64 {
+        let p = self.p;
+        let mut frames: Vec<Frame> = Vec::new();
+        let (mut fun, mut pc, mut bp) = (0, 0, 0);
+        self.stack[0] = 0;
+        self.enter(0, 0);
+        loop {
+            let f = &p.funs[fun];
+            let instr = &f.code[pc];
+            pc += 1;
+            match instr {
+                Instr::Const(v) => self.push(*v),
+                Instr::Load(s) => self.push(self.stack[bp + *s as usize]),
+                Instr::Store(s) => self.stack[bp + *s as usize] = self.pop(),
+                Instr::Input => self.push(self.input),
+                Instr::Captured(i) => {
+                    let closure = addr(self.stack[bp + f.arity as usize]);
+                    self.push(unsafe { *closure.add(3 + *i as usize) });
+                },
+
+                Instr::Add1 => {
+                    let a = num(self.pop());
+                    self.push(checked(a.checked_add(2)));
+                },
+                Instr::Sub1 => {
+                    let a = num(self.pop());
+                    self.push(checked(a.checked_sub(2)));
+                },
+                Instr::IsNum => {
+                    let a = self.pop();
+                    self.push(bool_val(a & 1 == 0));
+                },
+                Instr::IsBool => {
+                    let a = self.pop();
+                    self.push(bool_val(a & 3 == 3));
+                },
+                Instr::IsTuple => {
+                    let a = self.pop();
+                    self.push(bool_val(a & 15 == TUPLE_TAG));
+                },
+                Instr::IsFun => {
+                    let a = self.pop();
+                    self.push(bool_val(a & 15 == CLOSURE_TAG));
+                },
+                Instr::IsString => {
+                    let a = self.pop();
+                    self.push(bool_val(a & 15 == STRING_TAG));
+                },
+                // the header is the byte count shifted left, with the low bit set
+                Instr::StringLength => {
+                    let s = object(self.pop(), STRING_TAG);
+                    self.push(unsafe { *s } & -2);
+                },
+                // the header is the length as a snek number
+                Instr::VecLen => {
+                    let v = object(self.pop(), VEC_TAG);
+                    self.push(unsafe { *v });
+                },
+                Instr::Print => {
+                    let a = self.pop();
+                    self.push(snek_print(a));
+                },
+
+                Instr::Plus | Instr::Minus | Instr::Times | Instr::Less | Instr::LessEqual | Instr::Greater
+                | Instr::GreaterEqual => {
+                    let b = num(self.pop());
+                    let a = num(self.pop());
+                    self.push(match instr {
+                        Instr::Plus => checked(a.checked_add(b)),
+                        Instr::Minus => checked(a.checked_sub(b)),
+                        Instr::Times => checked((a >> 1).checked_mul(b)),
+                        Instr::Less => bool_val(a < b),
+                        Instr::LessEqual => bool_val(a <= b),
+                        Instr::Greater => bool_val(a > b),
+                        _ => bool_val(a >= b),
+                    });
+                },
+                Instr::Equal => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.push(bool_val(a == b));
+                },
+                Instr::StEq => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.push(snek_structural_eq_true(a, b));
+                },
+                // the operands stay on the stack while the result is allocated
+                Instr::StringAppend => {
+                    let len = |v| unsafe { *object(v, STRING_TAG) >> 1 } as usize;
+                    let bytes = len(self.peek(1)) + len(self.peek(0));
+                    let dst = self.alloc(1 + (bytes + 7) / 8);
+                    let s = unsafe { snek_string_append(dst as *mut u64, self.peek(1), self.peek(0)) };
+                    self.sp -= 2;
+                    self.push(s);
+                },
+                // `(string-ref s i)` is the one-byte string at index `i` of `s`
+                Instr::StringRef => {
+                    let s = object(self.peek(1), STRING_TAG);
+                    if num(self.peek(0)) as u64 >= unsafe { *s - 1 } as u64 {
+                        error(3);
+                    }
+                    let dst = self.alloc(2);
+                    let s = unsafe { snek_string_ref(dst as *mut u64, self.peek(1), self.peek(0)) };
+                    self.sp -= 2;
+                    self.push(s);
+                },
+                // `(make-vec n init)`; a length that cannot fit in the heap is
+                // out of memory
+                Instr::MakeVec => {
+                    let n = num(self.peek(1));
+                    if n < 0 {
+                        error(1);
+                    }
+                    if n > i32::MAX as i64 {
+                        error(4);
+                    }
+                    let len = (n >> 1) as usize;
+                    let v = self.alloc(1 + len);
+                    let init = self.pop();
+                    self.sp -= 1;
+                    unsafe {
+                        *v = n;
+                        for i in 1..=len {
+                            *v.add(i) = init;
+                        }
+                    }
+                    self.push(v as i64 | VEC_TAG);
+                },
+
+                Instr::Str(s) => {
+                    let obj = self.alloc(1 + (s.len() + 7) / 8);
+                    unsafe {
+                        *obj = ((s.len() as i64) << 1) | 1;
+                        std::ptr::copy_nonoverlapping(s.as_ptr(), obj.add(1) as *mut u8, s.len());
+                    }
+                    self.push(obj as i64 | STRING_TAG);
+                },
+                Instr::Tuple(n) => {
+                    let t = self.make_object(&[(*n as i64) << 1], *n as usize, TUPLE_TAG);
+                    self.push(t);
+                },
+                Instr::Get => {
+                    let idx = self.pop();
+                    let obj = self.pop();
+                    let v = unsafe { *self.field(obj, idx) };
+                    self.push(v);
+                },
+                Instr::Set => {
+                    let v = self.pop();
+                    let idx = self.pop();
+                    let obj = self.pop();
+                    unsafe { *self.field(obj, idx) = v };
+                    self.push(v);
+                },
+                // a header, the function, the arity, then the captured values
+                Instr::Closure(g) => {
+                    let n = p.funs[*g as usize].captured as usize;
+                    let arity = p.funs[*g as usize].arity as i64;
+                    let c = self.make_object(&[(n as i64 + 2) << 1, (*g as i64) << 1, arity << 1], n, CLOSURE_TAG);
+                    self.push(c);
+                },
+                // top-level functions are called without a closure
+                Instr::Call(g, n) => {
+                    frames.push(Frame { fun, pc, bp });
+                    bp = self.sp - *n as usize;
+                    self.stack[self.sp] = 0;
+                    (fun, pc) = (*g as usize, 0);
+                    self.enter(fun, bp);
+                },
+                Instr::Apply(n) => {
+                    let g = self.closure_fun(self.peek(0), *n);
+                    frames.push(Frame { fun, pc, bp });
+                    bp = self.sp - *n as usize - 1;
+                    (fun, pc) = (g, 0);
+                    self.enter(fun, bp);
+                },
+
+                Instr::CheckNum => {
+                    num(self.pop());
+                },
+                Instr::CheckTuple | Instr::CheckVec | Instr::CheckStr | Instr::CheckFun => {
+                    let tag = match instr {
+                        Instr::CheckTuple => TUPLE_TAG,
+                        Instr::CheckVec => VEC_TAG,
+                        Instr::CheckStr => STRING_TAG,
+                        _ => CLOSURE_TAG,
+                    };
+                    if self.pop() & 15 != tag {
+                        error(1);
+                    }
+                },
+                Instr::CheckIndex => {
+                    let idx = self.pop();
+                    let obj = self.pop();
+                    self.field(obj, idx);
+                },
+
+                Instr::Jump(l) => pc = *l as usize,
+                Instr::JumpIfFalse(l) => {
+                    if self.pop() == 3 {
+                        pc = *l as usize;
+                    }
+                },
+                Instr::Return => {
+                    let v = self.pop();
+                    self.sp = bp;
+                    let Some(frame) = frames.pop() else {
+                        return v;
+                    };
+                    self.push(v);
+                    (fun, pc, bp) = (frame.fun, frame.pc, frame.bp);
+                },
+                // the arguments replace the caller's
+                Instr::TailCall(g, n) => {
+                    let n = *n as usize;
+                    self.stack.copy_within(self.sp - n..self.sp, bp);
+                    self.stack[bp + n] = 0;
+                    (fun, pc) = (*g as usize, 0);
+                    self.enter(fun, bp);
+                },
+                Instr::TailApply(n) => {
+                    let g = self.closure_fun(self.peek(0), *n);
+                    let n = *n as usize;
+                    self.stack.copy_within(self.sp - n - 1..self.sp, bp);
+                    (fun, pc) = (g, 0);
+                    self.enter(fun, bp);
+                },
+            }
+        }
+    }
+}
+
+/// Runs a program on `input` with a heap of `heap_size` bytes, and prints the
+/// result.
+pub fn run(p: &Program, input: i64, heap_size: usize) {
+    let heap_words = heap_size / 8;
+    // one spare word to align the heap to 16 bytes
+    let mut memory = Vec::<u64>::with_capacity(heap_words + 1);
+    let buffer = memory.as_mut_ptr();
+    let buffer = unsafe { buffer.add(buffer as usize / 8 % 2) };
+    unsafe { HEAP_START = buffer };
+    let mut vm = Vm {
+        p,
+        stack: vec![0; STACK_WORDS + 1],
+        sp: 0,
+        heap: buffer,
+        heap_end: unsafe { buffer.add(heap_words) },
+        input,
+    };
+    let v = vm.exec();
+    snek_print(v);
+}
@@ -0,0 +1,846 @@
+use std::collections::HashMap;
+
+use crate::ast::{Op1, Op2};
+use crate::frame::{allocate, max_args};
+use crate::ir::{self, Operand, Rhs, Stmt, Term, Ty, Var};
+
+#[derive(Debug, Clone)]
+pub enum Val {
+    Reg(Reg),
+    Imm32(i32),
+    Imm64(i64),
+    RegOffset(Reg, i32),
+    EffectiveAddr(Reg, Reg, i32, i32),
+    RelLabel(String),
+}
+
+// Bytes taken by a heap object of `words` words, keeping the next one aligned.
+fn object_size(words: usize) -> i32 {
+    8 * (words + words % 2) as i32
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum Reg {
+    RAX,
+    RBX,
+    RCX,
+    RDX,
+    RSI,
+    RDI,
+    RSP,
+    RBP,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
+}
+
+// The registers the System V ABI has a function preserve for its caller.
+pub const CALLEE_SAVED: [Reg; 6] = [Reg::RBX, Reg::RBP, Reg::R12, Reg::R13, Reg::R14, Reg::R15];
+
+// Registers that the generated code never uses as scratch, so that they can
+// hold variables. None of them survives a call, to a snek function or into the
+// runtime, so only variables that are not live across one are kept there; the
+// collector never has to find or update them either.
+const ALLOC_REGS: [Reg; 6] = [Reg::R8, Reg::R9, Reg::R10, Reg::R11, Reg::R12, Reg::R13];
+
+#[derive(Debug)]
+pub enum Instr<'a> {
+    Mov(Val, Val),
+    Add(Val, Val),
+    Sub(Val, Val),
+    Imul(Val, Val),
+    And(Val, Val),
+    Xor(Val, Val),
+    Sar(Val, Val),
+    Cmp(Val, Val),
+    Test(Val, Val),
+    Push(Val),
+    Pop(Val),
+    Call(String),
+    CallInd(Val),
+    JmpInd(Val),
+    Leave,
+    Ret,
+    J(&'a str, String),
+    Cmov(&'a str, Val, Val),
+    Lea(Val, Val),
+    Label(String),
+}
+
+fn check_num(instrs: &mut Vec<Instr>) {
+    instrs.push(Instr::Test(Val::Reg(Reg::RAX), Val::Imm64(1)));
+    instrs.push(Instr::Mov(Val::Reg(Reg::RSI), Val::Imm64(1)));
+    instrs.push(Instr::J("ne", ERROR_LABEL.to_string()));
+}
+
+// Heap objects are 16-byte aligned and tagged in the low four bits.
+const TUPLE_TAG: i32 = 1;
+const CLOSURE_TAG: i32 = 5;
+const STRING_TAG: i32 = 9;
+const VEC_TAG: i32 = 13;
+
+fn check_tag(tag: i32, instrs: &mut Vec<Instr>) {
+    instrs.push(Instr::Mov(Val::Reg(Reg::RSI), Val::Reg(Reg::RAX)));
+    instrs.push(Instr::And(Val::Reg(Reg::RSI), Val::Imm64(15)));
+    instrs.push(Instr::Cmp(Val::Reg(Reg::RSI), Val::Imm32(tag)));
+    instrs.push(Instr::Mov(Val::Reg(Reg::RSI), Val::Imm64(1)));
+    instrs.push(Instr::J("ne", ERROR_LABEL.to_string()));
+}
+
+fn check_overflow(instrs: &mut Vec<Instr>) {
+    instrs.push(Instr::Mov(Val::Reg(Reg::RSI), Val::Imm64(2)));
+    instrs.push(Instr::J("o", ERROR_LABEL.to_string()));
+}
+
+fn new_label(label: &mut i32, s: &str) -> String {
+    let cur_label = *label;
+    *label += 1;
+    format!("{s}_{cur_label}")
+}
+
+fn imm(n: i64) -> Val {
+    match i32::try_from(n) {
+        Ok(n) => Val::Imm32(n),
+        Err(_) => Val::Imm64(n),
+    }
+}
+
+type Loc = crate::frame::Loc<Reg>;
+
+fn loc_val(loc: Loc) -> Val {
+    match loc {
+        Loc::Stack(w) => Val::RegOffset(Reg::RBP, 8 * w),
+        Loc::Reg(r) => Val::Reg(r),
+    }
+}
+
+// Code generation for one function, whose frame has `aligned` set when RSP is
+// 16-byte aligned with nothing pushed.
+struct FunGen<'a, 'b> {
+    locs: HashMap<Var, Loc>,
+    aligned: bool,
+    max_args: usize,
+    label: &'a mut i32,
+    instrs: &'a mut Vec<Instr<'b>>,
+}
+
+impl FunGen<'_, '_> {
+    fn val(&self, op: &Operand) -> Val {
+        match op {
+            Operand::Var(v) => loc_val(self.locs[v]),
+            Operand::Num(n) => imm(n << 1),
+            Operand::Bool(b) => Val::Imm32(if *b { 7 } else { 3 }),
+        }
+    }
+
+    fn load(&mut self, r: Reg, op: &Operand) {
+        let v = self.val(op);
+        self.instrs.push(Instr::Mov(Val::Reg(r), v));
+    }
+
+    // `op` as the source operand of an arithmetic instruction, which can only
+    // take a 32-bit immediate, going through `scratch` otherwise.
+    fn src(&mut self, op: &Operand, scratch: Reg) -> Val {
+        match self.val(op) {
+            v @ Val::Imm64(_) => {
+                self.instrs.push(Instr::Mov(Val::Reg(scratch), v));
+                Val::Reg(scratch)
+            },
+            v => v,
+        }
+    }
+
+    fn push_operand(&mut self, op: &Operand) {
+        match self.val(op) {
+            v @ (Val::Reg(_) | Val::Imm32(_)) => self.instrs.push(Instr::Push(v)),
+            v => {
+                self.instrs.push(Instr::Mov(Val::Reg(Reg::RAX), v));
+                self.instrs.push(Instr::Push(Val::Reg(Reg::RAX)));
+            },
+        }
+    }
+
+    // Pushes the arguments of a call in reverse, zero-filled up to `max_args`,
+    // after padding the stack so that it is aligned again once the closure is
+    // pushed. Every call reserves the same room for arguments, so that a tail
+    // call can reuse it whatever the arity of either function. Returns the
+    // number of bytes to pop after the call.
+    fn push_args(&mut self, args: &[Operand]) -> i32 {
+        let words = self.max_args + 1;
+        let pad = (words % 2 == 1) == self.aligned;
+        if pad {
+            self.instrs.push(Instr::Sub(Val::Reg(Reg::RSP), Val::Imm32(8)));
+        }
+        for _ in args.len()..self.max_args {
+            self.instrs.push(Instr::Push(Val::Imm32(0)));
+        }
+        for a in args.iter().rev() {
+            self.push_operand(a);
+        }
+        8 * (words as i32 + pad as i32)
+    }
+
+    // Moves the arguments of a tail call over the current function's
+    // arguments, through the stack, as they may be read from there.
+    fn move_tail_args(&mut self, args: &[Operand]) {
+        for a in args.iter().rev() {
+            self.push_operand(a);
+        }
+        for i in 0..args.len() as i32 {
+            self.instrs.push(Instr::Pop(Val::Reg(Reg::RCX)));
+            self.instrs.push(Instr::Mov(Val::RegOffset(Reg::RBP, 8 * (i + 3)), Val::Reg(Reg::RCX)));
+        }
+    }
+
+    // With the closure in RAX, leaves its address in RBX.
+    fn check_arity(&mut self, n: usize) {
+        self.instrs.push(Instr::Mov(Val::Reg(Reg::RBX), Val::Reg(Reg::RAX)));
+        self.instrs.push(Instr::And(Val::Reg(Reg::RBX), Val::Imm32(-16)));
+        self.instrs.push(Instr::Mov(Val::Reg(Reg::RCX), Val::RegOffset(Reg::RBX, 16)));
+        self.instrs.push(Instr::Cmp(Val::Reg(Reg::RCX), Val::Imm32((n as i32) << 1)));
+        self.instrs.push(Instr::Mov(Val::Reg(Reg::RSI), Val::Imm64(5)));
+        self.instrs.push(Instr::J("ne", ERROR_LABEL.to_string()));
+    }
+
+    // Makes sure there are `size` bytes free at R15, running the garbage
+    // collector if needed. R14 holds the end of the heap. `size` is an
+    // immediate or RBX, which the collector preserves.
+    fn alloc(&mut self, size: Val) {
+        let end = || match size {
+            Val::Imm32(n) => Val::RegOffset(Reg::R15, n),
+            Val::Reg(Reg::RBX) => Val::EffectiveAddr(Reg::R15, Reg::RBX, 1, 0),
+            _ => unreachable!(),
+        };
+        let lok = new_label(self.label, "alloc_ok");
+        let instrs = &mut *self.instrs;
+        instrs.push(Instr::Lea(Val::Reg(Reg::RAX), end()));
+        instrs.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Reg(Reg::R14)));
+        instrs.push(Instr::J("be", lok.to_string()));
+
+        if !self.aligned { instrs.push(Instr::Sub(Val::Reg(Reg::RSP), Val::Imm32(8))); }
+        instrs.push(Instr::Mov(Val::Reg(Reg::RDI), Val::Reg(Reg::R15)));
+        instrs.push(Instr::Lea(Val::Reg(Reg::RSI), end()));
+        instrs.push(Instr::Sub(Val::Reg(Reg::RSI), Val::Reg(Reg::R15)));
+        instrs.push(Instr::Mov(Val::Reg(Reg::RDX), Val::Reg(Reg::RSP)));
+        instrs.push(Instr::Mov(Val::Reg(Reg::RCX), Val::Reg(Reg::RBP)));
+        instrs.push(Instr::Call("snek_gc".to_string()));
+        if !self.aligned { instrs.push(Instr::Add(Val::Reg(Reg::RSP), Val::Imm32(8))); }
+        instrs.push(Instr::Mov(Val::Reg(Reg::R15), Val::Reg(Reg::RAX)));
+
+        // out-of-memory error code
+        instrs.push(Instr::Lea(Val::Reg(Reg::RAX), end()));
+        instrs.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Reg(Reg::R14)));
+        instrs.push(Instr::Mov(Val::Reg(Reg::RSI), Val::Imm64(4)));
+        instrs.push(Instr::J("a", ERROR_LABEL.to_string()));
+        instrs.push(Instr::Label(lok));
+    }
+
+    fn external_call(&mut self, args: &[Val], n: &str) {
+        if !self.aligned { self.instrs.push(Instr::Sub(Val::Reg(Reg::RSP), Val::Imm32(8))); }
+        for (r, a) in [Reg::RDI, Reg::RSI, Reg::RDX].into_iter().zip(args) {
+            self.instrs.push(Instr::Mov(Val::Reg(r), a.clone()));
+        }
+        self.instrs.push(Instr::Call(n.to_string()));
+        if !self.aligned { self.instrs.push(Instr::Add(Val::Reg(Reg::RSP), Val::Imm32(8))); }
+    }
+
+    fn stmt(&mut self, s: &Stmt) {
+        match s {
+            Stmt::Assign(d, Rhs::Copy(a)) => {
+                let (dst, src) = (loc_val(self.locs[d]), self.val(a));
+                match (&dst, &src) {
+                    (Val::Reg(r1), Val::Reg(r2)) if r1 == r2 => {},
+                    (Val::RegOffset(_, o1), Val::RegOffset(_, o2)) if o1 == o2 => {},
+                    (Val::Reg(_), _) | (_, Val::Reg(_)) => self.instrs.push(Instr::Mov(dst, src)),
+                    _ => {
+                        self.instrs.push(Instr::Mov(Val::Reg(Reg::RAX), src));
+                        self.instrs.push(Instr::Mov(dst, Val::Reg(Reg::RAX)));
+                    },
+                }
+            },
+            Stmt::Assign(d, rhs) => {
+                self.rhs(rhs);
+                let dst = loc_val(self.locs[d]);
+                self.instrs.push(Instr::Mov(dst, Val::Reg(Reg::RAX)));
+            },
+            Stmt::Check(ty, a) => {
+                self.load(Reg::RAX, a);
+                match ty {
+                    Ty::Num => check_num(self.instrs),
+                    Ty::Tuple => check_tag(TUPLE_TAG, self.instrs),
+                    Ty::Vec => check_tag(VEC_TAG, self.instrs),
+                    Ty::Str => check_tag(STRING_TAG, self.instrs),
+                    Ty::Fun => check_tag(CLOSURE_TAG, self.instrs),
+                }
+            },
+            // Tuples and vectors share a layout: a header holding the length as a
+            // snek number, then the fields.
+            Stmt::CheckIndex(obj, idx) => {
+                // index-out-of-range error code
+                self.instrs.push(Instr::Mov(Val::Reg(Reg::RSI), Val::Imm64(3)));
+                self.load(Reg::RAX, obj);
+                // check empty
+                self.instrs.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm32(1)));
+                self.instrs.push(Instr::J("e", ERROR_LABEL.to_string()));
+                self.instrs.push(Instr::And(Val::Reg(Reg::RAX), Val::Imm32(-16)));
+                self.load(Reg::RBX, idx);
+                // check len; the unsigned compare also rejects negative indices
+                self.instrs.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::RegOffset(Reg::RAX, 0)));
+                self.instrs.push(Instr::J("ae", ERROR_LABEL.to_string()));
+            },
+        }
+    }
+
+    // Evaluates `rhs` into RAX.
+    fn rhs(&mut self, rhs: &Rhs) {
+        match rhs {
+            Rhs::Copy(a) => self.load(Reg::RAX, a),
+            Rhs::Input => self.instrs.push(Instr::Mov(Val::Reg(Reg::RAX), Val::RelLabel(INPUT_LABEL.to_string()))),
+            Rhs::Prim1(o, a) => {
+                self.load(Reg::RAX, a);
+                self.prim1(o);
+            },
+            Rhs::Prim2(o, a, b) => self.prim2(o, a, b),
+            Rhs::Str(s) => self.string(s),
+            Rhs::Tuple(es) if es.is_empty() => self.instrs.push(Instr::Mov(Val::Reg(Reg::RAX), Val::Imm32(1))),
+            Rhs::Tuple(es) => {
+                let size = object_size(es.len() + 1);
+                self.alloc(Val::Imm32(size));
+                self.instrs.push(Instr::Mov(Val::Reg(Reg::RAX), Val::Imm64((es.len() as i64) << 1)));
+                self.instrs.push(Instr::Mov(Val::RegOffset(Reg::R15, 0), Val::Reg(Reg::RAX)));
+                for (i, e) in (1..).zip(es) {
+                    self.load(Reg::RAX, e);
+                    self.instrs.push(Instr::Mov(Val::RegOffset(Reg::R15, 8 * i), Val::Reg(Reg::RAX)));
+                }
+                self.instrs.push(Instr::Mov(Val::Reg(Reg::RAX), Val::Reg(Reg::R15)));
+                self.instrs.push(Instr::Xor(Val::Reg(Reg::RAX), Val::Imm32(TUPLE_TAG)));
+                self.instrs.push(Instr::Add(Val::Reg(Reg::R15), Val::Imm32(size)));
+            },
+            Rhs::Get(_, obj, idx) => {
+                self.load(Reg::RAX, obj);
+                self.instrs.push(Instr::And(Val::Reg(Reg::RAX), Val::Imm32(-16)));
+                self.load(Reg::RBX, idx);
+                self.instrs.push(Instr::Mov(Val::Reg(Reg::RAX), Val::EffectiveAddr(Reg::RAX, Reg::RBX, 4, 8)));
+            },
+            Rhs::Set(_, obj, idx, v) => {
+                self.load(Reg::RAX, obj);
+                self.instrs.push(Instr::And(Val::Reg(Reg::RAX), Val::Imm32(-16)));
+                self.load(Reg::RBX, idx);
+                self.instrs.push(Instr::Lea(Val::Reg(Reg::RBX), Val::EffectiveAddr(Reg::RAX, Reg::RBX, 4, 8)));
+                self.load(Reg::RAX, v);
+                self.instrs.push(Instr::Mov(Val::RegOffset(Reg::RBX, 0), Val::Reg(Reg::RAX)));
+            },
+            // a header, the code address, the arity, then the captured values
+            Rhs::Closure(label, arity, es) => {
+                let size = object_size(es.len() + 3);
+                self.alloc(Val::Imm32(size));
+                self.instrs.push(Instr::Mov(Val::Reg(Reg::RAX), Val::Imm64((es.len() as i64 + 2) << 1)));
+                self.instrs.push(Instr::Mov(Val::RegOffset(Reg::R15, 0), Val::Reg(Reg::RAX)));
+                self.instrs.push(Instr::Lea(Val::Reg(Reg::RAX), Val::RelLabel(label.to_string())));
+                self.instrs.push(Instr::Mov(Val::RegOffset(Reg::R15, 8), Val::Reg(Reg::RAX)));
+                self.instrs.push(Instr::Mov(Val::Reg(Reg::RAX), Val::Imm64((*arity as i64) << 1)));
+                self.instrs.push(Instr::Mov(Val::RegOffset(Reg::R15, 16), Val::Reg(Reg::RAX)));
+                for (i, e) in (3..).zip(es) {
+                    self.load(Reg::RAX, e);
+                    self.instrs.push(Instr::Mov(Val::RegOffset(Reg::R15, 8 * i), Val::Reg(Reg::RAX)));
+                }
+                self.instrs.push(Instr::Mov(Val::Reg(Reg::RAX), Val::Reg(Reg::R15)));
+                self.instrs.push(Instr::Add(Val::Reg(Reg::RAX), Val::Imm32(CLOSURE_TAG)));
+                self.instrs.push(Instr::Add(Val::Reg(Reg::R15), Val::Imm32(size)));
+            },
+            Rhs::Call(label, args) => {
+                let size = self.push_args(args);
+                // top-level functions ignore their closure
+                self.instrs.push(Instr::Push(Val::Imm32(0)));
+                self.instrs.push(Instr::Call(label.to_string()));
+                self.instrs.push(Instr::Add(Val::Reg(Reg::RSP), Val::Imm32(size)));
+            },
+            // the closure itself is passed below the arguments
+            Rhs::Apply(f, args) => {
+                let size = self.push_args(args);
+                self.load(Reg::RAX, f);
+                self.check_arity(args.len());
+                self.instrs.push(Instr::Push(Val::Reg(Reg::RAX)));
+                self.instrs.push(Instr::CallInd(Val::RegOffset(Reg::RBX, 8)));
+                self.instrs.push(Instr::Add(Val::Reg(Reg::RSP), Val::Imm32(size)));
+            },
+        }
+    }
+
+    fn prim1(&mut self, o: &Op1) {
+        let instrs = &mut *self.instrs;
+        match o {
+            Op1::Add1 => {
+                instrs.push(Instr::Add(Val::Reg(Reg::RAX), Val::Imm32(2)));
+                check_overflow(instrs);
+            },
+            Op1::Sub1 => {
+                instrs.push(Instr::Sub(Val::Reg(Reg::RAX), Val::Imm32(2)));
+                check_overflow(instrs);
+            },
+
+            // bool here
+            Op1::IsNum => {
+                instrs.push(Instr::Test(Val::Reg(Reg::RAX), Val::Imm64(1)));
+                instrs.push(Instr::Mov(Val::Reg(Reg::RAX), Val::Imm64(3)));
+                instrs.push(Instr::Mov(Val::Reg(Reg::RBX), Val::Imm64(7)));
+                instrs.push(Instr::Cmov("e", Val::Reg(Reg::RAX), Val::Reg(Reg::RBX)));
+            },
+            Op1::IsBool => {
+                instrs.push(Instr::And(Val::Reg(Reg::RAX), Val::Imm64(3)));
+                instrs.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm64(3)));
+                instrs.push(Instr::Mov(Val::Reg(Reg::RAX), Val::Imm64(3)));
+                instrs.push(Instr::Mov(Val::Reg(Reg::RBX), Val::Imm64(7)));
+                instrs.push(Instr::Cmov("e", Val::Reg(Reg::RAX), Val::Reg(Reg::RBX)));
+            },
+            Op1::IsTuple | Op1::IsFun | Op1::IsString => {
+                let tag = match o {
+                    Op1::IsTuple => TUPLE_TAG,
+                    Op1::IsFun => CLOSURE_TAG,
+                    _ => STRING_TAG,
+                };
+                instrs.push(Instr::And(Val::Reg(Reg::RAX), Val::Imm64(15)));
+                instrs.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm32(tag)));
+                instrs.push(Instr::Mov(Val::Reg(Reg::RAX), Val::Imm64(3)));
+                instrs.push(Instr::Mov(Val::Reg(Reg::RBX), Val::Imm64(7)));
+                instrs.push(Instr::Cmov("e", Val::Reg(Reg::RAX), Val::Reg(Reg::RBX)));
+            },
+            // the header is the length as a snek number
+            Op1::VecLen => {
+                instrs.push(Instr::And(Val::Reg(Reg::RAX), Val::Imm32(-16)));
+                instrs.push(Instr::Mov(Val::Reg(Reg::RAX), Val::RegOffset(Reg::RAX, 0)));
+            },
+            // the header is the byte count shifted left, with the low bit set
+            Op1::StringLength => {
+                instrs.push(Instr::And(Val::Reg(Reg::RAX), Val::Imm32(-16)));
+                instrs.push(Instr::Mov(Val::Reg(Reg::RAX), Val::RegOffset(Reg::RAX, 0)));
+                instrs.push(Instr::And(Val::Reg(Reg::RAX), Val::Imm32(-2)));
+            },
+            Op1::Print => self.external_call(&[Val::Reg(Reg::RAX)], "snek_print"),
+        }
+    }
+
+    fn prim2(&mut self, o: &Op2, a: &Operand, b: &Operand) {
+        match o {
+            Op2::StEq => {
+                self.load(Reg::RAX, a);
+                let b = self.val(b);
+                self.external_call(&[Val::Reg(Reg::RAX), b], "snek_structural_eq_true");
+            },
+            Op2::StringAppend => self.string_append(a, b),
+            Op2::StringRef => self.string_ref(a, b),
+            Op2::MakeVec => self.make_vec(a, b),
+            Op2::Plus | Op2::Minus | Op2::Times => {
+                self.load(Reg::RAX, a);
+                let b = self.src(b, Reg::RCX);
+                let i = match o {
+                    Op2::Plus => Instr::Add(Val::Reg(Reg::RAX), b),
+                    Op2::Minus => Instr::Sub(Val::Reg(Reg::RAX), b),
+                    _ => {
+                        self.instrs.push(Instr::Sar(Val::Reg(Reg::RAX), Val::Imm32(1)));
+                        match b {
+                            Val::Imm32(_) => {
+                                self.instrs.push(Instr::Mov(Val::Reg(Reg::RCX), b));
+                                Instr::Imul(Val::Reg(Reg::RAX), Val::Reg(Reg::RCX))
+                            },
+                            b => Instr::Imul(Val::Reg(Reg::RAX), b),
+                        }
+                    },
+                };
+                self.instrs.push(i);
+                check_overflow(self.instrs);
+            },
+
+            // bool here
+            _ => {
+                self.load(Reg::RAX, a);
+                let b = self.src(b, Reg::RCX);
+                self.instrs.push(Instr::Cmp(Val::Reg(Reg::RAX), b));
+                self.instrs.push(Instr::Mov(Val::Reg(Reg::RBX), Val::Imm32(7)));
+                self.instrs.push(Instr::Mov(Val::Reg(Reg::RAX), Val::Imm32(3)));
+                let c = match o {
+                    Op2::Equal => "e",
+                    Op2::Less => "l",
+                    Op2::LessEqual => "le",
+                    Op2::Greater => "g",
+                    Op2::GreaterEqual => "ge",
+                    _ => unreachable!(),
+                };
+                self.instrs.push(Instr::Cmov(c, Val::Reg(Reg::RAX), Val::Reg(Reg::RBX)));
+            },
+        }
+    }
+
+    // A string is a header holding its length in bytes, shifted left with the
+    // low bit set so that the collector does not scan it, followed by the bytes.
+    fn string(&mut self, s: &str) {
+        let bytes = s.as_bytes();
+        let size = object_size(1 + (bytes.len() + 7) / 8);
+        self.alloc(Val::Imm32(size));
+        let instrs = &mut *self.instrs;
+        instrs.push(Instr::Mov(Val::Reg(Reg::RAX), Val::Imm64(((bytes.len() as i64) << 1) | 1)));
+        instrs.push(Instr::Mov(Val::RegOffset(Reg::R15, 0), Val::Reg(Reg::RAX)));
+        for (i, chunk) in (1..).zip(bytes.chunks(8)) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            instrs.push(Instr::Mov(Val::Reg(Reg::RAX), Val::Imm64(i64::from_le_bytes(word))));
+            instrs.push(Instr::Mov(Val::RegOffset(Reg::R15, 8 * i), Val::Reg(Reg::RAX)));
+        }
+        instrs.push(Instr::Mov(Val::Reg(Reg::RAX), Val::Reg(Reg::R15)));
+        instrs.push(Instr::Add(Val::Reg(Reg::RAX), Val::Imm32(STRING_TAG)));
+        instrs.push(Instr::Add(Val::Reg(Reg::R15), Val::Imm32(size)));
+    }
+
+    // The operands of an allocation are in the frame, where the collector can
+    // update them.
+    fn string_append(&mut self, a: &Operand, b: &Operand) {
+        // size of the result: a header plus both lengths, rounded up to 16 bytes
+        self.load(Reg::RAX, b);
+        self.instrs.push(Instr::And(Val::Reg(Reg::RAX), Val::Imm32(-16)));
+        self.instrs.push(Instr::Mov(Val::Reg(Reg::RBX), Val::RegOffset(Reg::RAX, 0)));
+        self.instrs.push(Instr::Sar(Val::Reg(Reg::RBX), Val::Imm32(1)));
+        self.load(Reg::RAX, a);
+        self.instrs.push(Instr::And(Val::Reg(Reg::RAX), Val::Imm32(-16)));
+        self.instrs.push(Instr::Mov(Val::Reg(Reg::RAX), Val::RegOffset(Reg::RAX, 0)));
+        self.instrs.push(Instr::Sar(Val::Reg(Reg::RAX), Val::Imm32(1)));
+        self.instrs.push(Instr::Add(Val::Reg(Reg::RBX), Val::Reg(Reg::RAX)));
+        self.instrs.push(Instr::Add(Val::Reg(Reg::RBX), Val::Imm32(8 + 15)));
+        self.instrs.push(Instr::And(Val::Reg(Reg::RBX), Val::Imm32(-16)));
+
+        self.alloc(Val::Reg(Reg::RBX));
+        let (a, b) = (self.val(a), self.val(b));
+        self.external_call(&[Val::Reg(Reg::R15), a, b], "snek_string_append");
+        self.instrs.push(Instr::Add(Val::Reg(Reg::R15), Val::Reg(Reg::RBX)));
+    }
+
+    // `(string-ref s i)` is the one-byte string at index `i` of `s`.
+    fn string_ref(&mut self, s: &Operand, i: &Operand) {
+        // index-out-of-range error code
+        self.instrs.push(Instr::Mov(Val::Reg(Reg::RSI), Val::Imm64(3)));
+
+        // check bounds, catching negative indices with an unsigned compare
+        self.load(Reg::RAX, s);
+        self.instrs.push(Instr::And(Val::Reg(Reg::RAX), Val::Imm32(-16)));
+        self.instrs.push(Instr::Mov(Val::Reg(Reg::RBX), Val::RegOffset(Reg::RAX, 0)));
+        self.instrs.push(Instr::Sub(Val::Reg(Reg::RBX), Val::Imm32(1)));
+        self.load(Reg::RCX, i);
+        self.instrs.push(Instr::Cmp(Val::Reg(Reg::RCX), Val::Reg(Reg::RBX)));
+        self.instrs.push(Instr::J("ae", ERROR_LABEL.to_string()));
+
+        self.alloc(Val::Imm32(object_size(2)));
+        let (s, i) = (self.val(s), self.val(i));
+        self.external_call(&[Val::Reg(Reg::R15), s, i], "snek_string_ref");
+        self.instrs.push(Instr::Add(Val::Reg(Reg::R15), Val::Imm32(object_size(2))));
+    }
+
+    // `(make-vec n init)` allocates a vector of `n` copies of `init`.
+    fn make_vec(&mut self, n: &Operand, init: &Operand) {
+        self.load(Reg::RAX, n);
+        // a negative length is an invalid argument too
+        self.instrs.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm32(0)));
+        self.instrs.push(Instr::Mov(Val::Reg(Reg::RSI), Val::Imm64(1)));
+        self.instrs.push(Instr::J("l", ERROR_LABEL.to_string()));
+
+        // lengths that cannot fit in the heap, before the size computation overflows
+        self.instrs.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm32(i32::MAX)));
+        self.instrs.push(Instr::Mov(Val::Reg(Reg::RSI), Val::Imm64(4)));
+        self.instrs.push(Instr::J("g", ERROR_LABEL.to_string()));
+
+        // size: a header and n words, rounded up to 16 bytes
+        self.instrs.push(Instr::Lea(Val::Reg(Reg::RBX), Val::EffectiveAddr(Reg::RAX, Reg::RAX, 1, 0)));
+        self.instrs.push(Instr::Lea(Val::Reg(Reg::RBX), Val::EffectiveAddr(Reg::RBX, Reg::RBX, 1, 8 + 15)));
+        self.instrs.push(Instr::And(Val::Reg(Reg::RBX), Val::Imm32(-16)));
+        self.alloc(Val::Reg(Reg::RBX));
+
+        // the header is the length as a snek number
+        self.load(Reg::RCX, n);
+        self.instrs.push(Instr::Mov(Val::RegOffset(Reg::R15, 0), Val::Reg(Reg::RCX)));
+        self.instrs.push(Instr::Sar(Val::Reg(Reg::RCX), Val::Imm32(1)));
+        self.load(Reg::RAX, init);
+        let lfill = new_label(self.label, "fill");
+        let ldone = new_label(self.label, "fill_done");
+        let instrs = &mut *self.instrs;
+        instrs.push(Instr::Label(lfill.to_string()));
+        instrs.push(Instr::Cmp(Val::Reg(Reg::RCX), Val::Imm32(0)));
+        instrs.push(Instr::J("e", ldone.to_string()));
+        instrs.push(Instr::Mov(Val::EffectiveAddr(Reg::R15, Reg::RCX, 8, 0), Val::Reg(Reg::RAX)));
+        instrs.push(Instr::Sub(Val::Reg(Reg::RCX), Val::Imm32(1)));
+        instrs.push(Instr::J("", lfill));
+        instrs.push(Instr::Label(ldone));
+
+        instrs.push(Instr::Mov(Val::Reg(Reg::RAX), Val::Reg(Reg::R15)));
+        instrs.push(Instr::Add(Val::Reg(Reg::RAX), Val::Imm32(VEC_TAG)));
+        instrs.push(Instr::Add(Val::Reg(Reg::R15), Val::Reg(Reg::RBX)));
+    }
+
+    // `next` is the label of the block that follows, which needs no jump.
+    fn term(&mut self, t: &Term, next: Option<&String>) {
+        match t {
+            Term::Jump(l) if Some(l) == next => {},
+            Term::Jump(l) => self.instrs.push(Instr::J("", l.to_string())),
+            Term::Branch(a, thn, els) => {
+                self.load(Reg::RAX, a);
+
+                // bool here
+                self.instrs.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm32(3)));
+                if Some(els) == next {
+                    self.instrs.push(Instr::J("ne", thn.to_string()));
+                } else {
+                    self.instrs.push(Instr::J("e", els.to_string()));
+                    if Some(thn) != next {
+                        self.instrs.push(Instr::J("", thn.to_string()));
+                    }
+                }
+            },
+            Term::Return(a) => {
+                self.load(Reg::RAX, a);
+                self.instrs.push(Instr::Leave);
+                self.instrs.push(Instr::Ret);
+            },
+            Term::TailCall(label, args) => {
+                self.move_tail_args(args);
+                self.instrs.push(Instr::Leave);
+                self.instrs.push(Instr::J("", label.to_string()));
+            },
+            Term::TailApply(f, args) => {
+                self.push_operand(f);
+                self.move_tail_args(args);
+                self.instrs.push(Instr::Pop(Val::Reg(Reg::RAX)));
+                self.check_arity(args.len());
+                self.instrs.push(Instr::Mov(Val::RegOffset(Reg::RBP, 16), Val::Reg(Reg::RAX)));
+                self.instrs.push(Instr::Mov(Val::Reg(Reg::RAX), Val::RegOffset(Reg::RBX, 8)));
+                self.instrs.push(Instr::Leave);
+                self.instrs.push(Instr::JmpInd(Val::Reg(Reg::RAX)));
+            },
+        }
+    }
+}
+
+// The closure being called is at [rbp + 16] and the arguments follow it. The
+// captured values stored in the closure are copied out on entry.
+fn compile_fun(f: &ir::Fun, regs: &[Reg], max_args: usize, label: &mut i32, instrs: &mut Vec<Instr>) {
+    let (locs, slots) = allocate(f, regs);
+    instrs.push(Instr::Label(f.name.to_string()));
+    instrs.push(Instr::Push(Val::Reg(Reg::RBP)));
+    instrs.push(Instr::Mov(Val::Reg(Reg::RBP), Val::Reg(Reg::RSP)));
+    instrs.push(Instr::Sub(Val::Reg(Reg::RSP), Val::Imm32(8 * slots)));
+    if !f.captured.is_empty() {
+        instrs.push(Instr::Mov(Val::Reg(Reg::RBX), Val::RegOffset(Reg::RBP, 16)));
+        instrs.push(Instr::And(Val::Reg(Reg::RBX), Val::Imm32(-16)));
+        for (i, v) in (3..).zip(&f.captured) {
+            instrs.push(Instr::Mov(Val::Reg(Reg::RAX), Val::RegOffset(Reg::RBX, 8 * i)));
+            instrs.push(Instr::Mov(loc_val(locs[v]), Val::Reg(Reg::RAX)));
+        }
+    }
+    let mut g = FunGen { locs, aligned: slots % 2 == 0, max_args, label, instrs };
+    for (i, b) in f.blocks.iter().enumerate() {
+        if i > 0 {
+            g.instrs.push(Instr::Label(b.label.to_string()));
+        }
+        for s in &b.stmts {
+            g.stmt(s);
+        }
+        g.term(&b.term, f.blocks.get(i + 1).map(|b| &b.label));
+    }
+}
+
+fn instr_to_str(i: &Instr) -> String {
+    match i {
+        Instr::Mov(u, v) => format!("mov {}, {}\n", val_to_str(u), val_to_str(v)),
+        Instr::Add(u, v) => format!("add {}, {}\n", val_to_str(u), val_to_str(v)),
+        Instr::Sub(u, v) => format!("sub {}, {}\n", val_to_str(u), val_to_str(v)),
+        Instr::Imul(u, v) => format!("imul {}, {}\n", val_to_str(u), val_to_str(v)),
+        Instr::And(u, v) => format!("and {}, {}\n", val_to_str(u), val_to_str(v)),
+        Instr::Xor(u, v) => format!("xor {}, {}\n", val_to_str(u), val_to_str(v)),
+        Instr::Sar(u, v) => format!("sar {}, {}\n", val_to_str(u), val_to_str(v)),
+        Instr::Cmp(u, v) => format!("cmp {}, {}\n", val_to_str(u), val_to_str(v)),
+        Instr::Test(u, v) => format!("test {}, {}\n", val_to_str(u), val_to_str(v)),
+        Instr::Push(u) => format!("push {}\n", val_to_str(u)),
+        Instr::Pop(u) => format!("pop {}\n", val_to_str(u)),
+        Instr::Call(l) => format!("call {l}\n"),
+        Instr::CallInd(u) => format!("call {}\n", val_to_str(u)),
+        Instr::JmpInd(u) => format!("jmp {}\n", val_to_str(u)),
+        Instr::Leave => "leave\n".to_string(),
+        Instr::Ret => "ret\n".to_string(),
+        Instr::Cmov(c, u, v) => format!("cmov{} {}, {}\n", c, val_to_str(u), val_to_str(v)),
+        Instr::Lea(u, v) => format!("lea {}, {}\n", val_to_str(u), val_to_str(v)),
+        Instr::J("", l) => format!("jmp {l}\n"),
+        Instr::J(c, l) => format!("j{} {}\n", *c, l),
+        Instr::Label(l) => format!("{l}:\n"),
+    }
+}
+
+fn val_to_str(v: &Val) -> String {
+    match v {
+        Val::Reg(r) => reg_to_str(r).to_string(),
+        Val::Imm32(n) => format!("{}", n),
+        Val::Imm64(n) => format!("{}", n),
+        Val::RegOffset(r, n) => {
+            let rs = reg_to_str(r);
+            if *n > 0 {
+                format!("[{} + {}]", rs, n)
+            } else {
+                format!("[{} - {}]", rs, -n)
+            }
+        },
+        Val::EffectiveAddr(b, i, s, d) => {
+            let bs = reg_to_str(b);
+            let is = reg_to_str(i);
+            format!("[{} + {} * {} + {}]", bs, is, s, d)
+        },
+        Val::RelLabel(l) => format!("[rel {l}]"),
+    }
+}
+
+fn reg_to_str(r: &Reg) -> &str {
+    match r {
+        Reg::RAX => "rax",
+        Reg::RBX => "rbx",
+        Reg::RCX => "rcx",
+        Reg::RDX => "rdx",
+        Reg::RSI => "rsi",
+        Reg::RDI => "rdi",
+        Reg::RSP => "rsp",
+        Reg::RBP => "rbp",
+        Reg::R8 => "r8",
+        Reg::R9 => "r9",
+        Reg::R10 => "r10",
+        Reg::R11 => "r11",
+        Reg::R12 => "r12",
+        Reg::R13 => "r13",
+        Reg::R14 => "r14",
+        Reg::R15 => "r15",
+    }
+}
+
+// The same instructions in AT&T syntax, for the GNU assembler: operands come
+// source first, and every instruction taking a register or memory operand is
+// suffixed with its 64-bit size.
+fn instr_to_gas(i: &Instr) -> String {
+    match i {
+        Instr::Mov(u @ Val::Reg(_), Val::Imm64(n)) if i32::try_from(*n).is_err() => format!("movabsq ${n}, {}\n", val_to_gas(u)),
+        Instr::Mov(u, v) => format!("movq {}, {}\n", val_to_gas(v), val_to_gas(u)),
+        Instr::Add(u, v) => format!("addq {}, {}\n", val_to_gas(v), val_to_gas(u)),
+        Instr::Sub(u, v) => format!("subq {}, {}\n", val_to_gas(v), val_to_gas(u)),
+        Instr::Imul(u, v) => format!("imulq {}, {}\n", val_to_gas(v), val_to_gas(u)),
+        Instr::And(u, v) => format!("andq {}, {}\n", val_to_gas(v), val_to_gas(u)),
+        Instr::Xor(u, v) => format!("xorq {}, {}\n", val_to_gas(v), val_to_gas(u)),
+        Instr::Sar(u, v) => format!("sarq {}, {}\n", val_to_gas(v), val_to_gas(u)),
+        Instr::Cmp(u, v) => format!("cmpq {}, {}\n", val_to_gas(v), val_to_gas(u)),
+        Instr::Test(u, v) => format!("testq {}, {}\n", val_to_gas(v), val_to_gas(u)),
+        Instr::Push(u) => format!("pushq {}\n", val_to_gas(u)),
+        Instr::Pop(u) => format!("popq {}\n", val_to_gas(u)),
+        Instr::Call(l) => format!("call {l}\n"),
+        Instr::CallInd(u) => format!("call *{}\n", val_to_gas(u)),
+        Instr::JmpInd(u) => format!("jmp *{}\n", val_to_gas(u)),
+        Instr::Leave => "leave\n".to_string(),
+        Instr::Ret => "ret\n".to_string(),
+        Instr::Cmov(c, u, v) => format!("cmov{}q {}, {}\n", c, val_to_gas(v), val_to_gas(u)),
+        Instr::Lea(u, v) => format!("leaq {}, {}\n", val_to_gas(v), val_to_gas(u)),
+        Instr::J("", l) => format!("jmp {l}\n"),
+        Instr::J(c, l) => format!("j{} {}\n", *c, l),
+        Instr::Label(l) => format!("{l}:\n"),
+    }
+}
+
+fn val_to_gas(v: &Val) -> String {
+    match v {
+        Val::Reg(r) => format!("%{}", reg_to_str(r)),
+        Val::Imm32(n) => format!("${n}"),
+        Val::Imm64(n) => format!("${n}"),
+        Val::RegOffset(r, n) => format!("{}(%{})", n, reg_to_str(r)),
+        Val::EffectiveAddr(b, i, s, d) => format!("{}(%{}, %{}, {})", d, reg_to_str(b), reg_to_str(i), s),
+        Val::RelLabel(l) => format!("{l}(%rip)"),
+    }
+}
+
+/// The label of the code that reports runtime errors, whose code is in RSI.
+pub const ERROR_LABEL: &str = "my_error";
+
+/// The label of the data word holding the program's input.
+pub const INPUT_LABEL: &str = "snek_input";
+
+/// The label of the entry point called by the runtime.
+pub const ENTRY_LABEL: &str = "our_code_starts_here";
+
+/// The runtime functions that compiled code calls.
+pub const RUNTIME_FUNS: [&str; 6] = ["snek_error", "snek_print", "snek_structural_eq_true", "snek_gc", "snek_string_append", "snek_string_ref"];
+
+/// Generates the instructions for a program. With `regalloc` unset, every
+/// variable is kept in the frame.
+pub fn generate(p: &ir::Prog, regalloc: bool) -> Vec<Instr<'static>> {
+    let mut instrs: Vec<Instr> = Vec::new();
+    let mut label = 0;
+    let regs: &[Reg] = if regalloc { &ALLOC_REGS } else { &[] };
+    let max_args = max_args(p);
+
+    instrs.push(Instr::Label(ERROR_LABEL.to_string()));
+    instrs.push(Instr::And(Val::Reg(Reg::RSP), Val::Imm32(-16)));
+    instrs.push(Instr::Mov(Val::Reg(Reg::RDI), Val::Reg(Reg::RSI)));
+    instrs.push(Instr::Call("snek_error".to_string()));
+
+    for f in &p.funs {
+        compile_fun(f, regs, max_args, &mut label, &mut instrs);
+    }
+
+    // our_code_starts_here(input, heap start, heap end) saves the registers
+    // the ABI has it preserve, and keeps the stack aligned for the call to main
+    instrs.push(Instr::Label(ENTRY_LABEL.to_string()));
+    for r in CALLEE_SAVED {
+        instrs.push(Instr::Push(Val::Reg(r)));
+    }
+    instrs.push(Instr::Sub(Val::Reg(Reg::RSP), Val::Imm32(8)));
+    instrs.push(Instr::Mov(Val::Reg(Reg::R15), Val::Reg(Reg::RSI)));
+    instrs.push(Instr::Mov(Val::Reg(Reg::R14), Val::Reg(Reg::RDX)));
+    instrs.push(Instr::Mov(Val::RelLabel(INPUT_LABEL.to_string()), Val::Reg(Reg::RDI)));
+    // a zero saved RBP marks the outermost snek frame for the garbage collector
+    instrs.push(Instr::Mov(Val::Reg(Reg::RBP), Val::Imm32(0)));
+    instrs.push(Instr::Call(ir::MAIN_LABEL.to_string()));
+    instrs.push(Instr::Add(Val::Reg(Reg::RSP), Val::Imm32(8)));
+    for r in CALLEE_SAVED.into_iter().rev() {
+        instrs.push(Instr::Pop(Val::Reg(r)));
+    }
+    instrs.push(Instr::Ret);
+
+    compile_fun(&p.main, regs, max_args, &mut label, &mut instrs);
+    instrs
+}
+
+/// Generates the assembly for a program. With `regalloc` unset, every variable
+/// is kept in the frame.
+pub fn compile(p: &ir::Prog, regalloc: bool) -> String {
+    let externs = RUNTIME_FUNS.iter().map(|f| format!("extern {f}\n")).collect::<String>();
+    let result = generate(p, regalloc).iter().map(instr_to_str).collect::<String>();
+    format!(
+        "
+section .text
+{externs}global {ENTRY_LABEL}
+{result}
+section .data
+{INPUT_LABEL}: dq 0
+"
+    )
+}
+
+/// Generates the assembly for a program in the syntax of the GNU assembler.
+/// With `regalloc` unset, every variable is kept in the frame.
+pub fn compile_gas(p: &ir::Prog, regalloc: bool) -> String {
+    let externs = RUNTIME_FUNS.iter().map(|f| format!(".extern {f}\n")).collect::<String>();
+    let result = generate(p, regalloc).iter().map(instr_to_gas).collect::<String>();
+    format!(
+        ".text
+{externs}.globl {ENTRY_LABEL}
+{result}
+.data
+.balign 8
+{INPUT_LABEL}: .quad 0
+.section .note.GNU-stack,\"\",@progbits
+"
+    )
+}
@@ -0,0 +1,90 @@
+.text
+.globl our_code_starts_here
+my_error:
+bl snek_error
+our_code_starts_here:
+stp x29, x30, [sp, #-96]!
+stp x19, x20, [sp, #16]
+stp x21, x22, [sp, #32]
+stp x23, x24, [sp, #48]
+stp x25, x26, [sp, #64]
+stp x27, x28, [sp, #80]
+mov x27, x1
+mov x28, x2
+adrp x9, snek_input
+str x0, [x9, :lo12:snek_input]
+mov x29, xzr
+bl __our_code_starts_here
+mov x0, x9
+ldp x19, x20, [sp, #16]
+ldp x21, x22, [sp, #32]
+ldp x23, x24, [sp, #48]
+ldp x25, x26, [sp, #64]
+ldp x27, x28, [sp, #80]
+ldp x29, x30, [sp], #96
+ret
+__our_code_starts_here:
+stp x29, x30, [sp, #-16]!
+mov x29, sp
+adrp x9, snek_input
+ldr x9, [x9, :lo12:snek_input]
+mov x20, x9
+mov x9, x20
+tst x9, #1
+mov x0, #1
+b.ne my_error
+mov x9, x20
+adds x9, x9, #2
+mov x0, #2
+b.vs my_error
+mov x20, x9
+mov x9, x20
+mov x10, #6
+asr x9, x9, #1
+smulh x11, x9, x10
+mul x9, x9, x10
+cmp x11, x9, asr #63
+mov x0, #2
+b.ne my_error
+mov x21, x9
+mov x9, x21
+movz x16, #3392
+movk x16, #3, lsl #16
+cmp x9, x16
+mov x10, #7
+mov x9, #3
+csel x9, x10, x9, lt
+mov x22, x9
+mov x9, x22
+cmp x9, #3
+b.eq ifelse_2
+ifthen_1:
+mov x9, x20
+adds x9, x9, #2
+mov x0, #2
+b.vs my_error
+mov x20, x9
+mov x9, x21
+subs x9, x9, x20
+mov x0, #2
+b.vs my_error
+mov x20, x9
+mov x9, x20
+mov sp, x29
+ldp x29, x30, [sp], #16
+ret
+ifelse_2:
+mov x9, x21
+tst x9, #1
+mov x10, #7
+mov x9, #3
+csel x9, x10, x9, eq
+mov x20, x9
+mov x9, x20
+mov sp, x29
+ldp x29, x30, [sp], #16
+ret
+
+.data
+.balign 8
+snek_input: .quad 0
@@ -0,0 +1,187 @@
+.text
+.globl our_code_starts_here
+my_error:
+bl snek_error
+func_count:
+stp x29, x30, [sp, #-16]!
+mov x29, sp
+sub sp, sp, #16
+ldr x9, [x29, #24]
+cmp x9, #0
+mov x10, #7
+mov x9, #3
+csel x9, x10, x9, eq
+str x9, [x29, #-8]
+ldr x9, [x29, #-8]
+cmp x9, #3
+b.eq ifelse_2
+ifthen_1:
+ldr x9, [x29, #32]
+mov sp, x29
+ldp x29, x30, [sp], #16
+ret
+ifelse_2:
+ldr x9, [x29, #24]
+tst x9, #1
+mov x0, #1
+b.ne my_error
+ldr x9, [x29, #24]
+subs x9, x9, #2
+mov x0, #2
+b.vs my_error
+str x9, [x29, #-8]
+ldr x9, [x29, #32]
+tst x9, #1
+mov x0, #1
+b.ne my_error
+ldr x9, [x29, #32]
+ldr x10, [x29, #24]
+adds x9, x9, x10
+mov x0, #2
+b.vs my_error
+str x9, [x29, #-16]
+sub sp, sp, #16
+ldr x9, [x29, #-8]
+str x9, [sp]
+ldr x9, [x29, #-16]
+str x9, [sp, #8]
+ldr x9, [sp]
+str x9, [x29, #24]
+ldr x9, [sp, #8]
+str x9, [x29, #32]
+add sp, sp, #16
+mov sp, x29
+ldp x29, x30, [sp], #16
+b func_count
+func_adder:
+stp x29, x30, [sp, #-16]!
+mov x29, sp
+sub sp, sp, #16
+add x9, x27, #32
+cmp x9, x28
+b.ls alloc_ok_0
+mov x0, x27
+mov x1, #32
+mov x2, sp
+mov x3, x29
+bl snek_gc
+mov x27, x0
+add x9, x27, #32
+cmp x9, x28
+mov x0, #4
+b.hi my_error
+alloc_ok_0:
+mov x9, #6
+str x9, [x27]
+adr x9, lambda_6
+str x9, [x27, #8]
+mov x9, #2
+str x9, [x27, #16]
+ldr x9, [x29, #24]
+str x9, [x27, #24]
+add x9, x27, #5
+add x27, x27, #32
+str x9, [x29, #-8]
+ldr x9, [x29, #-8]
+mov sp, x29
+ldp x29, x30, [sp], #16
+ret
+lambda_6:
+stp x29, x30, [sp, #-16]!
+mov x29, sp
+sub sp, sp, #16
+ldr x19, [x29, #16]
+and x19, x19, #-16
+ldr x9, [x19, #24]
+str x9, [x29, #-8]
+ldr x9, [x29, #-8]
+tst x9, #1
+mov x0, #1
+b.ne my_error
+ldr x9, [x29, #24]
+tst x9, #1
+mov x0, #1
+b.ne my_error
+ldr x9, [x29, #24]
+ldr x10, [x29, #-8]
+adds x9, x9, x10
+mov x0, #2
+b.vs my_error
+str x9, [x29, #-8]
+ldr x9, [x29, #-8]
+mov sp, x29
+ldp x29, x30, [sp], #16
+ret
+our_code_starts_here:
+stp x29, x30, [sp, #-96]!
+stp x19, x20, [sp, #16]
+stp x21, x22, [sp, #32]
+stp x23, x24, [sp, #48]
+stp x25, x26, [sp, #64]
+stp x27, x28, [sp, #80]
+mov x27, x1
+mov x28, x2
+adrp x9, snek_input
+str x0, [x9, :lo12:snek_input]
+mov x29, xzr
+bl __our_code_starts_here
+mov x0, x9
+ldp x19, x20, [sp, #16]
+ldp x21, x22, [sp, #32]
+ldp x23, x24, [sp, #48]
+ldp x25, x26, [sp, #64]
+ldp x27, x28, [sp, #80]
+ldp x29, x30, [sp], #96
+ret
+__our_code_starts_here:
+stp x29, x30, [sp, #-16]!
+mov x29, sp
+sub sp, sp, #16
+sub sp, sp, #32
+mov x9, #6
+str x9, [sp, #8]
+str xzr, [sp, #16]
+str xzr, [sp]
+bl func_adder
+add sp, sp, #32
+str x9, [x29, #-8]
+adrp x9, snek_input
+ldr x9, [x9, :lo12:snek_input]
+str x9, [x29, #-16]
+sub sp, sp, #32
+ldr x9, [x29, #-16]
+str x9, [sp, #8]
+mov x9, #0
+str x9, [sp, #16]
+str xzr, [sp]
+bl func_count
+add sp, sp, #32
+str x9, [x29, #-16]
+ldr x9, [x29, #-8]
+and x10, x9, #15
+cmp x10, #5
+mov x0, #1
+b.ne my_error
+sub sp, sp, #32
+ldr x9, [x29, #-16]
+str x9, [sp, #8]
+str xzr, [sp, #16]
+ldr x9, [x29, #-8]
+and x19, x9, #-16
+ldr x11, [x19, #16]
+cmp x11, #2
+mov x0, #5
+b.ne my_error
+str x9, [sp]
+ldr x10, [x19, #8]
+blr x10
+add sp, sp, #32
+str x9, [x29, #-8]
+ldr x9, [x29, #-8]
+mov sp, x29
+ldp x29, x30, [sp], #16
+ret
+
+.data
+.balign 8
+snek_input: .quad 0
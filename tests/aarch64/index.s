@@ -0,0 +1,176 @@
+.text
+.globl our_code_starts_here
+my_error:
+bl snek_error
+our_code_starts_here:
+stp x29, x30, [sp, #-96]!
+stp x19, x20, [sp, #16]
+stp x21, x22, [sp, #32]
+stp x23, x24, [sp, #48]
+stp x25, x26, [sp, #64]
+stp x27, x28, [sp, #80]
+mov x27, x1
+mov x28, x2
+adrp x9, snek_input
+str x0, [x9, :lo12:snek_input]
+mov x29, xzr
+bl __our_code_starts_here
+mov x0, x9
+ldp x19, x20, [sp, #16]
+ldp x21, x22, [sp, #32]
+ldp x23, x24, [sp, #48]
+ldp x25, x26, [sp, #64]
+ldp x27, x28, [sp, #80]
+ldp x29, x30, [sp], #96
+ret
+__our_code_starts_here:
+stp x29, x30, [sp, #-16]!
+mov x29, sp
+sub sp, sp, #16
+adrp x9, snek_input
+ldr x9, [x9, :lo12:snek_input]
+str x9, [x29, #-8]
+add x9, x27, #32
+cmp x9, x28
+b.ls alloc_ok_0
+mov x0, x27
+mov x1, #32
+mov x2, sp
+mov x3, x29
+bl snek_gc
+mov x27, x0
+add x9, x27, #32
+cmp x9, x28
+mov x0, #4
+b.hi my_error
+alloc_ok_0:
+mov x9, #4
+str x9, [x27]
+mov x9, #2
+str x9, [x27, #8]
+ldr x9, [x29, #-8]
+str x9, [x27, #16]
+add x9, x27, #1
+add x27, x27, #32
+str x9, [x29, #-8]
+mov x9, #6
+cmp x9, #0
+mov x0, #1
+b.lt my_error
+movz x16, #65535
+movk x16, #32767, lsl #16
+cmp x9, x16
+mov x0, #4
+b.gt my_error
+lsl x19, x9, #2
+add x19, x19, #23
+and x19, x19, #-16
+add x9, x27, x19
+cmp x9, x28
+b.ls alloc_ok_1
+mov x0, x27
+mov x1, x19
+mov x2, sp
+mov x3, x29
+bl snek_gc
+mov x27, x0
+add x9, x27, x19
+cmp x9, x28
+mov x0, #4
+b.hi my_error
+alloc_ok_1:
+mov x11, #6
+str x11, [x27]
+asr x11, x11, #1
+ldr x9, [x29, #-8]
+fill_2:
+cmp x11, #0
+b.eq fill_done_3
+str x9, [x27, x11, lsl #3]
+sub x11, x11, #1
+b fill_2
+fill_done_3:
+add x9, x27, #13
+add x27, x27, x19
+mov x20, x9
+mov x0, #3
+mov x9, x20
+cmp x9, #1
+b.eq my_error
+and x9, x9, #-16
+mov x10, #2
+ldr x11, [x9]
+cmp x10, x11
+b.hs my_error
+mov x0, #3
+ldr x9, [x29, #-8]
+cmp x9, #1
+b.eq my_error
+and x9, x9, #-16
+mov x10, #0
+ldr x11, [x9]
+cmp x10, x11
+b.hs my_error
+ldr x9, [x29, #-8]
+and x9, x9, #-16
+mov x10, #0
+add x9, x9, x10, lsl #2
+ldr x9, [x9, #8]
+mov x21, x9
+mov x9, x20
+and x9, x9, #-16
+mov x10, #2
+add x10, x9, x10, lsl #2
+mov x9, x21
+str x9, [x10, #8]
+mov x21, x9
+mov x9, x20
+and x9, x9, #-16
+ldr x9, [x9]
+str x9, [x29, #-8]
+mov x0, #3
+mov x9, x20
+cmp x9, #1
+b.eq my_error
+and x9, x9, #-16
+mov x10, #4
+ldr x11, [x9]
+cmp x10, x11
+b.hs my_error
+mov x9, x20
+and x9, x9, #-16
+mov x10, #4
+add x9, x9, x10, lsl #2
+ldr x9, [x9, #8]
+str x9, [x29, #-16]
+add x9, x27, #32
+cmp x9, x28
+b.ls alloc_ok_4
+mov x0, x27
+mov x1, #32
+mov x2, sp
+mov x3, x29
+bl snek_gc
+mov x27, x0
+add x9, x27, #32
+cmp x9, x28
+mov x0, #4
+b.hi my_error
+alloc_ok_4:
+mov x9, #4
+str x9, [x27]
+ldr x9, [x29, #-8]
+str x9, [x27, #8]
+ldr x9, [x29, #-16]
+str x9, [x27, #16]
+add x9, x27, #1
+add x27, x27, #32
+mov x20, x9
+mov x9, x20
+mov sp, x29
+ldp x29, x30, [sp], #16
+ret
+
+.data
+.balign 8
+snek_input: .quad 0
@@ -0,0 +1,214 @@
+.text
+.globl our_code_starts_here
+my_error:
+bl snek_error
+our_code_starts_here:
+stp x29, x30, [sp, #-96]!
+stp x19, x20, [sp, #16]
+stp x21, x22, [sp, #32]
+stp x23, x24, [sp, #48]
+stp x25, x26, [sp, #64]
+stp x27, x28, [sp, #80]
+mov x27, x1
+mov x28, x2
+adrp x9, snek_input
+str x0, [x9, :lo12:snek_input]
+mov x29, xzr
+bl __our_code_starts_here
+mov x0, x9
+ldp x19, x20, [sp, #16]
+ldp x21, x22, [sp, #32]
+ldp x23, x24, [sp, #48]
+ldp x25, x26, [sp, #64]
+ldp x27, x28, [sp, #80]
+ldp x29, x30, [sp], #96
+ret
+__our_code_starts_here:
+stp x29, x30, [sp, #-16]!
+mov x29, sp
+sub sp, sp, #32
+add x9, x27, #16
+cmp x9, x28
+b.ls alloc_ok_0
+mov x0, x27
+mov x1, #16
+mov x2, sp
+mov x3, x29
+bl snek_gc
+mov x27, x0
+add x9, x27, #16
+cmp x9, x28
+mov x0, #4
+b.hi my_error
+alloc_ok_0:
+mov x9, #11
+str x9, [x27]
+movz x9, #28535
+movk x9, #27762, lsl #16
+movk x9, #100, lsl #32
+str x9, [x27, #8]
+add x9, x27, #9
+add x27, x27, #16
+str x9, [x29, #-8]
+add x9, x27, #16
+cmp x9, x28
+b.ls alloc_ok_1
+mov x0, x27
+mov x1, #16
+mov x2, sp
+mov x3, x29
+bl snek_gc
+mov x27, x0
+add x9, x27, #16
+cmp x9, x28
+mov x0, #4
+b.hi my_error
+alloc_ok_1:
+mov x9, #15
+str x9, [x27]
+movz x9, #25960
+movk x9, #27756, lsl #16
+movk x9, #11375, lsl #32
+movk x9, #32, lsl #48
+str x9, [x27, #8]
+add x9, x27, #9
+add x27, x27, #16
+str x9, [x29, #-16]
+ldr x9, [x29, #-8]
+and x9, x9, #-16
+ldr x19, [x9]
+asr x19, x19, #1
+ldr x9, [x29, #-16]
+and x9, x9, #-16
+ldr x9, [x9]
+asr x9, x9, #1
+add x19, x19, x9
+add x19, x19, #23
+and x19, x19, #-16
+add x9, x27, x19
+cmp x9, x28
+b.ls alloc_ok_2
+mov x0, x27
+mov x1, x19
+mov x2, sp
+mov x3, x29
+bl snek_gc
+mov x27, x0
+add x9, x27, x19
+cmp x9, x28
+mov x0, #4
+b.hi my_error
+alloc_ok_2:
+mov x0, x27
+ldr x1, [x29, #-16]
+ldr x2, [x29, #-8]
+bl snek_string_append
+mov x9, x0
+add x27, x27, x19
+str x9, [x29, #-8]
+ldr x9, [x29, #-8]
+and x9, x9, #-16
+ldr x9, [x9]
+and x9, x9, #-2
+str x9, [x29, #-16]
+adrp x9, snek_input
+ldr x9, [x9, :lo12:snek_input]
+str x9, [x29, #-24]
+ldr x9, [x29, #-24]
+tst x9, #1
+mov x0, #1
+b.ne my_error
+mov x0, #3
+ldr x9, [x29, #-8]
+and x9, x9, #-16
+ldr x10, [x9]
+sub x10, x10, #1
+ldr x11, [x29, #-24]
+cmp x11, x10
+b.hs my_error
+add x9, x27, #16
+cmp x9, x28
+b.ls alloc_ok_3
+mov x0, x27
+mov x1, #16
+mov x2, sp
+mov x3, x29
+bl snek_gc
+mov x27, x0
+add x9, x27, #16
+cmp x9, x28
+mov x0, #4
+b.hi my_error
+alloc_ok_3:
+mov x0, x27
+ldr x1, [x29, #-8]
+ldr x2, [x29, #-24]
+bl snek_string_ref
+mov x9, x0
+add x27, x27, #16
+str x9, [x29, #-24]
+add x9, x27, #32
+cmp x9, x28
+b.ls alloc_ok_4
+mov x0, x27
+mov x1, #32
+mov x2, sp
+mov x3, x29
+bl snek_gc
+mov x27, x0
+add x9, x27, #32
+cmp x9, x28
+mov x0, #4
+b.hi my_error
+alloc_ok_4:
+mov x9, #25
+str x9, [x27]
+movz x9, #25960
+movk x9, #27756, lsl #16
+movk x9, #11375, lsl #32
+movk x9, #30496, lsl #48
+str x9, [x27, #8]
+movz x9, #29295
+movk x9, #25708, lsl #16
+str x9, [x27, #16]
+add x9, x27, #9
+add x27, x27, #32
+mov x20, x9
+ldr x0, [x29, #-8]
+mov x1, x20
+bl snek_structural_eq_true
+mov x9, x0
+str x9, [x29, #-8]
+add x9, x27, #32
+cmp x9, x28
+b.ls alloc_ok_5
+mov x0, x27
+mov x1, #32
+mov x2, sp
+mov x3, x29
+bl snek_gc
+mov x27, x0
+add x9, x27, #32
+cmp x9, x28
+mov x0, #4
+b.hi my_error
+alloc_ok_5:
+mov x9, #6
+str x9, [x27]
+ldr x9, [x29, #-16]
+str x9, [x27, #8]
+ldr x9, [x29, #-24]
+str x9, [x27, #16]
+ldr x9, [x29, #-8]
+str x9, [x27, #24]
+add x9, x27, #1
+add x27, x27, #32
+mov x20, x9
+mov x9, x20
+mov sp, x29
+ldp x29, x30, [sp], #16
+ret
+
+.data
+.balign 8
+snek_input: .quad 0
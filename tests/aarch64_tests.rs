@@ -0,0 +1,37 @@
+mod infra;
+
+// There is no AArch64 machine to run these on, so the assembly is checked
+// against golden files. `make tests/NAME.aarch64.run` builds an executable
+// for qemu-aarch64 with a cross toolchain.
+golden_tests! {
+    {
+        name: aarch64_arith,
+        file: "aarch64/arith.snek",
+        flags: ["--target", "aarch64"],
+        golden: "aarch64/arith.s",
+    },
+    {
+        name: aarch64_funs,
+        file: "aarch64/funs.snek",
+        flags: ["--target", "aarch64"],
+        golden: "aarch64/funs.s",
+    },
+    {
+        name: aarch64_index,
+        file: "aarch64/index.snek",
+        flags: ["--target", "aarch64"],
+        golden: "aarch64/index.s",
+    },
+    {
+        name: aarch64_string,
+        file: "aarch64/string.snek",
+        flags: ["--target", "aarch64"],
+        golden: "aarch64/string.s",
+    },
+    {
+        name: aarch64_no_regalloc,
+        file: "aarch64/funs.snek",
+        flags: ["--target", "aarch64", "--no-regalloc"],
+        golden: "aarch64/funs_no_regalloc.s",
+    },
+}
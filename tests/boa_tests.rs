@@ -170,4 +170,51 @@ success_tests! {
         input: "0",
         expected: "-8905",
     },
-}
\ No newline at end of file
+}
+static_error_tests! {
+    {
+        name: err1_unclosed_paren,
+        file: "boa/err1.snek",
+        expected: "err1.snek:1:3: Invalid s-expression",
+    },
+    {
+        name: err2_invalid_expression,
+        file: "boa/err2.snek",
+        expected: "err2.snek:1:3: Invalid expression",
+    },
+    {
+        name: err3_invalid_binding,
+        file: "boa/err3.snek",
+        expected: "err3.snek:1:7: Invalid let expression",
+    },
+    {
+        name: err4_empty_let,
+        file: "boa/err4.snek",
+        expected: "err4.snek:1:1: Invalid let expression",
+    },
+    {
+        name: err5_wrong_arity,
+        file: "boa/err5.snek",
+        expected: "err5.snek:1:1: Invalid: + takes 2 arguments\n  |\n1 | (+ 10086)\n  | ^^^^^^^^^",
+    },
+    {
+        name: err6_self_reference,
+        file: "boa/err6.snek",
+        expected: "err6.snek:1:10: Unbound variable identifier x",
+    },
+    {
+        name: err7_duplicate_binding,
+        file: "boa/err7.snek",
+        expected: "Duplicate binding",
+    },
+    {
+        name: duplicate_binding,
+        file: "boa/duplicate_binding.snek",
+        expected: "duplicate_binding.snek:1:1: Duplicate binding x",
+    },
+    {
+        name: unbound_id,
+        file: "boa/unbound_id.snek",
+        expected: "unbound_id.snek:1:1: Unbound variable identifier x\n  |\n1 | x\n  | ^",
+    },
+}
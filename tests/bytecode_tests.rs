@@ -0,0 +1,122 @@
+mod infra;
+
+// Programs compiled to bytecode with `--target=bytecode` and run by `snek-vm`
+// must behave as they do when run in memory.
+differential_tests! {
+    {
+        name: bytecode_examples,
+        dir: ".",
+        flags: ["--target=bytecode"],
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: bytecode_diamondback,
+        dir: "diamondback",
+        flags: ["--target=bytecode"],
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: bytecode_closures,
+        dir: "closures",
+        flags: ["--target=bytecode"],
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: bytecode_strings,
+        dir: "strings",
+        flags: ["--target=bytecode"],
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: bytecode_vectors,
+        dir: "vectors",
+        flags: ["--target=bytecode"],
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: bytecode_tail,
+        dir: "tail",
+        flags: ["--target=bytecode"],
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: bytecode_gc,
+        dir: "gc",
+        flags: ["--target=bytecode"],
+        args: ["run"],
+        input: "5",
+    },
+}
+
+success_tests! {
+    {
+        name: bytecode_fact,
+        file: "diamondback/fact.snek",
+        flags: ["--target=bytecode"],
+        input: "10",
+        expected: "3628800",
+    },
+    {
+        name: bytecode_deep_tail_calls,
+        file: "tail/count.snek",
+        flags: ["--target=bytecode"],
+        input: "10000000",
+        expected: "10000000",
+    },
+    {
+        name: bytecode_cycle,
+        file: "cycle-print1.snek",
+        flags: ["--target=bytecode"],
+        expected: "((1 20) (3 40) (-5))\n(((3 40) 20) (3 40) (-5))\n(((3 (...)) 20) (3 ((...) 20)) (-5))\n(((...) 20) (3 ((...) 20)) (-5))\n(((...) 20) (3 ((...) 20)) ((...)))\n0",
+    },
+    {
+        name: bytecode_small_heap,
+        file: "gc/live_list.snek",
+        flags: ["--target=bytecode"],
+        heap_size: 65536,
+        expected: "499500",
+    },
+}
+
+runtime_error_tests! {
+    {
+        name: bytecode_invalid_argument,
+        file: "cobra/invalid_argument.snek",
+        flags: ["--target=bytecode"],
+        expected: "invalid argument",
+    },
+    {
+        name: bytecode_overflow,
+        file: "opt/overflow.snek",
+        flags: ["--target=bytecode"],
+        input: "0",
+        expected: "overflow",
+    },
+    {
+        name: bytecode_index_out_of_range,
+        file: "vectors/get_out_of_range.snek",
+        flags: ["--target=bytecode"],
+        input: "-1",
+        expected: "index out of range",
+    },
+    {
+        name: bytecode_out_of_memory,
+        file: "gc/grow_forever.snek",
+        flags: ["--target=bytecode"],
+        heap_size: 65536,
+        expected: "out of memory",
+    },
+    {
+        name: bytecode_heap_size_missing_value,
+        file: "gc/too_big.snek",
+        flags: ["--target=bytecode"],
+        input: "--heap-size",
+        expected: "option --heap-size needs a value",
+    },
+}
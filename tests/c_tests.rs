@@ -0,0 +1,104 @@
+mod infra;
+
+// Programs translated to C with `--target=c`, compiled with `cc` and linked
+// with the runtime, must behave as they do when run in memory.
+differential_tests! {
+    {
+        name: c_examples,
+        dir: ".",
+        flags: ["--target=c"],
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: c_diamondback,
+        dir: "diamondback",
+        flags: ["--target=c"],
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: c_closures,
+        dir: "closures",
+        flags: ["--target=c"],
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: c_strings,
+        dir: "strings",
+        flags: ["--target=c"],
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: c_vectors,
+        dir: "vectors",
+        flags: ["--target=c"],
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: c_tail,
+        dir: "tail",
+        flags: ["--target=c"],
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: c_gc,
+        dir: "gc",
+        flags: ["--target=c"],
+        args: ["run"],
+        input: "5",
+    },
+}
+
+success_tests! {
+    {
+        name: c_fact,
+        file: "diamondback/fact.snek",
+        flags: ["--target=c"],
+        input: "10",
+        expected: "3628800",
+    },
+    {
+        name: c_no_regalloc,
+        file: "diamondback/my2.snek",
+        flags: ["--target=c", "--no-regalloc"],
+        input: "10",
+        expected: "89",
+    },
+    {
+        name: c_deep_tail_calls,
+        file: "tail/count.snek",
+        flags: ["--target=c"],
+        input: "10000000",
+        expected: "10000000",
+    },
+}
+
+runtime_error_tests! {
+    {
+        name: c_overflow,
+        file: "opt/overflow.snek",
+        flags: ["--target=c"],
+        input: "0",
+        expected: "overflow",
+    },
+    {
+        name: c_wrong_arity,
+        file: "closures/wrong_arity.snek",
+        flags: ["--target=c"],
+        expected: "wrong number of arguments",
+    },
+}
+
+static_error_tests! {
+    {
+        name: c_unknown_target,
+        file: "diamondback/fact.snek",
+        flags: ["--target=riscv64"],
+        expected: "unknown target",
+    },
+}
@@ -0,0 +1,118 @@
+mod infra;
+
+success_tests! {
+    {
+        name: lambda,
+        file: "closures/lambda.snek",
+        expected: "3",
+    },
+    {
+        name: adder,
+        file: "closures/adder.snek",
+        input: "7",
+        expected: "(12 17)",
+    },
+    {
+        name: map,
+        file: "closures/map.snek",
+        input: "10",
+        expected: "(1 (4 (9 ())))\n(11 (12 (13 ())))",
+    },
+    {
+        name: compose,
+        file: "closures/compose.snek",
+        input: "3",
+        expected: "9",
+    },
+    {
+        name: print_function,
+        file: "closures/print.snek",
+        expected: "<function>\n(1 <function>)\n<function>",
+    },
+    {
+        name: isfun,
+        file: "closures/isfun.snek",
+        expected: "(true true false false false false)",
+    },
+    {
+        name: shadow_function,
+        file: "closures/shadow.snek",
+        expected: "(2 100)",
+    },
+    {
+        name: nested_capture,
+        file: "closures/nested.snek",
+        expected: "11111",
+    },
+    {
+        name: counter,
+        file: "closures/counter.snek",
+        input: "40",
+        expected: "43",
+    },
+    {
+        name: closure_churn,
+        file: "closures/closure_churn.snek",
+        heap_size: 65536,
+        expected: "20000099999",
+    },
+    {
+        name: set_captured,
+        file: "closures/set_captured.snek",
+        input: "40",
+        expected: "43",
+    },
+    {
+        name: set_after_capture,
+        file: "closures/set_after_capture.snek",
+        input: "40",
+        expected: "41",
+    },
+    {
+        name: shared_param,
+        file: "closures/shared_param.snek",
+        input: "40",
+        expected: "55",
+    },
+    {
+        name: set_nested,
+        file: "closures/set_nested.snek",
+        expected: "(20 20)",
+    },
+}
+
+runtime_error_tests! {
+    {
+        name: not_a_function,
+        file: "closures/not_a_function.snek",
+        expected: "invalid argument",
+    },
+    {
+        name: wrong_arity,
+        file: "closures/wrong_arity.snek",
+        expected: "wrong number of arguments",
+    },
+}
+
+static_error_tests! {
+    {
+        name: lambda_bad_param,
+        file: "closures/lambda_bad_param.snek",
+        expected: "Invalid definition: bad parameter name",
+    },
+    {
+        name: lambda_break,
+        file: "closures/lambda_break.snek",
+        expected: "Invalid: break outside of loop",
+    },
+    {
+        name: call_input,
+        file: "closures/call_input.snek",
+        expected: "call_input.snek:1:7: Invalid: cannot call input",
+    },
+    {
+        name: tail_call_input,
+        file: "closures/tail_call_input.snek",
+        expected: "tail_call_input.snek:1:12: Invalid: cannot call input",
+    },
+}
@@ -41,6 +41,11 @@ success_tests! {
         input: "1",
         expected: "6",
     },
+    {
+        name: nested_deep,
+        file: "cobra/nested.snek",
+        expected: "-1",
+    },
 }
 
 runtime_error_tests! {
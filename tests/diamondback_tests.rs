@@ -68,6 +68,23 @@ success_tests! {
         input: "10",
         expected: "3628800",
     },
+    {
+        name: input_in_fun,
+        file: "diamondback/err4.snek",
+        input: "5",
+        expected: "6",
+    },
+    {
+        name: input_in_nested_funs,
+        file: "diamondback/input_in_nested_funs.snek",
+        input: "7",
+        expected: "7\n14",
+    },
+    {
+        name: nested_deep,
+        file: "diamondback/nested.snek",
+        expected: "-1",
+    },
 }
 
 runtime_error_tests! {
@@ -119,13 +136,28 @@ static_error_tests! {
         expected: "",
     },
     {
-        name: err4_input_in_fun,
-        file: "diamondback/err4.snek",
-        expected: "",
+        name: set_input_in_fun,
+        file: "diamondback/set_input_in_fun.snek",
+        expected: "Invalid: cannot assign to input",
+    },
+    {
+        name: set_input_in_main,
+        file: "diamondback/set_input_in_main.snek",
+        expected: "Invalid: cannot assign to input",
     },
     {
         name: err5_function_undefined,
         file: "diamondback/err5.snek",
         expected: "",
     },
+    {
+        name: multiple_errors,
+        file: "diamondback/multiple_errors.snek",
+        expected: "aborting due to 7 previous errors",
+    },
+    {
+        name: recover_after_unclosed,
+        file: "diamondback/recover_after_unclosed.snek",
+        expected: "recover_after_unclosed.snek:3:18: Unbound variable identifier z",
+    },
 }
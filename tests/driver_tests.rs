@@ -0,0 +1,151 @@
+mod infra;
+
+driver_tests! {
+    {
+        name: driver_build,
+        args: ["build"],
+        file: "tail/even_odd.snek",
+        input: "11",
+        expected: "(false true)",
+    },
+    {
+        name: driver_build_exe,
+        args: ["build", "--emit=exe", "-O"],
+        file: "tail/count.snek",
+        input: "1000000",
+        expected: "1000000",
+    },
+    {
+        name: driver_build_stdin,
+        args: ["build"],
+        file: "strings/hello.snek",
+        stdin: true,
+        expected: "hello, world",
+    },
+    {
+        name: driver_build_asm,
+        args: ["build", "--emit=asm"],
+        file: "tail/even_odd.snek",
+        input: "4",
+        expected: "(true false)",
+    },
+    {
+        name: driver_build_gas,
+        args: ["build", "--emit=asm", "--syntax=gas"],
+        file: "tail/even_odd.snek",
+        input: "4",
+        expected: "(true false)",
+    },
+    {
+        name: driver_build_obj,
+        args: ["build", "--emit=obj"],
+        file: "tail/count.snek",
+        input: "7",
+        expected: "7",
+    },
+    {
+        name: driver_build_runtime,
+        args: ["build", "--runtime", "runtime/libsnek.a"],
+        file: "strings/hello.snek",
+        expected: "hello, world",
+    },
+    {
+        name: driver_missing_runtime,
+        args: ["build", "--runtime", "tests/missing.a"],
+        file: "strings/hello.snek",
+        expected: "",
+        stderr: "runtime library tests/missing.a not found",
+    },
+    {
+        name: driver_build_ir,
+        args: ["build", "--emit=ir"],
+        file: "strings/hello.snek",
+        expected: "",
+        stderr: "use `emit` for the IR",
+    },
+    {
+        name: driver_build_c,
+        args: ["build", "--target=c"],
+        file: "strings/hello.snek",
+        expected: "",
+        stderr: "machine code is only generated for x86_64",
+    },
+    {
+        name: driver_run_stdin,
+        args: ["run"],
+        file: "tail/even_odd.snek",
+        stdin: true,
+        input: "3",
+        expected: "(false true)",
+    },
+    {
+        name: driver_check,
+        args: ["check"],
+        file: "tail/even_odd.snek",
+        expected: "",
+    },
+    {
+        name: driver_check_error,
+        args: ["check"],
+        file: "strings/unterminated.snek",
+        stdin: true,
+        expected: "",
+        stderr: "<stdin>:1:8: Invalid s-expression: unterminated string",
+    },
+    {
+        name: driver_emit_ir,
+        args: ["emit", "--emit=ir"],
+        file: "strings/hello.snek",
+        stdin: true,
+        expected: "fun __our_code_starts_here():\nentry_0:\n  %0 := \"hello, world\"\n  ret %0",
+    },
+    {
+        name: driver_emit_exe,
+        args: ["emit", "--emit=exe"],
+        file: "strings/hello.snek",
+        expected: "",
+        stderr: "executables are made with `build`",
+    },
+    {
+        name: driver_unknown_emit,
+        args: ["build", "--emit=wasm"],
+        file: "strings/hello.snek",
+        expected: "",
+        stderr: "unknown output kind: wasm",
+    },
+    {
+        name: driver_emit_spaced,
+        args: ["emit", "--emit", "ir"],
+        file: "strings/hello.snek",
+        stdin: true,
+        expected: "fun __our_code_starts_here():\nentry_0:\n  %0 := \"hello, world\"\n  ret %0",
+    },
+    {
+        name: driver_heap_size_spaced,
+        args: ["run", "--heap-size", "16"],
+        file: "strings/hello.snek",
+        expected: "",
+        stderr: "out of memory",
+    },
+    {
+        name: driver_duplicate_output,
+        args: ["build", "-o", "tests/driver_duplicate_output.run"],
+        file: "strings/hello.snek",
+        expected: "",
+        stderr: "option -o given more than once",
+    },
+    {
+        name: driver_duplicate_target,
+        args: ["emit", "--target=c", "--target", "aarch64"],
+        file: "strings/hello.snek",
+        expected: "",
+        stderr: "option --target given more than once",
+    },
+    {
+        name: driver_unknown_option,
+        args: ["check", "--bogus"],
+        file: "strings/hello.snek",
+        expected: "",
+        stderr: "unknown option: --bogus",
+    },
+}
@@ -0,0 +1,83 @@
+mod infra;
+
+// Programs written in GNU assembler syntax with `--syntax=gas`, assembled with
+// `as` and linked with the runtime, must behave as they do when run in memory.
+differential_tests! {
+    {
+        name: gas_examples,
+        dir: ".",
+        flags: ["--syntax=gas"],
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: gas_diamondback,
+        dir: "diamondback",
+        flags: ["--syntax=gas"],
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: gas_closures,
+        dir: "closures",
+        flags: ["--syntax=gas"],
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: gas_strings,
+        dir: "strings",
+        flags: ["--syntax=gas"],
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: gas_vectors,
+        dir: "vectors",
+        flags: ["--syntax=gas"],
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: gas_gc,
+        dir: "gc",
+        flags: ["--syntax=gas"],
+        args: ["run"],
+        input: "5",
+    },
+}
+
+success_tests! {
+    {
+        name: gas_fact,
+        file: "diamondback/fact.snek",
+        flags: ["--syntax=gas"],
+        input: "10",
+        expected: "3628800",
+    },
+    {
+        name: gas_no_regalloc,
+        file: "diamondback/my2.snek",
+        flags: ["--syntax=gas", "--no-regalloc"],
+        input: "10",
+        expected: "89",
+    },
+}
+
+runtime_error_tests! {
+    {
+        name: gas_invalid_argument,
+        file: "cobra/invalid_argument.snek",
+        flags: ["--syntax=gas"],
+        expected: "invalid argument",
+    },
+}
+
+static_error_tests! {
+    {
+        name: gas_unknown_syntax,
+        file: "diamondback/fact.snek",
+        flags: ["--syntax=att"],
+        expected: "unknown assembly syntax",
+    },
+}
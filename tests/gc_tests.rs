@@ -0,0 +1,63 @@
+mod infra;
+
+success_tests! {
+    {
+        name: garbage_loop,
+        file: "gc/garbage_loop.snek",
+        expected: "10000000",
+    },
+    {
+        name: live_list,
+        file: "gc/live_list.snek",
+        expected: "499500",
+    },
+    {
+        name: bst_churn,
+        file: "gc/bst_churn.snek",
+        expected: "(-13 () (0 () (7 (3 () (6 () ())) (27 (9 () (12 () (15 () (18 () (21 () ()))))) (47 () (67 () (87 () (107 () (127 () ())))))))))",
+    },
+    {
+        name: cycle,
+        file: "gc/cycle.snek",
+        expected: "(1 (...))",
+    },
+    {
+        name: live_list_small_heap,
+        file: "gc/live_list.snek",
+        heap_size: 65536,
+        expected: "499500",
+    },
+    {
+        name: tuple_fits_exactly,
+        file: "gc/too_big.snek",
+        heap_size: 48,
+        expected: "(1 2 3 4)",
+    },
+}
+
+runtime_error_tests! {
+    {
+        name: grow_forever,
+        file: "gc/grow_forever.snek",
+        heap_size: 65536,
+        expected: "out of memory",
+    },
+    {
+        name: too_big,
+        file: "gc/too_big.snek",
+        heap_size: 32,
+        expected: "out of memory",
+    },
+    {
+        name: empty_heap,
+        file: "gc/too_big.snek",
+        heap_size: 0,
+        expected: "out of memory",
+    },
+    {
+        name: heap_size_missing_value,
+        file: "gc/too_big.snek",
+        input: "--heap-size",
+        expected: "option --heap-size needs a value",
+    },
+}
@@ -0,0 +1,74 @@
+mod infra;
+
+golden_tests! {
+    {
+        name: infer_arith_ir,
+        file: "infer/arith.snek",
+        flags: ["--report-checks", "--emit=ir"],
+        golden: "infer/arith.ir",
+        stderr: "removed 7 of 8 tag checks",
+    },
+    {
+        name: infer_branches_ir,
+        file: "infer/branches.snek",
+        flags: ["--report-checks", "--emit=ir"],
+        golden: "infer/branches.ir",
+        stderr: "removed 2 of 3 tag checks",
+    },
+    {
+        name: infer_loop_ir,
+        file: "infer/loop.snek",
+        flags: ["--report-checks", "--emit=ir"],
+        golden: "infer/loop.ir",
+        stderr: "removed 12 of 15 tag checks",
+    },
+    {
+        name: infer_mixed_ir,
+        file: "infer/mixed.snek",
+        flags: ["--report-checks", "--emit=ir"],
+        golden: "infer/mixed.ir",
+        stderr: "removed 2 of 4 tag checks",
+    },
+}
+
+success_tests! {
+    {
+        name: infer_arith,
+        file: "infer/arith.snek",
+        input: "3",
+        expected: "14",
+    },
+    {
+        name: infer_branches,
+        file: "infer/branches.snek",
+        input: "false",
+        expected: "12",
+    },
+    {
+        name: infer_loop,
+        file: "infer/loop.snek",
+        input: "4",
+        expected: "2",
+    },
+    {
+        name: infer_mixed,
+        file: "infer/mixed.snek",
+        input: "5",
+        expected: "6",
+    },
+}
+
+runtime_error_tests! {
+    {
+        name: infer_mixed_false,
+        file: "infer/mixed.snek",
+        input: "-1",
+        expected: "invalid argument",
+    },
+    {
+        name: infer_arith_bool,
+        file: "infer/arith.snek",
+        input: "true",
+        expected: "invalid argument",
+    },
+}
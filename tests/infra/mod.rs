@@ -1,8 +1,10 @@
 use std::{
+    fs::File,
     path::{Path, PathBuf},
     process::Command,
 };
 
+#[allow(dead_code)]
 pub(crate) enum TestKind {
     Success,
     RuntimeError,
@@ -31,7 +33,9 @@ macro_rules! tests {
             {
                 name: $name:ident,
                 file: $file:literal,
+                $(flags: [$($flag:literal),* $(,)?],)?
                 $(input: $input:literal,)?
+                $(heap_size: $heap_size:literal,)?
                 expected: $expected:literal $(,)?
                 $(" $(tt:$tt)* ")?
             }
@@ -44,33 +48,175 @@ macro_rules! tests {
                 #[allow(unused_assignments, unused_mut)]
                 let mut input = None;
                 $(input = Some($input);)?
+                #[allow(unused_assignments, unused_mut)]
+                let mut heap_size = None;
+                $(heap_size = Some($heap_size);)?
+                #[allow(unused_assignments, unused_mut)]
+                let mut flags: &[&str] = &[];
+                $(flags = &[$($flag),*];)?
                 let kind = $crate::infra::TestKind::$kind;
-                $crate::infra::run_test(stringify!($name), $file, input, $expected, kind);
+                $crate::infra::run_test(stringify!($name), $file, flags, input, heap_size, $expected, kind);
+            }
+        )*
+    };
+}
+
+// Compiles `file` with `flags` and compares what the compiler writes with the
+// golden file `golden`. Setting `BLESS` overwrites the golden file instead.
+// `stderr`, if given, must be part of what the compiler prints there.
+#[macro_export]
+macro_rules! golden_tests {
+    (
+        $(
+            {
+                name: $name:ident,
+                file: $file:literal,
+                flags: [$($flag:literal),* $(,)?],
+                golden: $golden:literal
+                $(, stderr: $stderr:literal)? $(,)?
+            }
+        ),*
+        $(,)?
+    ) => {
+        $(
+            #[test]
+            fn $name() {
+                #[allow(unused_assignments, unused_mut)]
+                let mut stderr = None;
+                $(stderr = Some($stderr);)?
+                $crate::infra::run_golden_test(stringify!($name), $file, &[$($flag),*], $golden, stderr);
+            }
+        )*
+    };
+}
+
+// Feeds `file` to `repl` line by line and compares what it prints with
+// `expected`. `stderr`, if given, must be part of what it reports there.
+#[macro_export]
+macro_rules! repl_tests {
+    (
+        $(
+            {
+                name: $name:ident,
+                file: $file:literal,
+                $(input: $input:literal,)?
+                expected: $expected:literal
+                $(, stderr: $stderr:literal)? $(,)?
+            }
+        ),*
+        $(,)?
+    ) => {
+        $(
+            #[test]
+            fn $name() {
+                #[allow(unused_assignments, unused_mut)]
+                let mut input = None;
+                $(input = Some($input);)?
+                #[allow(unused_assignments, unused_mut)]
+                let mut stderr = None;
+                $(stderr = Some($stderr);)?
+                $crate::infra::run_repl_test($file, input, $expected, stderr);
+            }
+        )*
+    };
+}
+
+// Runs every program in the directory `dir` of `tests`, both compiled with
+// `flags` and with the compiler given `args` before the file (`--interp` by
+// default), and checks that each prints the same output or stops with the same
+// error either way.
+#[macro_export]
+macro_rules! differential_tests {
+    (
+        $(
+            {
+                name: $name:ident,
+                dir: $dir:literal,
+                $(flags: [$($flag:literal),* $(,)?],)?
+                $(args: [$($arg:literal),* $(,)?],)?
+                input: $input:literal $(,)?
+            }
+        ),*
+        $(,)?
+    ) => {
+        $(
+            #[test]
+            fn $name() {
+                #[allow(unused_assignments, unused_mut)]
+                let mut args: &[&str] = &["--interp"];
+                $(args = &[$($arg),*];)?
+                #[allow(unused_assignments, unused_mut)]
+                let mut flags: &[&str] = &[];
+                $(flags = &[$($flag),*];)?
+                $crate::infra::run_differential_test(stringify!($name), $dir, flags, args, $input);
+            }
+        )*
+    };
+}
+
+// Runs the compiler with `args` and `file`, which is fed on stdin as `-` with
+// `stdin: true`. `build` writes to `tests/NAME.run`, or to the assembly or
+// object that the Makefile then links there, and the program is run on `input`
+// and must print `expected`. Other commands get `input` after the file, and
+// must print `expected` themselves. With `stderr`, the compiler must fail
+// instead, reporting it.
+#[macro_export]
+macro_rules! driver_tests {
+    (
+        $(
+            {
+                name: $name:ident,
+                args: [$($arg:literal),* $(,)?],
+                file: $file:literal,
+                $(stdin: $stdin:literal,)?
+                $(input: $input:literal,)?
+                expected: $expected:literal
+                $(, stderr: $stderr:literal)? $(,)?
+            }
+        ),*
+        $(,)?
+    ) => {
+        $(
+            #[test]
+            fn $name() {
+                #[allow(unused_assignments, unused_mut)]
+                let mut stdin = false;
+                $(stdin = $stdin;)?
+                #[allow(unused_assignments, unused_mut)]
+                let mut input = None;
+                $(input = Some($input);)?
+                #[allow(unused_assignments, unused_mut)]
+                let mut stderr = None;
+                $(stderr = Some($stderr);)?
+                $crate::infra::run_driver_test(stringify!($name), &[$($arg),*], $file, stdin, input, $expected, stderr);
             }
         )*
     };
 }
 
+#[allow(dead_code)]
 pub(crate) fn run_test(
     name: &str,
     file: &str,
+    flags: &[&str],
     input: Option<&str>,
+    heap_size: Option<usize>,
     expected: &str,
     kind: TestKind,
 ) {
     let file = Path::new("tests").join(file);
     match kind {
-        TestKind::Success => run_success_test(name, &file, expected, input),
-        TestKind::RuntimeError => run_runtime_error_test(name, &file, expected, input),
-        TestKind::StaticError => run_static_error_test(name, &file, expected),
+        TestKind::Success => run_success_test(name, &file, flags, expected, input, heap_size),
+        TestKind::RuntimeError => run_runtime_error_test(name, &file, flags, expected, input, heap_size),
+        TestKind::StaticError => run_static_error_test(name, &file, flags, expected),
     }
 }
 
-fn run_success_test(name: &str, file: &Path, expected: &str, input: Option<&str>) {
-    if let Err(err) = compile(name, file) {
+fn run_success_test(name: &str, file: &Path, flags: &[&str], expected: &str, input: Option<&str>, heap_size: Option<usize>) {
+    if let Err(err) = compile(name, file, flags) {
         panic!("expected a successful compilation, but got an error: `{err}`");
     }
-    match run(name, input) {
+    match run(name, flags, input, heap_size) {
         Err(err) => {
             panic!("expected a successful execution, but got an error: `{err}`");
         }
@@ -80,11 +226,11 @@ fn run_success_test(name: &str, file: &Path, expected: &str, input: Option<&str>
     }
 }
 
-fn run_runtime_error_test(name: &str, file: &Path, expected: &str, input: Option<&str>) {
-    if let Err(err) = compile(name, file) {
+fn run_runtime_error_test(name: &str, file: &Path, flags: &[&str], expected: &str, input: Option<&str>, heap_size: Option<usize>) {
+    if let Err(err) = compile(name, file, flags) {
         panic!("expected a successful compilation, but got an error: `{err}`");
     }
-    match run(name, input) {
+    match run(name, flags, input, heap_size) {
         Ok(out) => {
             panic!("expected a runtime error, but program executed succesfully - expected error: `{expected}`, output: `{out}`");
         }
@@ -92,8 +238,8 @@ fn run_runtime_error_test(name: &str, file: &Path, expected: &str, input: Option
     }
 }
 
-fn run_static_error_test(name: &str, file: &Path, expected: &str) {
-    match compile(name, file) {
+fn run_static_error_test(name: &str, file: &Path, flags: &[&str], expected: &str) {
+    match compile(name, file, flags) {
         Ok(()) => {
             panic!(
                 "expected a static error, but compilation succeeded - expected error: `{expected}`"
@@ -103,12 +249,182 @@ fn run_static_error_test(name: &str, file: &Path, expected: &str) {
     }
 }
 
-fn compile(name: &str, file: &Path) -> Result<(), String> {
-    // Run the compiler
+#[allow(dead_code)]
+pub(crate) fn run_golden_test(name: &str, file: &str, flags: &[&str], golden: &str, stderr: Option<&str>) {
+    let file = Path::new("tests").join(file);
+    let golden = Path::new("tests").join(golden);
     let compiler: PathBuf = ["target", "debug", env!("CARGO_PKG_NAME")].iter().collect();
+    let out = mk_path(name, Ext::Out);
     let output = Command::new(&compiler)
+        .args(flags)
+        .arg(&file)
+        .arg(&out)
+        .output()
+        .expect("could not run the compiler");
+    if !output.status.success() {
+        panic!("expected a successful compilation, but got an error: `{}`", String::from_utf8(output.stderr).unwrap());
+    }
+    if let Some(stderr) = stderr {
+        check_error_msg(&String::from_utf8(output.stderr).unwrap(), stderr);
+    }
+    let actual = std::fs::read_to_string(&out).unwrap();
+    if std::env::var_os("BLESS").is_some() {
+        std::fs::write(&golden, actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&golden)
+        .unwrap_or_else(|_| panic!("missing golden file {}, run with BLESS=1 to create it", golden.display()));
+    diff(&expected, actual);
+}
+
+#[allow(dead_code)]
+pub(crate) fn run_repl_test(file: &str, input: Option<&str>, expected: &str, stderr: Option<&str>) {
+    let compiler: PathBuf = ["target", "debug", env!("CARGO_PKG_NAME")].iter().collect();
+    let session = File::open(Path::new("tests").join(file)).unwrap();
+    let output = Command::new(&compiler)
+        .arg("repl")
+        .args(input)
+        .stdin(session)
+        .output()
+        .expect("could not run the compiler");
+    assert!(output.status.success(), "the repl failed: `{}`", String::from_utf8(output.stderr).unwrap());
+    if let Some(stderr) = stderr {
+        check_error_msg(&String::from_utf8(output.stderr).unwrap(), stderr);
+    }
+    diff(expected, String::from_utf8(output.stdout).unwrap());
+}
+
+#[allow(dead_code)]
+pub(crate) fn run_differential_test(name: &str, dir: &str, flags: &[&str], args: &[&str], input: &str) {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(Path::new("tests").join(dir))
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().map_or(false, |e| e == "snek"))
+        .collect();
+    files.sort();
+    let mut failures = Vec::new();
+    for file in files {
+        let stem = file.file_stem().unwrap().to_str().unwrap();
+        let compiled = match compile(&format!("{name}_{stem}"), &file, flags) {
+            Ok(()) => run(&format!("{name}_{stem}"), flags, Some(input), None),
+            Err(err) => Err(err.trim().to_string()),
+        };
+        let other = run_compiler(args, &file, input);
+        if compiled != other {
+            failures.push(format!("{}:\n  compiled: {compiled:?}\n  with {args:?}: {other:?}", file.display()));
+        }
+    }
+    assert!(failures.is_empty(), "running with {args:?} disagrees with the compiled code on\n{}", failures.join("\n"));
+}
+
+#[allow(dead_code)]
+pub(crate) fn run_driver_test(
+    name: &str,
+    args: &[&str],
+    file: &str,
+    stdin: bool,
+    input: Option<&str>,
+    expected: &str,
+    stderr: Option<&str>,
+) {
+    // `build` links against the runtime library, made once for all the tests
+    static RUNTIME: std::sync::Once = std::sync::Once::new();
+    RUNTIME.call_once(|| {
+        let output = Command::new("make").arg("runtime/libsnek.a").output().expect("could not run make");
+        assert!(output.status.success(), "building the runtime library failed");
+    });
+
+    let file = Path::new("tests").join(file);
+    let build = args.first() == Some(&"build");
+    // the Makefile rule linking what `build` stops at, if it is not the
+    // executable
+    let (out, link) = if !build || args.contains(&"--emit=exe") {
+        (Ext::Run, None)
+    } else if args.contains(&"--emit=asm") {
+        (Ext::Asm, Some(if args.contains(&"--syntax=gas") { Ext::Gas } else { Ext::Run }))
+    } else if args.contains(&"--emit=obj") {
+        (Ext::Obj, Some(Ext::Link))
+    } else {
+        (Ext::Run, None)
+    };
+    let compiler: PathBuf = ["target", "debug", env!("CARGO_PKG_NAME")].iter().collect();
+    let mut cmd = Command::new(&compiler);
+    cmd.args(args);
+    if stdin {
+        cmd.arg("-").stdin(File::open(&file).unwrap());
+    } else {
+        cmd.arg(&file);
+    }
+    if build {
+        cmd.arg("-o").arg(mk_path(name, out));
+    } else {
+        cmd.args(input);
+    }
+    let output = cmd.output().expect("could not run the compiler");
+    if let Some(stderr) = stderr {
+        assert!(!output.status.success(), "expected the compiler to fail - expected error: `{stderr}`");
+        return check_error_msg(&String::from_utf8(output.stderr).unwrap(), stderr);
+    }
+    if !output.status.success() {
+        panic!("expected the compiler to succeed, but got an error: `{}`", String::from_utf8(output.stderr).unwrap());
+    }
+    if !build {
+        return diff(expected, String::from_utf8(output.stdout).unwrap());
+    }
+    if let Some(link) = link {
+        let output = Command::new("make")
+            .arg(mk_path(name, link))
+            .output()
+            .expect("could not run make");
+        assert!(output.status.success(), "linking failed");
+    }
+    match run(name, &[], input, None) {
+        Ok(actual) => diff(expected, actual),
+        Err(err) => panic!("expected the program to succeed, but got an error: `{err}`"),
+    }
+}
+
+// Runs the compiler with `args`, `file` and `input`, for modes that run the
+// program themselves.
+fn run_compiler(args: &[&str], file: &Path, input: &str) -> Result<String, String> {
+    let compiler: PathBuf = ["target", "debug", env!("CARGO_PKG_NAME")].iter().collect();
+    let output = Command::new(&compiler)
+        .args(args)
+        .arg(file)
+        .arg(input)
+        .output()
+        .expect("could not run the compiler");
+    if output.status.success() {
+        Ok(String::from_utf8(output.stdout).unwrap().trim().to_string())
+    } else {
+        Err(String::from_utf8(output.stderr).unwrap().trim().to_string())
+    }
+}
+
+fn compile(name: &str, file: &Path, flags: &[&str]) -> Result<(), String> {
+    // Run the compiler; an object file is linked as it is, without the
+    // assembler, GNU syntax goes to `as` instead of nasm, C to `cc`, and
+    // bytecode is run as it is by `snek-vm`
+    let obj = flags.contains(&"--emit=obj");
+    let gas = flags.contains(&"--syntax=gas");
+    let c = flags.contains(&"--target=c");
+    let bytecode = flags.contains(&"--target=bytecode");
+    let (out, build) = if bytecode {
+        (Ext::Snekc, None)
+    } else if obj {
+        (Ext::Obj, Some(Ext::Link))
+    } else if c {
+        (Ext::C, Some(Ext::CBuild))
+    } else if gas {
+        (Ext::Asm, Some(Ext::Gas))
+    } else {
+        (Ext::Asm, Some(Ext::Run))
+    };
+    let compiler: PathBuf = ["target", "debug", env!("CARGO_PKG_NAME")].iter().collect();
+    let output = Command::new(&compiler)
+        .args(flags)
         .arg(file)
-        .arg(&mk_path(name, Ext::Asm))
+        .arg(mk_path(name, out))
         .output()
         .expect("could not run the compiler");
     if !output.status.success() {
@@ -116,17 +432,29 @@ fn compile(name: &str, file: &Path) -> Result<(), String> {
     }
 
     // Assemble and link
-    let output = Command::new("make")
-        .arg(&mk_path(name, Ext::Run))
-        .output()
-        .expect("could not run make");
-    assert!(output.status.success(), "linking failed");
+    if let Some(build) = build {
+        let output = Command::new("make")
+            .arg(mk_path(name, build))
+            .output()
+            .expect("could not run make");
+        assert!(output.status.success(), "linking failed");
+    }
 
     Ok(())
 }
 
-fn run(name: &str, input: Option<&str>) -> Result<String, String> {
-    let mut cmd = Command::new(&mk_path(name, Ext::Run));
+fn run(name: &str, flags: &[&str], input: Option<&str>, heap_size: Option<usize>) -> Result<String, String> {
+    let mut cmd = if flags.contains(&"--target=bytecode") {
+        let vm: PathBuf = ["target", "debug", "snek-vm"].iter().collect();
+        let mut cmd = Command::new(vm);
+        cmd.arg(mk_path(name, Ext::Snekc));
+        cmd
+    } else {
+        Command::new(mk_path(name, Ext::Run))
+    };
+    if let Some(heap_size) = heap_size {
+        cmd.arg("--heap-size").arg(heap_size.to_string());
+    }
     if let Some(input) = input {
         cmd.arg(input);
     }
@@ -168,14 +496,28 @@ fn mk_path(name: &str, ext: Ext) -> PathBuf {
 #[derive(Copy, Clone)]
 enum Ext {
     Asm,
+    Obj,
     Run,
+    Link,
+    Gas,
+    C,
+    CBuild,
+    Snekc,
+    Out,
 }
 
 impl std::fmt::Display for Ext {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Ext::Asm => write!(f, "s"),
+            Ext::Obj => write!(f, "o"),
             Ext::Run => write!(f, "run"),
+            Ext::Link => write!(f, "link"),
+            Ext::Gas => write!(f, "gas"),
+            Ext::C => write!(f, "c"),
+            Ext::CBuild => write!(f, "cbuild"),
+            Ext::Snekc => write!(f, "snekc"),
+            Ext::Out => write!(f, "out"),
         }
     }
 }
@@ -0,0 +1,90 @@
+mod infra;
+
+// The collector's tests are left out: they depend on the size of the heap, and
+// the interpreter has no heap of its own.
+differential_tests! {
+    {
+        name: interp_examples,
+        dir: ".",
+        input: "5",
+    },
+    {
+        name: interp_boa,
+        dir: "boa",
+        input: "5",
+    },
+    {
+        name: interp_cobra,
+        dir: "cobra",
+        input: "5",
+    },
+    {
+        name: interp_diamondback,
+        dir: "diamondback",
+        input: "5",
+    },
+    {
+        name: interp_tuples,
+        dir: "tuples",
+        input: "5",
+    },
+    {
+        name: interp_closures,
+        dir: "closures",
+        input: "5",
+    },
+    {
+        name: interp_strings,
+        dir: "strings",
+        input: "5",
+    },
+    {
+        name: interp_vectors,
+        dir: "vectors",
+        input: "5",
+    },
+    {
+        name: interp_tail,
+        dir: "tail",
+        input: "5",
+    },
+    {
+        name: interp_ir,
+        dir: "ir",
+        input: "5",
+    },
+    {
+        name: interp_opt,
+        dir: "opt",
+        input: "5",
+    },
+    {
+        name: interp_infer,
+        dir: "infer",
+        input: "5",
+    },
+    {
+        name: interp_regalloc,
+        dir: "regalloc",
+        input: "5",
+    },
+    {
+        name: interp_typed,
+        dir: "typed",
+        input: "5",
+    },
+    {
+        name: interp_false,
+        dir: "cobra",
+        input: "false",
+    },
+}
+
+success_tests! {
+    {
+        name: interp_negative_input,
+        file: "diamondback/fact.snek",
+        input: "-3",
+        expected: "1",
+    },
+}
@@ -0,0 +1,55 @@
+mod infra;
+
+golden_tests! {
+    {
+        name: ir_arith,
+        file: "ir/arith.snek",
+        flags: ["--emit=ir"],
+        golden: "ir/arith.ir",
+    },
+    {
+        name: ir_if,
+        file: "ir/if.snek",
+        flags: ["--emit=ir"],
+        golden: "ir/if.ir",
+    },
+    {
+        name: ir_loop,
+        file: "ir/loop.snek",
+        flags: ["--emit=ir"],
+        golden: "ir/loop.ir",
+    },
+    {
+        name: ir_funs,
+        file: "ir/funs.snek",
+        flags: ["--emit=ir"],
+        golden: "ir/funs.ir",
+    },
+    {
+        name: ir_index,
+        file: "ir/index.snek",
+        flags: ["--emit=ir"],
+        golden: "ir/index.ir",
+    },
+}
+
+success_tests! {
+    {
+        name: ir_loop_run,
+        file: "ir/loop.snek",
+        input: "10",
+        expected: "45",
+    },
+    {
+        name: ir_funs_run,
+        file: "ir/funs.snek",
+        input: "4",
+        expected: "13",
+    },
+    {
+        name: ir_index_run,
+        file: "ir/index.snek",
+        input: "5",
+        expected: "1",
+    },
+}
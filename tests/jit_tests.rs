@@ -0,0 +1,84 @@
+mod infra;
+
+// Every program run in memory with `run` must behave as it does compiled and
+// linked.
+differential_tests! {
+    {
+        name: jit_examples,
+        dir: ".",
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: jit_boa,
+        dir: "boa",
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: jit_cobra,
+        dir: "cobra",
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: jit_diamondback,
+        dir: "diamondback",
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: jit_tuples,
+        dir: "tuples",
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: jit_closures,
+        dir: "closures",
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: jit_strings,
+        dir: "strings",
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: jit_vectors,
+        dir: "vectors",
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: jit_gc,
+        dir: "gc",
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: jit_tail,
+        dir: "tail",
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: jit_regalloc,
+        dir: "regalloc",
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: jit_no_regalloc,
+        dir: "regalloc",
+        args: ["run", "--no-regalloc"],
+        input: "5",
+    },
+    {
+        name: jit_false,
+        dir: "cobra",
+        args: ["run"],
+        input: "false",
+    },
+}
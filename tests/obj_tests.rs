@@ -0,0 +1,67 @@
+mod infra;
+
+// Programs written as object files with `--emit=obj` and linked with the
+// runtime must behave as they do when run in memory.
+differential_tests! {
+    {
+        name: obj_examples,
+        dir: ".",
+        flags: ["--emit=obj"],
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: obj_diamondback,
+        dir: "diamondback",
+        flags: ["--emit=obj"],
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: obj_closures,
+        dir: "closures",
+        flags: ["--emit=obj"],
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: obj_strings,
+        dir: "strings",
+        flags: ["--emit=obj"],
+        args: ["run"],
+        input: "5",
+    },
+    {
+        name: obj_gc,
+        dir: "gc",
+        flags: ["--emit=obj"],
+        args: ["run"],
+        input: "5",
+    },
+}
+
+success_tests! {
+    {
+        name: obj_fact,
+        file: "diamondback/fact.snek",
+        flags: ["--emit=obj"],
+        input: "10",
+        expected: "3628800",
+    },
+    {
+        name: obj_no_regalloc,
+        file: "diamondback/my2.snek",
+        flags: ["--emit=obj", "--no-regalloc"],
+        input: "10",
+        expected: "89",
+    },
+}
+
+runtime_error_tests! {
+    {
+        name: obj_invalid_argument,
+        file: "cobra/invalid_argument.snek",
+        flags: ["--emit=obj"],
+        expected: "invalid argument",
+    },
+}
@@ -0,0 +1,82 @@
+mod infra;
+
+golden_tests! {
+    {
+        name: opt_fold_ir,
+        file: "opt/fold.snek",
+        flags: ["-O", "--emit=ir"],
+        golden: "opt/fold.ir",
+    },
+    {
+        name: opt_effects_ir,
+        file: "opt/effects.snek",
+        flags: ["-O", "--emit=ir"],
+        golden: "opt/effects.ir",
+    },
+    {
+        name: opt_auto_let_ir,
+        file: "boa/auto_let_2.snek",
+        flags: ["-O", "--emit=ir"],
+        golden: "opt/auto_let_2.ir",
+    },
+}
+
+success_tests! {
+    {
+        name: opt_fold,
+        file: "opt/fold.snek",
+        flags: ["-O"],
+        input: "2",
+        expected: "42",
+    },
+    {
+        name: opt_max,
+        file: "opt/max.snek",
+        flags: ["-O"],
+        expected: "4611686018427387903",
+    },
+    {
+        name: opt_effects,
+        file: "opt/effects.snek",
+        flags: ["-O"],
+        input: "7",
+        expected: "5\n7",
+    },
+    {
+        name: opt_assigned,
+        file: "opt/assigned.snek",
+        flags: ["-O"],
+        input: "2",
+        expected: "6",
+    },
+    {
+        name: opt_shadow,
+        file: "opt/shadow.snek",
+        flags: ["-O"],
+        input: "3",
+        expected: "9",
+    },
+    {
+        name: opt_auto_expr,
+        file: "boa/auto_expr_3.snek",
+        flags: ["-O"],
+        expected: "102",
+    },
+}
+
+runtime_error_tests! {
+    {
+        name: opt_overflow,
+        file: "opt/overflow.snek",
+        flags: ["-O"],
+        input: "0",
+        expected: "overflow",
+    },
+    {
+        name: opt_type_error,
+        file: "opt/type_error.snek",
+        flags: ["-O"],
+        input: "0",
+        expected: "invalid argument",
+    },
+}
@@ -0,0 +1,34 @@
+mod infra;
+
+success_tests! {
+    {
+        name: spill,
+        file: "regalloc/spill.snek",
+        input: "10",
+        expected: "351",
+    },
+    {
+        name: nested_temps,
+        file: "regalloc/nested_temps.snek",
+        input: "3",
+        expected: "232",
+    },
+    {
+        name: across_calls,
+        file: "regalloc/across_calls.snek",
+        input: "4",
+        expected: "5\n255\n15",
+    },
+    {
+        name: across_gc,
+        file: "regalloc/across_gc.snek",
+        heap_size: 256,
+        expected: "1003",
+    },
+    {
+        name: loop_call,
+        file: "regalloc/loop_call.snek",
+        input: "100",
+        expected: "4950",
+    },
+}
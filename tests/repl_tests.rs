@@ -0,0 +1,45 @@
+mod infra;
+
+repl_tests! {
+    {
+        name: repl_defs,
+        file: "repl/defs.snek",
+        input: "4",
+        expected: "3628800\n10\n7\n7\n1001\n8",
+    },
+    {
+        name: repl_values,
+        file: "repl/values.snek",
+        expected: "(1 true () snek)\n[(1 2) (1 2) (1 2)]\n(1 (...))\n<function>\nsnek\nsnek\ntrue\nfalse",
+    },
+    {
+        name: repl_errors,
+        file: "repl/errors.snek",
+        expected: "1\n2\n1000000",
+        stderr: "an error ocurred invalid argument",
+    },
+    {
+        name: repl_unbound,
+        file: "repl/errors.snek",
+        expected: "1\n2\n1000000",
+        stderr: "Invalid: Function y undefined",
+    },
+    {
+        name: repl_invalid_define,
+        file: "repl/errors.snek",
+        expected: "1\n2\n1000000",
+        stderr: "Invalid define",
+    },
+    {
+        name: repl_redefine_arity,
+        file: "repl/redefine.snek",
+        expected: "1\n3\n3",
+        stderr: "an error ocurred wrong number of arguments",
+    },
+    {
+        name: repl_captured,
+        file: "repl/captured.snek",
+        expected: "2\n2\n2",
+        stderr: "an error ocurred invalid argument",
+    },
+}
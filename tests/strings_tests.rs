@@ -0,0 +1,75 @@
+mod infra;
+
+success_tests! {
+    {
+        name: hello,
+        file: "strings/hello.snek",
+        expected: "hello, world",
+    },
+    {
+        name: label,
+        file: "strings/label.snek",
+        input: "10",
+        expected: "sum: of the first numbers\n(sum: 55)\n4",
+    },
+    {
+        name: ops,
+        file: "strings/ops.snek",
+        expected: "(abcdefghijklmnopq 17 a q 0 true false false false)",
+    },
+    {
+        name: escapes,
+        file: "strings/escapes.snek",
+        expected: "a \"quoted\" word\tand a tab\n1",
+    },
+    {
+        name: string_equal,
+        file: "strings/equal.snek",
+        expected: "(true false true false true true)",
+    },
+    {
+        name: reverse,
+        file: "strings/reverse.snek",
+        expected: "evil stressed desserts",
+    },
+    {
+        name: string_churn,
+        file: "strings/churn.snek",
+        heap_size: 16384,
+        expected: "(100 x)",
+    },
+}
+
+runtime_error_tests! {
+    {
+        name: ref_out_of_range,
+        file: "strings/ref_out_of_range.snek",
+        input: "3",
+        expected: "index out of range",
+    },
+    {
+        name: ref_negative,
+        file: "strings/ref_out_of_range.snek",
+        input: "-1",
+        expected: "index out of range",
+    },
+    {
+        name: ref_not_a_number,
+        file: "strings/ref_out_of_range.snek",
+        input: "true",
+        expected: "invalid argument",
+    },
+    {
+        name: append_not_a_string,
+        file: "strings/not_a_string.snek",
+        expected: "invalid argument",
+    },
+}
+
+static_error_tests! {
+    {
+        name: unterminated,
+        file: "strings/unterminated.snek",
+        expected: "unterminated.snek:1:8: Invalid s-expression: unterminated string",
+    },
+}
@@ -0,0 +1,34 @@
+mod infra;
+
+success_tests! {
+    {
+        name: count_deep,
+        file: "tail/count.snek",
+        input: "10000000",
+        expected: "10000000",
+    },
+    {
+        name: even_odd_deep,
+        file: "tail/even_odd.snek",
+        input: "1000001",
+        expected: "(false true)",
+    },
+    {
+        name: differing_arity,
+        file: "tail/arity.snek",
+        input: "1000000",
+        expected: "42",
+    },
+    {
+        name: lambda_self_call,
+        file: "tail/lambda.snek",
+        input: "1000000",
+        expected: "500000500000",
+    },
+    {
+        name: bst_contains,
+        file: "tail/bst.snek",
+        input: "3000",
+        expected: "(true false)",
+    },
+}
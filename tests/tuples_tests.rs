@@ -0,0 +1,60 @@
+mod infra;
+
+success_tests! {
+    {
+        name: get_first,
+        file: "tuples/get.snek",
+        input: "0",
+        expected: "10",
+    },
+    {
+        name: get_last,
+        file: "tuples/get.snek",
+        input: "2",
+        expected: "30",
+    },
+    {
+        name: set_last,
+        file: "tuples/set.snek",
+        input: "2",
+        expected: "(10 20 0)",
+    },
+}
+
+runtime_error_tests! {
+    {
+        name: get_negative,
+        file: "tuples/get.snek",
+        input: "-1",
+        expected: "index out of range",
+    },
+    {
+        name: get_past_end,
+        file: "tuples/get.snek",
+        input: "3",
+        expected: "index out of range",
+    },
+    {
+        name: set_negative,
+        file: "tuples/set.snek",
+        input: "-1",
+        expected: "index out of range",
+    },
+    {
+        name: set_very_negative,
+        file: "tuples/set.snek",
+        input: "-4611686018427387904",
+        expected: "index out of range",
+    },
+    {
+        name: set_past_end,
+        file: "tuples/set.snek",
+        input: "3",
+        expected: "index out of range",
+    },
+    {
+        name: get_empty,
+        file: "tuples/get_empty.snek",
+        expected: "index out of range",
+    },
+}
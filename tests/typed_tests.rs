@@ -0,0 +1,95 @@
+mod infra;
+
+success_tests! {
+    {
+        name: typed_pair,
+        file: "typed/pair.snek",
+        flags: ["--typecheck"],
+        input: "5",
+        expected: "51",
+    },
+    {
+        name: typed_pair_unchecked,
+        file: "typed/pair.snek",
+        input: "5",
+        expected: "51",
+    },
+    {
+        name: typed_gradual,
+        file: "typed/gradual.snek",
+        flags: ["--typecheck"],
+        input: "4",
+        expected: "21",
+    },
+    {
+        name: typed_untyped_program,
+        file: "diamondback/fact.snek",
+        flags: ["--typecheck"],
+        input: "5",
+        expected: "120",
+    },
+}
+
+runtime_error_tests! {
+    {
+        name: typed_error_tag_unchecked,
+        file: "typed/error-tag.snek",
+        expected: "invalid argument",
+    },
+}
+
+static_error_tests! {
+    {
+        name: typed_error_tag,
+        file: "typed/error-tag.snek",
+        flags: ["--typecheck"],
+        expected: "Type error: expected a tuple, found Num",
+    },
+    {
+        name: typed_error_arg,
+        file: "typed/error-arg.snek",
+        flags: ["--typecheck"],
+        expected: "Type error: expected Num, found Bool",
+    },
+    {
+        name: typed_error_ret,
+        file: "typed/error-ret.snek",
+        flags: ["--typecheck"],
+        expected: "Type error: expected Bool, found Num",
+    },
+    {
+        name: typed_error_index,
+        file: "typed/error-index.snek",
+        flags: ["--typecheck"],
+        expected: "Type error: index 2 out of range for (Tuple Num Num)",
+    },
+    {
+        name: typed_error_tuple_ret,
+        file: "typed/error-tuple-ret.snek",
+        flags: ["--typecheck"],
+        expected: "error-tuple-ret.snek:2:3: Type error: expected (Tuple Num), found (Tuple Bool)",
+    },
+    {
+        name: typed_error_tuple_arg,
+        file: "typed/error-tuple-arg.snek",
+        flags: ["--typecheck"],
+        expected: "error-tuple-arg.snek:4:6: Type error: expected (Tuple Num Num), found (Tuple Bool Bool)",
+    },
+    {
+        name: typed_error_tuple_set,
+        file: "typed/error-tuple-set.snek",
+        flags: ["--typecheck"],
+        expected: "error-tuple-set.snek:3:21: Type error: expected Num, found Bool",
+    },
+    {
+        name: typed_error_arity,
+        file: "typed/error-type.snek",
+        flags: ["--typecheck"],
+        expected: "Type error: function takes 1 arguments but 2 were given",
+    },
+    {
+        name: typed_error_bad_type,
+        file: "typed/error-bad-type.snek",
+        expected: "Invalid type",
+    },
+}
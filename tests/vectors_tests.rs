@@ -0,0 +1,93 @@
+mod infra;
+
+success_tests! {
+    {
+        name: make_vec,
+        file: "vectors/make.snek",
+        input: "4",
+        expected: "4\n[0 0 0 0]",
+    },
+    {
+        name: make_empty_vec,
+        file: "vectors/make.snek",
+        input: "0",
+        expected: "0\n[]",
+    },
+    {
+        name: squares,
+        file: "vectors/squares.snek",
+        input: "6",
+        expected: "(0 25 [0 1 4 9 16 25])",
+    },
+    {
+        name: sieve,
+        file: "vectors/sieve.snek",
+        input: "10000",
+        expected: "1229",
+    },
+    {
+        name: vec_equal,
+        file: "vectors/equal.snek",
+        expected: "[[...] 1 1]\n(true false false true false)",
+    },
+    {
+        name: nested_vecs,
+        file: "vectors/nested.snek",
+        expected: "[[0] [1 1] [2 2 2]]",
+    },
+    {
+        name: vec_churn,
+        file: "vectors/churn.snek",
+        heap_size: 4096,
+        expected: "[9990 9991 9992 9993 9994 9995 9996 9997 9998 9999]",
+    },
+}
+
+runtime_error_tests! {
+    {
+        name: get_out_of_range,
+        file: "vectors/get_out_of_range.snek",
+        input: "3",
+        expected: "index out of range",
+    },
+    {
+        name: set_out_of_range,
+        file: "vectors/set_out_of_range.snek",
+        input: "3",
+        expected: "index out of range",
+    },
+    {
+        name: get_negative,
+        file: "vectors/get_out_of_range.snek",
+        input: "-1",
+        expected: "index out of range",
+    },
+    {
+        name: set_negative,
+        file: "vectors/set_out_of_range.snek",
+        input: "-1",
+        expected: "index out of range",
+    },
+    {
+        name: negative_length,
+        file: "vectors/negative_length.snek",
+        input: "-1",
+        expected: "invalid argument",
+    },
+    {
+        name: length_not_a_number,
+        file: "vectors/negative_length.snek",
+        input: "true",
+        expected: "invalid argument",
+    },
+    {
+        name: tuple_is_not_a_vec,
+        file: "vectors/tuple_not_vec.snek",
+        expected: "invalid argument",
+    },
+    {
+        name: huge_vec,
+        file: "vectors/huge.snek",
+        expected: "out of memory",
+    },
+}